@@ -0,0 +1,366 @@
+//! Async facade of the data handlers
+//!
+//! Storage calls block the calling thread, which stalls all tasks of an async executor
+//! sharing that thread. `AsyncHandler` runs them on the blocking thread pool of the tokio
+//! runtime instead, e.g. to serve quote queries of a web server:
+//!
+//! ```ignore
+//! let db = AsyncHandler::new(RocksDB::new(path)?);
+//! // clones share the handler, reads of all clones run in parallel
+//! let quotes = db.clone().quote_range_forward(&ticker, from, to).await?;
+//! ```
+//!
+//! Reads run concurrently with each other, writes wait for all other calls to finish.
+//! The handler must thus be `Send` and `Sync`, which `RocksDB` and `MemoryDB` are, while
+//! SQLite connections can't be shared between threads and should be opened per worker
+//! instead. Cursors are collected into lists, since they borrow the handler.
+//!
+//! Requires the `async` feature and a tokio runtime.
+use std::sync::{Arc, RwLock};
+
+use chrono::{DateTime, Utc};
+use tokio::task;
+
+use crate::asset::Asset;
+use crate::data_handler::{
+    AssetHandler, Batch, BatchHandler, BulkReport, DataError, OnDuplicate, Page, QuoteHandler, TickerFilter,
+    TransactionHandler,
+};
+use crate::quote::{Quote, Ticker};
+use crate::transaction::Transaction;
+
+/// Handler running the calls of a data handler on the blocking thread pool, see the
+/// module documentation
+pub struct AsyncHandler<H> {
+    db: Arc<RwLock<H>>,
+}
+
+impl<H> Clone for AsyncHandler<H> {
+    fn clone(&self) -> AsyncHandler<H> {
+        AsyncHandler { db: Arc::clone(&self.db) }
+    }
+}
+
+impl<H: Send + Sync + 'static> AsyncHandler<H> {
+    pub fn new(db: H) -> AsyncHandler<H> {
+        AsyncHandler { db: Arc::new(RwLock::new(db)) }
+    }
+
+    /// Run the reads on the blocking thread pool, in parallel with other reads
+    pub async fn read<T, F>(&self, reads: F) -> Result<T, DataError>
+    where
+        T: Send + 'static,
+        F: FnOnce(&H) -> Result<T, DataError> + Send + 'static,
+    {
+        let db = Arc::clone(&self.db);
+        task::spawn_blocking(move || {
+            let db = db.read().map_err(|_| DataError::storage("lock handler for reading", "lock poisoned"))?;
+            reads(&db)
+        })
+        .await
+        .map_err(|e| DataError::storage("run reads on blocking thread", e))?
+    }
+
+    /// Run the writes on the blocking thread pool, once all other calls have finished
+    pub async fn write<T, F>(&self, writes: F) -> Result<T, DataError>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut H) -> Result<T, DataError> + Send + 'static,
+    {
+        let db = Arc::clone(&self.db);
+        task::spawn_blocking(move || {
+            let mut db = db.write().map_err(|_| DataError::storage("lock handler for writing", "lock poisoned"))?;
+            writes(&mut db)
+        })
+        .await
+        .map_err(|e| DataError::storage("run writes on blocking thread", e))?
+    }
+}
+
+impl<H: AssetHandler + Send + Sync + 'static> AsyncHandler<H> {
+    pub async fn get_asset_by_name(&self, name: &str) -> Result<Asset, DataError> {
+        let name = name.to_string();
+        self.read(move |db| db.get_asset_by_name(&name)).await
+    }
+
+    pub async fn get_asset_by_isin(&self, isin: &str) -> Result<Asset, DataError> {
+        let isin = isin.to_string();
+        self.read(move |db| db.get_asset_by_isin(&isin)).await
+    }
+
+    pub async fn get_asset_by_wkn(&self, wkn: &str) -> Result<Asset, DataError> {
+        let wkn = wkn.to_string();
+        self.read(move |db| db.get_asset_by_wkn(&wkn)).await
+    }
+
+    pub async fn list_assets(&self, page: &Page) -> Result<Vec<Asset>, DataError> {
+        let page = page.clone();
+        self.read(move |db| db.list_assets(&page)).await
+    }
+
+    pub async fn insert_asset(&self, asset: &Asset) -> Result<(), DataError> {
+        let asset = asset.clone();
+        self.write(move |db| db.insert_asset(&asset)).await
+    }
+
+    pub async fn update_asset(&self, asset: &Asset) -> Result<(), DataError> {
+        let asset = asset.clone();
+        self.write(move |db| db.update_asset(&asset)).await
+    }
+
+    pub async fn upsert_asset(&self, asset: &Asset) -> Result<(), DataError> {
+        let asset = asset.clone();
+        self.write(move |db| db.upsert_asset(&asset)).await
+    }
+
+    pub async fn delete_asset(&self, asset: &Asset) -> Result<(), DataError> {
+        let asset = asset.clone();
+        self.write(move |db| db.delete_asset(&asset)).await
+    }
+}
+
+impl<H: QuoteHandler + Send + Sync + 'static> AsyncHandler<H> {
+    pub async fn get_ticker_by_name(&self, name: &str) -> Result<Ticker, DataError> {
+        let name = name.to_string();
+        self.read(move |db| db.get_ticker_by_name(&name)).await
+    }
+
+    pub async fn list_tickers(&self, filter: &TickerFilter, page: &Page) -> Result<Vec<Ticker>, DataError> {
+        let (filter, page) = (filter.clone(), page.clone());
+        self.read(move |db| db.list_tickers(&filter, &page)).await
+    }
+
+    pub async fn get_quote_by_id(&self, ticker_name: &str, time: DateTime<Utc>, id: i64) -> Result<Quote, DataError> {
+        let ticker_name = ticker_name.to_string();
+        self.read(move |db| db.get_quote_by_id(&ticker_name, time, id)).await
+    }
+
+    pub async fn get_latest_quote(&self, ticker_name: &str) -> Result<Option<Quote>, DataError> {
+        let ticker_name = ticker_name.to_string();
+        self.read(move |db| db.get_latest_quote(&ticker_name)).await
+    }
+
+    pub async fn get_oldest_quote(&self, ticker_name: &str) -> Result<Option<Quote>, DataError> {
+        let ticker_name = ticker_name.to_string();
+        self.read(move |db| db.get_oldest_quote(&ticker_name)).await
+    }
+
+    pub async fn insert_ticker(&self, ticker: &Ticker) -> Result<(), DataError> {
+        let ticker = ticker.clone();
+        self.write(move |db| db.insert_ticker(&ticker)).await
+    }
+
+    pub async fn update_ticker(&self, ticker: &Ticker) -> Result<(), DataError> {
+        let ticker = ticker.clone();
+        self.write(move |db| db.update_ticker(&ticker)).await
+    }
+
+    pub async fn upsert_ticker(&self, ticker: &Ticker) -> Result<(), DataError> {
+        let ticker = ticker.clone();
+        self.write(move |db| db.upsert_ticker(&ticker)).await
+    }
+
+    pub async fn delete_ticker(&self, ticker: &Ticker) -> Result<(), DataError> {
+        let ticker = ticker.clone();
+        self.write(move |db| db.delete_ticker(&ticker)).await
+    }
+
+    pub async fn insert_quote(&self, quote: &Quote) -> Result<i64, DataError> {
+        let quote = quote.clone();
+        self.write(move |db| db.insert_quote(&quote)).await
+    }
+
+    pub async fn update_quote(&self, quote: &Quote) -> Result<(), DataError> {
+        let quote = quote.clone();
+        self.write(move |db| db.update_quote(&quote)).await
+    }
+
+    pub async fn upsert_quote(&self, quote: &Quote) -> Result<(), DataError> {
+        let quote = quote.clone();
+        self.write(move |db| db.upsert_quote(&quote)).await
+    }
+
+    pub async fn delete_quote(&self, quote: &Quote) -> Result<(), DataError> {
+        let quote = quote.clone();
+        self.write(move |db| db.delete_quote(&quote)).await
+    }
+
+    pub async fn delete_quotes(
+        &self,
+        ticker_name: &str,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    ) -> Result<(), DataError> {
+        let ticker_name = ticker_name.to_string();
+        self.write(move |db| db.delete_quotes(&ticker_name, from, to)).await
+    }
+
+    /// Insert the quotes at once, taking them by value, since large numbers of quotes
+    /// should not be copied
+    pub async fn insert_quotes(&self, quotes: Vec<Quote>, on_duplicate: OnDuplicate) -> Result<BulkReport, DataError> {
+        self.write(move |db| db.insert_quotes(&quotes, on_duplicate)).await
+    }
+
+    /// List the ticker's quotes in `[from, to)` in ascending time order
+    pub async fn quote_range_forward(
+        &self,
+        ticker: &Ticker,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    ) -> Result<Vec<Quote>, DataError> {
+        let ticker = ticker.clone();
+        self.read(move |db| db.quote_range_forward(&ticker, from, to).collect()).await
+    }
+
+    /// List the ticker's quotes in `[from, to)` in descending time order
+    pub async fn quote_range_reverse(
+        &self,
+        ticker: &Ticker,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    ) -> Result<Vec<Quote>, DataError> {
+        let ticker = ticker.clone();
+        self.read(move |db| db.quote_range_reverse(&ticker, from, to).collect()).await
+    }
+}
+
+impl<H: TransactionHandler + Send + Sync + 'static> AsyncHandler<H> {
+    pub async fn get_transaction_by_id(&self, sort_prefix: &str, id: u128) -> Result<Transaction, DataError> {
+        let sort_prefix = sort_prefix.to_string();
+        self.read(move |db| db.get_transaction_by_id(&sort_prefix, id)).await
+    }
+
+    pub async fn list_sort_prefixes(&self, page: &Page) -> Result<Vec<String>, DataError> {
+        let page = page.clone();
+        self.read(move |db| db.list_sort_prefixes(&page)).await
+    }
+
+    pub async fn get_latest_transaction(&self, sort_prefix: &str) -> Result<Option<Transaction>, DataError> {
+        let sort_prefix = sort_prefix.to_string();
+        self.read(move |db| db.get_latest_transaction(&sort_prefix)).await
+    }
+
+    pub async fn get_oldest_transaction(&self, sort_prefix: &str) -> Result<Option<Transaction>, DataError> {
+        let sort_prefix = sort_prefix.to_string();
+        self.read(move |db| db.get_oldest_transaction(&sort_prefix)).await
+    }
+
+    pub async fn insert_transaction(&self, sort_prefix: &str, transaction: &Transaction) -> Result<(), DataError> {
+        let (sort_prefix, transaction) = (sort_prefix.to_string(), transaction.clone());
+        self.write(move |db| db.insert_transaction(&sort_prefix, &transaction)).await
+    }
+
+    pub async fn update_transaction(&self, sort_prefix: &str, transaction: &Transaction) -> Result<(), DataError> {
+        let (sort_prefix, transaction) = (sort_prefix.to_string(), transaction.clone());
+        self.write(move |db| db.update_transaction(&sort_prefix, &transaction)).await
+    }
+
+    pub async fn upsert_transaction(&self, sort_prefix: &str, transaction: &Transaction) -> Result<(), DataError> {
+        let (sort_prefix, transaction) = (sort_prefix.to_string(), transaction.clone());
+        self.write(move |db| db.upsert_transaction(&sort_prefix, &transaction)).await
+    }
+
+    pub async fn delete_transaction(&self, sort_prefix: &str, transaction: &Transaction) -> Result<(), DataError> {
+        let (sort_prefix, transaction) = (sort_prefix.to_string(), transaction.clone());
+        self.write(move |db| db.delete_transaction(&sort_prefix, &transaction)).await
+    }
+
+    /// List the transactions with the given sort prefix in `[from, to)` in ascending order
+    pub async fn transaction_range_forward(
+        &self,
+        sort_prefix: &str,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    ) -> Result<Vec<Transaction>, DataError> {
+        let sort_prefix = sort_prefix.to_string();
+        self.read(move |db| db.transaction_range_forward(&sort_prefix, from, to).collect()).await
+    }
+
+    /// List the transactions with the given sort prefix in `[from, to)` in descending order
+    pub async fn transaction_range_reverse(
+        &self,
+        sort_prefix: &str,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    ) -> Result<Vec<Transaction>, DataError> {
+        let sort_prefix = sort_prefix.to_string();
+        self.read(move |db| db.transaction_range_reverse(&sort_prefix, from, to).collect()).await
+    }
+}
+
+impl<H: BatchHandler + Send + Sync + 'static> AsyncHandler<H> {
+    pub async fn write_batch(&self, batch: Batch) -> Result<(), DataError> {
+        self.write(move |db| db.write_batch(&batch)).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fiat::Currency;
+    use crate::helpers::make_time;
+    use crate::memory_handler::MemoryDB;
+    use crate::rocksdb_handler::RocksDB;
+    use tokio::runtime::Builder;
+
+    fn assert_send_sync<T: Send + Sync>() {}
+
+    #[test]
+    fn test_handlers_are_send_and_sync() {
+        assert_send_sync::<MemoryDB>();
+        assert_send_sync::<RocksDB>();
+    }
+
+    #[test]
+    fn test_parallel_reads() {
+        let mut runtime = Builder::new().basic_scheduler().enable_all().build().unwrap();
+        runtime.block_on(check_parallel_reads(AsyncHandler::new(MemoryDB::new())));
+    }
+
+    #[test]
+    fn test_parallel_reads_on_threaded_scheduler() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = AsyncHandler::new(RocksDB::new(dir.path()).unwrap());
+        let mut runtime = Builder::new().threaded_scheduler().core_threads(4).enable_all().build().unwrap();
+        runtime.block_on(check_parallel_reads(db));
+    }
+
+    async fn check_parallel_reads<H: AssetHandler + QuoteHandler + Send + Sync + 'static>(db: AsyncHandler<H>) {
+        let aapl = Ticker {
+            name: "AAPL".to_string(),
+            asset: "Apple".to_string(),
+            currency: Currency::USD,
+            priority: 1,
+            factor: 1.0,
+        };
+        db.insert_asset(&Asset::new("Apple", None, None, None)).await.unwrap();
+        db.insert_ticker(&aapl).await.unwrap();
+        let quotes = (10..14)
+            .map(|hour| Quote {
+                id: None,
+                ticker: "AAPL".to_string(),
+                price: hour as f64,
+                time: make_time(2020, 9, 1, hour, 0, 0).unwrap(),
+                volume: None,
+            })
+            .collect();
+        let report = db.insert_quotes(quotes, OnDuplicate::Skip).await.unwrap();
+        assert_eq!(report.inserted, 4);
+
+        let from = make_time(2020, 9, 1, 11, 0, 0).unwrap();
+        let to = make_time(2020, 9, 1, 13, 0, 0).unwrap();
+        let readers: Vec<_> = (0..4)
+            .map(|_| {
+                let (db, aapl) = (db.clone(), aapl.clone());
+                tokio::spawn(async move { db.quote_range_forward(&aapl, from, to).await })
+            })
+            .collect();
+        for reader in readers {
+            let prices: Vec<f64> = reader.await.unwrap().unwrap().iter().map(|quote| quote.price).collect();
+            assert_eq!(prices, vec![11.0, 12.0]);
+        }
+
+        assert_eq!(db.get_latest_quote("AAPL").await.unwrap().map(|quote| quote.price), Some(13.0));
+        assert!(matches!(db.get_ticker_by_name("MSFT").await, Err(DataError::NotFound { .. })));
+    }
+}
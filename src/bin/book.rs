@@ -1,5 +1,154 @@
+//! Command line tool to maintain ticky databases
+use std::env;
+use std::error::Error;
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::process;
 
+use ticky::data_handler::{export_jsonl, import_jsonl, DataError, IntegrityHandler};
+use ticky::rocksdb_handler::{RocksDB, RocksDBOptions, SCHEMA_VERSION};
 
-fn main () {
+const USAGE: &str = "usage: book migrate <database path>
+       book backup <database path> <backup dir> [<backups to keep>]
+       book restore <backup dir> <database path>
+       book export <database path> [<dump file>]
+       book import <dump file> <database path>";
 
+/// Upgrade the RocksDB database at the given path to the current schema version
+fn migrate(path: &str) -> Result<(), Box<dyn Error>> {
+    let db = RocksDB::builder(path).migrate_on_open(false).open()?;
+    let version = db.schema_version()?;
+    let migrated = db.migrate()?;
+    println!(
+        "migrated {} records from schema version {} to {}",
+        migrated, version, SCHEMA_VERSION
+    );
+    Ok(())
+}
+
+/// Whether opening a database failed since another process holds its lock file
+fn is_locked(err: &DataError) -> bool {
+    match err {
+        DataError::Storage { source, .. } => source.to_string().contains("LOCK"),
+        _ => false,
+    }
+}
+
+/// Back up the database into the backup directory and delete all but the latest `keep`
+/// backups, if given
+///
+/// The backup engine has to keep the files of the database from being deleted while
+/// they are copied, which only the instance writing the database can do. Hence the
+/// database is opened read-write and must not be open in another process, which has to
+/// call `RocksDB::create_backup` itself instead.
+fn backup(path: &str, backup_dir: &str, keep: Option<&str>) -> Result<(), Box<dyn Error>> {
+    let keep = keep.map(|keep| keep.parse::<usize>()).transpose()?;
+    let db = RocksDB::builder(path)
+        .create_if_missing(false)
+        .migrate_on_open(false)
+        .open()
+        .map_err(|err| -> Box<dyn Error> {
+            if is_locked(&err) {
+                format!("database at '{}' is open in another process, which has to create the backup", path).into()
+            } else {
+                err.into()
+            }
+        })?;
+    let version = db.schema_version()?;
+    if version < SCHEMA_VERSION {
+        eprintln!(
+            "warning: database has outdated schema version {}, `book migrate` upgrades it to version {}",
+            version, SCHEMA_VERSION
+        );
+    }
+
+    let info = db.create_backup(backup_dir)?;
+    println!(
+        "created backup {} of {} files ({} bytes) with schema version {} at {}",
+        info.id, info.num_files, info.size, version, info.time
+    );
+
+    if let Some(keep) = keep {
+        RocksDB::purge_backups(backup_dir, keep)?;
+        println!("kept the latest {} backups", keep);
+    }
+    Ok(())
+}
+
+/// Restore the latest backup of the backup directory to the given path and check the
+/// references between the restored records
+fn restore(backup_dir: &str, path: &str) -> Result<(), Box<dyn Error>> {
+    let mut db = RocksDB::restore_backup(backup_dir, path, &RocksDBOptions::default())?;
+    println!("restored latest backup of '{}' to '{}'", backup_dir, path);
+
+    let report = db.check_integrity()?;
+    if !report.is_clean() {
+        for ticker in &report.tickers {
+            eprintln!("ticker '{}' refers to missing asset '{}'", ticker.ticker, ticker.asset);
+        }
+        for quotes in &report.quotes {
+            eprintln!("{} quotes refer to missing ticker '{}'", quotes.count, quotes.ticker);
+        }
+        for transaction in &report.transactions {
+            eprintln!(
+                "transaction '{}/{}' refers to missing asset '{}'",
+                transaction.sort_prefix, transaction.id, transaction.asset
+            );
+        }
+        return Err("restored database failed the integrity check".into());
+    }
+    println!("integrity check passed");
+    Ok(())
+}
+
+/// Write a JSON Lines dump of the database to the file, or to stdout if not given
+fn export(path: &str, file: Option<&str>) -> Result<(), Box<dyn Error>> {
+    let mut db = RocksDB::builder(path).read_only().open()?;
+    let report = match file {
+        Some(file) => export_jsonl(&mut db, File::create(file)?)?,
+        None => export_jsonl(&mut db, io::stdout())?,
+    };
+    eprintln!(
+        "exported {} assets, {} tickers, {} quotes and {} transactions",
+        report.assets, report.tickers, report.quotes, report.transactions
+    );
+    Ok(())
+}
+
+/// Read a JSON Lines dump into the database, which is created if missing
+fn import(file: &str, path: &str) -> Result<(), Box<dyn Error>> {
+    let mut db = RocksDB::new(path)?;
+    let report = import_jsonl(&mut db, BufReader::new(File::open(file)?))?;
+    println!(
+        "imported {} assets, {} tickers, {} quotes and {} transactions",
+        report.assets, report.tickers, report.quotes, report.transactions
+    );
+    Ok(())
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let result = match args.as_slice() {
+        [command, path] if command == "migrate" => migrate(path),
+        [command, path, backup_dir] if command == "backup" => backup(path, backup_dir, None),
+        [command, path, backup_dir, keep] if command == "backup" => backup(path, backup_dir, Some(keep)),
+        [command, backup_dir, path] if command == "restore" => restore(backup_dir, path),
+        [command, path] if command == "export" => export(path, None),
+        [command, path, file] if command == "export" => export(path, Some(file)),
+        [command, file, path] if command == "import" => import(file, path),
+        _ => {
+            eprintln!("{}", USAGE);
+            process::exit(2);
+        }
+    };
+
+    if let Err(err) = result {
+        eprintln!("error: {}", err);
+        let mut source = err.source();
+        while let Some(cause) = source {
+            eprintln!("  caused by: {}", cause);
+            source = cause.source();
+        }
+        process::exit(1);
+    }
 }
@@ -0,0 +1,129 @@
+//! Least recently used cache of a fixed number of entries
+use std::borrow::Borrow;
+use std::collections::{BTreeMap, HashMap};
+use std::hash::Hash;
+
+use super::CacheStats;
+
+/// Map holding at most `capacity` entries, evicting the least recently used entry
+///
+/// Entries are stamped with a counter increased on each access, hence the entry with the
+/// lowest stamp is the least recently used one.
+#[derive(Debug)]
+pub(super) struct Lru<K, V> {
+    capacity: usize,
+    entries: HashMap<K, (V, u64)>,
+    /// Keys of all entries by their stamp
+    order: BTreeMap<u64, K>,
+    next_stamp: u64,
+    stats: CacheStats,
+}
+
+impl<K: Hash + Eq + Clone, V: Clone> Lru<K, V> {
+    pub fn new(capacity: usize) -> Lru<K, V> {
+        Lru {
+            capacity,
+            entries: HashMap::new(),
+            order: BTreeMap::new(),
+            next_stamp: 0,
+            stats: CacheStats::default(),
+        }
+    }
+
+    fn stamp(&mut self) -> u64 {
+        self.next_stamp += 1;
+        self.next_stamp
+    }
+
+    /// Copy of the value of the key, counting a hit or miss
+    pub fn get<Q>(&mut self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let stamp = self.stamp();
+        match self.entries.get_mut(key) {
+            Some((value, used)) => {
+                let key = self.order.remove(used).expect("entry without stamp");
+                self.order.insert(stamp, key);
+                *used = stamp;
+                self.stats.hits += 1;
+                Some(value.clone())
+            }
+            None => {
+                self.stats.misses += 1;
+                None
+            }
+        }
+    }
+
+    pub fn insert(&mut self, key: K, value: V) {
+        if self.capacity == 0 {
+            return;
+        }
+        self.remove(&key);
+        if self.entries.len() >= self.capacity {
+            if let Some((_, oldest)) = self.order.pop_first() {
+                self.entries.remove(&oldest);
+                self.stats.evictions += 1;
+            }
+        }
+
+        let stamp = self.stamp();
+        self.order.insert(stamp, key.clone());
+        self.entries.insert(key, (value, stamp));
+    }
+
+    pub fn remove<Q>(&mut self, key: &Q)
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        if let Some((_, used)) = self.entries.remove(key) {
+            self.order.remove(&used);
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.order.clear();
+    }
+
+    /// Counts of the cache, with the number of entries currently held
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            entries: self.entries.len(),
+            ..self.stats
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_evicts_least_recently_used() {
+        let mut lru = Lru::new(2);
+        lru.insert("a", 1);
+        lru.insert("b", 2);
+        assert_eq!(lru.get(&"a"), Some(1));
+        lru.insert("c", 3);
+        assert_eq!(lru.get(&"b"), None, "least recently used entry not evicted");
+        assert_eq!(lru.get(&"a"), Some(1));
+        assert_eq!(lru.get(&"c"), Some(3));
+
+        lru.insert("a", 4);
+        lru.remove(&"c");
+        assert_eq!(lru.get(&"a"), Some(4));
+        assert_eq!(lru.get(&"c"), None);
+        assert_eq!(
+            lru.stats(),
+            CacheStats { hits: 4, misses: 2, evictions: 1, entries: 1 }
+        );
+
+        let mut disabled = Lru::new(0);
+        disabled.insert("a", 1);
+        assert_eq!(disabled.get(&"a"), None);
+    }
+}
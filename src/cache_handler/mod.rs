@@ -0,0 +1,500 @@
+//! Read-through cache in front of a data handler
+//!
+//! Valuations look up the same tickers and latest quotes over and over, each time paying
+//! for a read and decoding the record. `CachedHandler` wraps any handler and keeps the
+//! assets by name, the tickers by name and the latest quote of each ticker it has read
+//! in least recently used caches of limited size:
+//!
+//! ```ignore
+//! let mut db = CachedHandler::new(RocksDB::new(path)?);
+//! let ticker = db.get_ticker_by_name("AAPL")?;
+//! let price = db.get_latest_quote(&ticker.name).unwrap().map(|quote| quote.price);
+//! println!("{:?}", db.metrics().tickers);
+//! ```
+//!
+//! Writes through the cached handler drop the cached records they change, hence reads
+//! never see stale records as long as all writes go through it. Writes through other
+//! handlers of the same database are not noticed. All other reads go to the wrapped
+//! handler.
+use std::sync::mpsc::Receiver;
+use std::sync::{Mutex, MutexGuard, PoisonError};
+
+use chrono::{DateTime, Utc};
+
+use crate::asset::Asset;
+use crate::data_handler::{
+    AssetHandler, Batch, BatchHandler, BulkReport, Change, ChangeFilter, Cursor, DataError, IntegrityHandler,
+    IntegrityPolicy, IntegrityReport, OnDuplicate, Operation, Page, QuoteHandler, Snapshot, SnapshotHandler,
+    SubscriptionHandler, TickerFilter, TransactionHandler,
+};
+use crate::quote::{Quote, Ticker};
+use crate::transaction::Transaction;
+
+mod lru;
+
+use lru::Lru;
+
+/// Maximum number of entries of each cache, a size of 0 disables the cache
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct CacheSizes {
+    pub assets: usize,
+    pub tickers: usize,
+    /// Number of tickers whose latest quote is cached
+    pub latest_quotes: usize,
+}
+
+impl Default for CacheSizes {
+    fn default() -> CacheSizes {
+        CacheSizes {
+            assets: 1_000,
+            tickers: 10_000,
+            latest_quotes: 10_000,
+        }
+    }
+}
+
+/// Counts of lookups and entries of a cache
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    /// Entries dropped to make room for new ones
+    pub evictions: u64,
+    /// Entries currently held
+    pub entries: usize,
+}
+
+impl CacheStats {
+    /// Share of lookups answered by the cache, 0 if there were none
+    pub fn hit_ratio(&self) -> f64 {
+        match self.hits + self.misses {
+            0 => 0.0,
+            lookups => self.hits as f64 / lookups as f64,
+        }
+    }
+}
+
+/// Counts of all caches of a `CachedHandler`
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct CacheMetrics {
+    pub assets: CacheStats,
+    pub tickers: CacheStats,
+    pub latest_quotes: CacheStats,
+}
+
+/// Handler caching the assets, tickers and latest quotes read through the wrapped
+/// handler, see the module documentation
+///
+/// Caches are locked on each lookup, such that the handler can be shared between threads
+/// like the wrapped handler.
+pub struct CachedHandler<H> {
+    inner: H,
+    assets: Mutex<Lru<String, Asset>>,
+    tickers: Mutex<Lru<String, Ticker>>,
+    /// Latest quotes by ticker name, `None` for tickers without quotes
+    latest_quotes: Mutex<Lru<String, Option<Quote>>>,
+}
+
+/// Lock the cache, which remains consistent even if a thread panicked holding the lock
+fn lock<T>(cache: &Mutex<T>) -> MutexGuard<'_, T> {
+    cache.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+fn get_mut<T>(cache: &mut Mutex<T>) -> &mut T {
+    cache.get_mut().unwrap_or_else(PoisonError::into_inner)
+}
+
+impl<H> CachedHandler<H> {
+    pub fn new(inner: H) -> CachedHandler<H> {
+        CachedHandler::with_sizes(inner, CacheSizes::default())
+    }
+
+    pub fn with_sizes(inner: H, sizes: CacheSizes) -> CachedHandler<H> {
+        CachedHandler {
+            inner,
+            assets: Mutex::new(Lru::new(sizes.assets)),
+            tickers: Mutex::new(Lru::new(sizes.tickers)),
+            latest_quotes: Mutex::new(Lru::new(sizes.latest_quotes)),
+        }
+    }
+
+    /// Wrapped handler, writes through it bypass the caches
+    pub fn get_ref(&self) -> &H {
+        &self.inner
+    }
+
+    pub fn into_inner(self) -> H {
+        self.inner
+    }
+
+    pub fn metrics(&self) -> CacheMetrics {
+        CacheMetrics {
+            assets: lock(&self.assets).stats(),
+            tickers: lock(&self.tickers).stats(),
+            latest_quotes: lock(&self.latest_quotes).stats(),
+        }
+    }
+
+    /// Drop all cached records, e.g. after writing to the database through another handler
+    pub fn clear(&mut self) {
+        get_mut(&mut self.assets).clear();
+        get_mut(&mut self.tickers).clear();
+        get_mut(&mut self.latest_quotes).clear();
+    }
+
+    fn forget_asset(&mut self, name: &str, deleted: bool) {
+        get_mut(&mut self.assets).remove(name);
+        if deleted {
+            // cascading deletes may delete tickers of the asset which are not cached, but
+            // whose latest quote is
+            get_mut(&mut self.tickers).clear();
+            get_mut(&mut self.latest_quotes).clear();
+        }
+    }
+
+    fn forget_ticker(&mut self, name: &str, deleted: bool) {
+        get_mut(&mut self.tickers).remove(name);
+        if deleted {
+            self.forget_quotes(name);
+        }
+    }
+
+    fn forget_quotes(&mut self, ticker: &str) {
+        get_mut(&mut self.latest_quotes).remove(ticker);
+    }
+
+    /// Drop the cached records the operation may change
+    fn forget(&mut self, operation: &Operation) {
+        match operation {
+            Operation::InsertAsset(asset) | Operation::UpdateAsset(asset) | Operation::UpsertAsset(asset) =>
+                self.forget_asset(&asset.name, false),
+            Operation::DeleteAsset(asset) => self.forget_asset(&asset.name, true),
+            Operation::InsertTicker(ticker) | Operation::UpdateTicker(ticker) | Operation::UpsertTicker(ticker) =>
+                self.forget_ticker(&ticker.name, false),
+            Operation::DeleteTicker(ticker) => self.forget_ticker(&ticker.name, true),
+            Operation::InsertQuote(quote)
+            | Operation::UpdateQuote(quote)
+            | Operation::UpsertQuote(quote)
+            | Operation::DeleteQuote(quote) => self.forget_quotes(&quote.ticker),
+            Operation::InsertTransaction { .. }
+            | Operation::UpdateTransaction { .. }
+            | Operation::UpsertTransaction { .. }
+            | Operation::DeleteTransaction { .. } => {}
+        }
+    }
+}
+
+impl<H: AssetHandler> AssetHandler for CachedHandler<H> {
+    fn get_asset_by_name(&self, name: &str) -> Result<Asset, DataError> {
+        if let Some(asset) = lock(&self.assets).get(name) {
+            return Ok(asset);
+        }
+        let asset = self.inner.get_asset_by_name(name)?;
+        lock(&self.assets).insert(name.to_string(), asset.clone());
+        Ok(asset)
+    }
+
+    fn get_asset_by_isin(&self, isin: &str) -> Result<Asset, DataError> {
+        self.inner.get_asset_by_isin(isin)
+    }
+
+    fn get_asset_by_wkn(&self, wkn: &str) -> Result<Asset, DataError> {
+        self.inner.get_asset_by_wkn(wkn)
+    }
+
+    fn list_assets(&self, page: &Page) -> Result<Vec<Asset>, DataError> {
+        self.inner.list_assets(page)
+    }
+
+    fn insert_asset(&mut self, asset: &Asset) -> Result<(), DataError> {
+        self.forget_asset(&asset.name, false);
+        self.inner.insert_asset(asset)
+    }
+
+    fn update_asset(&mut self, asset: &Asset) -> Result<(), DataError> {
+        self.forget_asset(&asset.name, false);
+        self.inner.update_asset(asset)
+    }
+
+    fn upsert_asset(&mut self, asset: &Asset) -> Result<(), DataError> {
+        self.forget_asset(&asset.name, false);
+        self.inner.upsert_asset(asset)
+    }
+
+    fn delete_asset(&mut self, asset: &Asset) -> Result<(), DataError> {
+        self.forget_asset(&asset.name, true);
+        self.inner.delete_asset(asset)
+    }
+}
+
+impl<H: QuoteHandler> QuoteHandler for CachedHandler<H> {
+    fn get_ticker_by_name(&self, name: &str) -> Result<Ticker, DataError> {
+        if let Some(ticker) = lock(&self.tickers).get(name) {
+            return Ok(ticker);
+        }
+        let ticker = self.inner.get_ticker_by_name(name)?;
+        lock(&self.tickers).insert(name.to_string(), ticker.clone());
+        Ok(ticker)
+    }
+
+    fn list_tickers(&self, filter: &TickerFilter, page: &Page) -> Result<Vec<Ticker>, DataError> {
+        self.inner.list_tickers(filter, page)
+    }
+
+    fn get_quote_by_id(&self, ticker_name: &str, time: DateTime<Utc>, id: i64) -> Result<Quote, DataError> {
+        self.inner.get_quote_by_id(ticker_name, time, id)
+    }
+
+    fn get_latest_quote(&self, ticker_name: &str) -> Result<Option<Quote>, DataError> {
+        if let Some(quote) = lock(&self.latest_quotes).get(ticker_name) {
+            return Ok(quote);
+        }
+        let quote = self.inner.get_latest_quote(ticker_name)?;
+        lock(&self.latest_quotes).insert(ticker_name.to_string(), quote.clone());
+        Ok(quote)
+    }
+
+    fn get_oldest_quote(&self, ticker_name: &str) -> Result<Option<Quote>, DataError> {
+        self.inner.get_oldest_quote(ticker_name)
+    }
+
+    fn insert_ticker(&mut self, ticker: &Ticker) -> Result<(), DataError> {
+        self.forget_ticker(&ticker.name, false);
+        self.inner.insert_ticker(ticker)
+    }
+
+    fn update_ticker(&mut self, ticker: &Ticker) -> Result<(), DataError> {
+        self.forget_ticker(&ticker.name, false);
+        self.inner.update_ticker(ticker)
+    }
+
+    fn upsert_ticker(&mut self, ticker: &Ticker) -> Result<(), DataError> {
+        self.forget_ticker(&ticker.name, false);
+        self.inner.upsert_ticker(ticker)
+    }
+
+    fn delete_ticker(&mut self, ticker: &Ticker) -> Result<(), DataError> {
+        self.forget_ticker(&ticker.name, true);
+        self.inner.delete_ticker(ticker)
+    }
+
+    fn insert_quote(&mut self, quote: &Quote) -> Result<i64, DataError> {
+        self.forget_quotes(&quote.ticker);
+        self.inner.insert_quote(quote)
+    }
+
+    fn update_quote(&mut self, quote: &Quote) -> Result<(), DataError> {
+        self.forget_quotes(&quote.ticker);
+        self.inner.update_quote(quote)
+    }
+
+    fn upsert_quote(&mut self, quote: &Quote) -> Result<(), DataError> {
+        self.forget_quotes(&quote.ticker);
+        self.inner.upsert_quote(quote)
+    }
+
+    fn delete_quote(&mut self, quote: &Quote) -> Result<(), DataError> {
+        self.forget_quotes(&quote.ticker);
+        self.inner.delete_quote(quote)
+    }
+
+    fn delete_quotes(&mut self, ticker_name: &str, from: DateTime<Utc>, to: DateTime<Utc>) -> Result<(), DataError> {
+        self.forget_quotes(ticker_name);
+        self.inner.delete_quotes(ticker_name, from, to)
+    }
+
+    fn insert_quotes(&mut self, quotes: &[Quote], on_duplicate: OnDuplicate) -> Result<BulkReport, DataError> {
+        for quote in quotes {
+            self.forget_quotes(&quote.ticker);
+        }
+        self.inner.insert_quotes(quotes, on_duplicate)
+    }
+
+    fn quote_cursor_forward(&self, ticker: &Ticker, time: DateTime<Utc>) -> Cursor<'_, Quote> {
+        self.inner.quote_cursor_forward(ticker, time)
+    }
+
+    fn quote_cursor_reverse(&self, ticker: &Ticker, time: DateTime<Utc>) -> Cursor<'_, Quote> {
+        self.inner.quote_cursor_reverse(ticker, time)
+    }
+
+    fn quote_range_forward(&self, ticker: &Ticker, from: DateTime<Utc>, to: DateTime<Utc>) -> Cursor<'_, Quote> {
+        self.inner.quote_range_forward(ticker, from, to)
+    }
+
+    fn quote_range_reverse(&self, ticker: &Ticker, from: DateTime<Utc>, to: DateTime<Utc>) -> Cursor<'_, Quote> {
+        self.inner.quote_range_reverse(ticker, from, to)
+    }
+}
+
+impl<H: TransactionHandler> TransactionHandler for CachedHandler<H> {
+    fn get_transaction_by_id(&self, sort_prefix: &str, id: u128) -> Result<Transaction, DataError> {
+        self.inner.get_transaction_by_id(sort_prefix, id)
+    }
+
+    fn list_sort_prefixes(&self, page: &Page) -> Result<Vec<String>, DataError> {
+        self.inner.list_sort_prefixes(page)
+    }
+
+    fn get_latest_transaction(&self, sort_prefix: &str) -> Result<Option<Transaction>, DataError> {
+        self.inner.get_latest_transaction(sort_prefix)
+    }
+
+    fn get_oldest_transaction(&self, sort_prefix: &str) -> Result<Option<Transaction>, DataError> {
+        self.inner.get_oldest_transaction(sort_prefix)
+    }
+
+    fn insert_transaction(&mut self, sort_prefix: &str, transaction: &Transaction) -> Result<(), DataError> {
+        self.inner.insert_transaction(sort_prefix, transaction)
+    }
+
+    fn update_transaction(&mut self, sort_prefix: &str, transaction: &Transaction) -> Result<(), DataError> {
+        self.inner.update_transaction(sort_prefix, transaction)
+    }
+
+    fn upsert_transaction(&mut self, sort_prefix: &str, transaction: &Transaction) -> Result<(), DataError> {
+        self.inner.upsert_transaction(sort_prefix, transaction)
+    }
+
+    fn delete_transaction(&mut self, sort_prefix: &str, transaction: &Transaction) -> Result<(), DataError> {
+        self.inner.delete_transaction(sort_prefix, transaction)
+    }
+
+    fn transaction_cursor_forward(&self, sort_prefix: &str, time: DateTime<Utc>) -> Cursor<'_, Transaction> {
+        self.inner.transaction_cursor_forward(sort_prefix, time)
+    }
+
+    fn transaction_cursor_reverse(&self, sort_prefix: &str, time: DateTime<Utc>) -> Cursor<'_, Transaction> {
+        self.inner.transaction_cursor_reverse(sort_prefix, time)
+    }
+
+    fn transaction_range_forward(&self, sort_prefix: &str, from: DateTime<Utc>, to: DateTime<Utc>) -> Cursor<'_, Transaction> {
+        self.inner.transaction_range_forward(sort_prefix, from, to)
+    }
+
+    fn transaction_range_reverse(&self, sort_prefix: &str, from: DateTime<Utc>, to: DateTime<Utc>) -> Cursor<'_, Transaction> {
+        self.inner.transaction_range_reverse(sort_prefix, from, to)
+    }
+}
+
+/// Retention rules are applied through the cached handler, hence drop the latest quotes
+/// of the tickers they delete quotes of
+impl<H: BatchHandler> BatchHandler for CachedHandler<H> {
+    fn write_batch(&mut self, batch: &Batch) -> Result<(), DataError> {
+        for operation in batch.operations() {
+            self.forget(operation);
+        }
+        self.inner.write_batch(batch)
+    }
+}
+
+impl<H: IntegrityHandler> IntegrityHandler for CachedHandler<H> {
+    fn integrity_policy(&self) -> IntegrityPolicy {
+        self.inner.integrity_policy()
+    }
+
+    fn set_integrity_policy(&mut self, policy: IntegrityPolicy) {
+        self.inner.set_integrity_policy(policy);
+    }
+
+    fn check_integrity(&mut self) -> Result<IntegrityReport, DataError> {
+        self.inner.check_integrity()
+    }
+}
+
+/// Snapshots read from the wrapped handler, bypassing the caches
+impl<H: SnapshotHandler> SnapshotHandler for CachedHandler<H> {
+    fn snapshot(&mut self) -> Result<Box<dyn Snapshot + '_>, DataError> {
+        self.inner.snapshot()
+    }
+}
+
+impl<H: SubscriptionHandler> SubscriptionHandler for CachedHandler<H> {
+    fn subscribe(&mut self, filter: ChangeFilter) -> Receiver<Change> {
+        self.inner.subscribe(filter)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data_handler::conformance;
+    use crate::fiat::Currency;
+    use crate::helpers::make_time;
+    use crate::memory_handler::MemoryDB;
+
+    fn ticker(name: &str) -> Ticker {
+        Ticker {
+            name: name.to_string(),
+            asset: "Apple".to_string(),
+            currency: Currency::USD,
+            priority: 1,
+            factor: 1.0,
+        }
+    }
+
+    fn quote(ticker: &str, hour: u32) -> Quote {
+        Quote {
+            id: None,
+            ticker: ticker.to_string(),
+            price: hour as f64,
+            time: make_time(2020, 9, 1, hour, 0, 0).unwrap(),
+            volume: None,
+        }
+    }
+
+    fn latest_price<H: QuoteHandler>(db: &H, ticker: &str) -> Option<f64> {
+        db.get_latest_quote(ticker).unwrap().map(|quote| quote.price)
+    }
+
+    #[test]
+    fn test_conformance() {
+        conformance::check_all(|| CachedHandler::new(MemoryDB::new()), MemoryDB::new);
+    }
+
+    #[test]
+    fn test_invalidation_and_metrics() {
+        let mut db = CachedHandler::new(MemoryDB::new());
+        db.insert_asset(&Asset::new("Apple", None, None, None)).unwrap();
+        db.insert_ticker(&ticker("AAPL")).unwrap();
+        db.insert_quote(&quote("AAPL", 10)).unwrap();
+
+        assert_eq!(latest_price(&db, "AAPL"), Some(10.0));
+        assert_eq!(latest_price(&db, "AAPL"), Some(10.0));
+        assert_eq!(db.get_ticker_by_name("AAPL").unwrap().priority, 1);
+        assert_eq!(db.get_ticker_by_name("AAPL").unwrap().priority, 1);
+        assert_eq!(
+            db.metrics().latest_quotes,
+            CacheStats { hits: 1, misses: 1, evictions: 0, entries: 1 }
+        );
+        assert_eq!(db.metrics().tickers.hit_ratio(), 0.5);
+
+        db.insert_quote(&quote("AAPL", 11)).unwrap();
+        assert_eq!(latest_price(&db, "AAPL"), Some(11.0), "latest quote not invalidated by insert");
+        db.insert_quotes(&[quote("AAPL", 12)], OnDuplicate::Skip).unwrap();
+        assert_eq!(latest_price(&db, "AAPL"), Some(12.0), "latest quote not invalidated by bulk insert");
+        let mut batch = Batch::new();
+        batch.update_ticker(&Ticker { priority: 2, ..ticker("AAPL") });
+        db.write_batch(&batch).unwrap();
+        assert_eq!(db.get_ticker_by_name("AAPL").unwrap().priority, 2, "ticker not invalidated by batch");
+
+        // writes of the wrapped handler bypass the caches, a new cache reads them
+        let mut inner = db.into_inner();
+        inner.delete_quotes("AAPL", quote("", 12).time, quote("", 13).time).unwrap();
+        let mut db = CachedHandler::new(inner);
+        assert_eq!(latest_price(&db, "AAPL"), Some(11.0));
+
+        db.set_integrity_policy(IntegrityPolicy::Cascade);
+        db.delete_asset(&Asset::new("Apple", None, None, None)).unwrap();
+        assert!(db.get_ticker_by_name("AAPL").is_err(), "ticker of deleted asset still cached");
+        assert_eq!(latest_price(&db, "AAPL"), None, "latest quote of deleted ticker still cached");
+
+        let sizes = CacheSizes { latest_quotes: 1, ..CacheSizes::default() };
+        let mut db = CachedHandler::with_sizes(MemoryDB::new(), sizes);
+        db.get_latest_quote("AAPL").unwrap();
+        db.get_latest_quote("MSFT").unwrap();
+        assert_eq!(db.metrics().latest_quotes.evictions, 1);
+        db.clear();
+        assert_eq!(db.metrics().latest_quotes.entries, 0);
+    }
+}
@@ -1,11 +1,37 @@
-use super::DataError;
+use super::{DataError, DataType, Page};
 use crate::asset::Asset;
 
 /// Handler for globally available data of transactions and related data
+///
+/// Assets are identified by name and can also be looked up by ISIN or WKN, which must be
+/// unique among all assets. Writing an asset with an ISIN or WKN of another asset fails
+/// with `DataError::Conflict`. Renaming an asset is done by deleting and inserting it in
+/// the same batch.
+///
+/// Inserting a record fails with `DataError::Conflict` if a record with the same key
+/// exists already and updating one fails with `DataError::NotFound` if it does not exist,
+/// which applies to the records of all handlers. Upserting a record stores it either way.
+///
+/// Reads of all handlers take `&self`, such that handlers which are `Sync` can serve
+/// reads of several threads at once, while writes take `&mut self`.
 pub trait AssetHandler {
-    fn get_asset_by_name(&mut self, name: &str) -> Result<Asset, DataError>;
+    fn get_asset_by_name(&self, name: &str) -> Result<Asset, DataError>;
+    fn get_asset_by_isin(&self, isin: &str) -> Result<Asset, DataError>;
+    fn get_asset_by_wkn(&self, wkn: &str) -> Result<Asset, DataError>;
+    /// List the page of assets ordered by name
+    fn list_assets(&self, page: &Page) -> Result<Vec<Asset>, DataError>;
 
     fn insert_asset(&mut self, asset: &Asset) -> Result<(), DataError>;
     fn update_asset(&mut self, asset: &Asset) -> Result<(), DataError>;
+    fn upsert_asset(&mut self, asset: &Asset) -> Result<(), DataError>;
     fn delete_asset(&mut self, asset: &Asset) -> Result<(), DataError>;
 }
+
+/// Error for writing an asset whose ISIN or WKN `id` is used by the asset `owner`
+pub(crate) fn duplicate_id(asset: &Asset, id_kind: &str, id: &str, owner: &str) -> DataError {
+    DataError::conflict(
+        DataType::Asset,
+        &asset.name,
+        format!("{} {} is used by asset '{}'", id_kind, id, owner),
+    )
+}
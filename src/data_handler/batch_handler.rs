@@ -0,0 +1,267 @@
+//! Data handler trait for atomic writes of multiple records
+use super::retention::{self, RetentionReport, RetentionRules};
+use super::{quote_key_string, transaction_key_string, DataError, DataType, QuoteHandler, TransactionHandler};
+use crate::asset::Asset;
+use crate::quote::{Quote, Ticker};
+use crate::transaction::Transaction;
+use chrono::{DateTime, Utc};
+
+/// Single write operation of a batch
+///
+/// Inserts fail with `DataError::Conflict` if a record with the same key exists and
+/// updates with `DataError::NotFound` if there is none, while upserts write the record
+/// either way. Deleting a missing record is not an error.
+#[derive(Debug, Clone)]
+pub enum Operation {
+    InsertAsset(Asset),
+    UpdateAsset(Asset),
+    UpsertAsset(Asset),
+    DeleteAsset(Asset),
+    InsertTicker(Ticker),
+    UpdateTicker(Ticker),
+    UpsertTicker(Ticker),
+    DeleteTicker(Ticker),
+    InsertQuote(Quote),
+    UpdateQuote(Quote),
+    UpsertQuote(Quote),
+    DeleteQuote(Quote),
+    InsertTransaction { sort_prefix: String, transaction: Transaction },
+    UpdateTransaction { sort_prefix: String, transaction: Transaction },
+    UpsertTransaction { sort_prefix: String, transaction: Transaction },
+    DeleteTransaction { sort_prefix: String, transaction: Transaction },
+}
+
+impl Operation {
+    /// Apply the operation by calling the respective single record method of the handler,
+    /// which checks the references of the operation on its own
+    pub fn apply<H: QuoteHandler + TransactionHandler>(&self, handler: &mut H) -> Result<(), DataError> {
+        match self {
+            Operation::InsertAsset(asset) => handler.insert_asset(asset),
+            Operation::UpdateAsset(asset) => handler.update_asset(asset),
+            Operation::UpsertAsset(asset) => handler.upsert_asset(asset),
+            Operation::DeleteAsset(asset) => handler.delete_asset(asset),
+            Operation::InsertTicker(ticker) => handler.insert_ticker(ticker),
+            Operation::UpdateTicker(ticker) => handler.update_ticker(ticker),
+            Operation::UpsertTicker(ticker) => handler.upsert_ticker(ticker),
+            Operation::DeleteTicker(ticker) => handler.delete_ticker(ticker),
+            Operation::InsertQuote(quote) => handler.insert_quote(quote).map(|_| ()),
+            Operation::UpdateQuote(quote) => handler.update_quote(quote),
+            Operation::UpsertQuote(quote) => handler.upsert_quote(quote),
+            Operation::DeleteQuote(quote) => handler.delete_quote(quote),
+            Operation::InsertTransaction { sort_prefix, transaction } =>
+                handler.insert_transaction(sort_prefix, transaction),
+            Operation::UpdateTransaction { sort_prefix, transaction } =>
+                handler.update_transaction(sort_prefix, transaction),
+            Operation::UpsertTransaction { sort_prefix, transaction } =>
+                handler.upsert_transaction(sort_prefix, transaction),
+            Operation::DeleteTransaction { sort_prefix, transaction } =>
+                handler.delete_transaction(sort_prefix, transaction),
+        }
+    }
+
+    /// Data type and readable key of the record written or deleted by the operation
+    pub fn record_key(&self) -> (DataType, String) {
+        match self {
+            Operation::InsertAsset(asset)
+            | Operation::UpdateAsset(asset)
+            | Operation::UpsertAsset(asset)
+            | Operation::DeleteAsset(asset) => (DataType::Asset, asset.name.clone()),
+            Operation::InsertTicker(ticker)
+            | Operation::UpdateTicker(ticker)
+            | Operation::UpsertTicker(ticker)
+            | Operation::DeleteTicker(ticker) => (DataType::Ticker, ticker.name.clone()),
+            Operation::InsertQuote(quote)
+            | Operation::UpdateQuote(quote)
+            | Operation::UpsertQuote(quote)
+            | Operation::DeleteQuote(quote) => (
+                DataType::Quote,
+                quote_key_string(&quote.ticker, &quote.time, quote.id.unwrap_or(0)),
+            ),
+            Operation::InsertTransaction { sort_prefix, transaction }
+            | Operation::UpdateTransaction { sort_prefix, transaction }
+            | Operation::UpsertTransaction { sort_prefix, transaction }
+            | Operation::DeleteTransaction { sort_prefix, transaction } => (
+                DataType::Transaction,
+                transaction_key_string(sort_prefix, transaction.id),
+            ),
+        }
+    }
+
+    /// Fail if the operation is an insert of an existing record or an update of a
+    /// missing record
+    pub(crate) fn check_existing(&self, exists: bool) -> Result<(), DataError> {
+        match self {
+            Operation::InsertAsset(_)
+            | Operation::InsertTicker(_)
+            | Operation::InsertQuote(_)
+            | Operation::InsertTransaction { .. } if exists => {
+                let (data_type, key) = self.record_key();
+                Err(DataError::conflict(data_type, key, format!("{} exists already", data_type)))
+            }
+            Operation::UpdateAsset(_)
+            | Operation::UpdateTicker(_)
+            | Operation::UpdateQuote(_)
+            | Operation::UpdateTransaction { .. } if !exists => {
+                let (data_type, key) = self.record_key();
+                Err(DataError::not_found(data_type, key))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Whether the operation depends on the record existing or not
+    pub(crate) fn is_checked(&self) -> bool {
+        matches!(
+            self,
+            Operation::InsertAsset(_)
+                | Operation::InsertTicker(_)
+                | Operation::InsertQuote(_)
+                | Operation::InsertTransaction { .. }
+                | Operation::UpdateAsset(_)
+                | Operation::UpdateTicker(_)
+                | Operation::UpdateQuote(_)
+                | Operation::UpdateTransaction { .. }
+        )
+    }
+}
+
+/// Unit of work, i.e. a list of write operations that are stored together or not at all
+///
+/// Operations are applied in the order they have been added, e.g. booking a purchase
+/// together with its fee and tax transactions:
+///
+/// ```ignore
+/// let mut batch = Batch::new();
+/// batch
+///     .insert_transaction("book", &purchase)
+///     .insert_transaction("book", &fee)
+///     .insert_transaction("book", &tax);
+/// db.write_batch(&batch)?;
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct Batch {
+    operations: Vec<Operation>,
+}
+
+impl Batch {
+    pub fn new() -> Batch {
+        Batch::default()
+    }
+
+    pub fn push(&mut self, operation: Operation) -> &mut Batch {
+        self.operations.push(operation);
+        self
+    }
+
+    pub fn insert_asset(&mut self, asset: &Asset) -> &mut Batch {
+        self.push(Operation::InsertAsset(asset.clone()))
+    }
+
+    pub fn update_asset(&mut self, asset: &Asset) -> &mut Batch {
+        self.push(Operation::UpdateAsset(asset.clone()))
+    }
+
+    pub fn upsert_asset(&mut self, asset: &Asset) -> &mut Batch {
+        self.push(Operation::UpsertAsset(asset.clone()))
+    }
+
+    pub fn delete_asset(&mut self, asset: &Asset) -> &mut Batch {
+        self.push(Operation::DeleteAsset(asset.clone()))
+    }
+
+    pub fn insert_ticker(&mut self, ticker: &Ticker) -> &mut Batch {
+        self.push(Operation::InsertTicker(ticker.clone()))
+    }
+
+    pub fn update_ticker(&mut self, ticker: &Ticker) -> &mut Batch {
+        self.push(Operation::UpdateTicker(ticker.clone()))
+    }
+
+    pub fn upsert_ticker(&mut self, ticker: &Ticker) -> &mut Batch {
+        self.push(Operation::UpsertTicker(ticker.clone()))
+    }
+
+    pub fn delete_ticker(&mut self, ticker: &Ticker) -> &mut Batch {
+        self.push(Operation::DeleteTicker(ticker.clone()))
+    }
+
+    pub fn insert_quote(&mut self, quote: &Quote) -> &mut Batch {
+        self.push(Operation::InsertQuote(quote.clone()))
+    }
+
+    pub fn update_quote(&mut self, quote: &Quote) -> &mut Batch {
+        self.push(Operation::UpdateQuote(quote.clone()))
+    }
+
+    pub fn upsert_quote(&mut self, quote: &Quote) -> &mut Batch {
+        self.push(Operation::UpsertQuote(quote.clone()))
+    }
+
+    pub fn delete_quote(&mut self, quote: &Quote) -> &mut Batch {
+        self.push(Operation::DeleteQuote(quote.clone()))
+    }
+
+    pub fn insert_transaction(&mut self, sort_prefix: &str, transaction: &Transaction) -> &mut Batch {
+        self.push(Operation::InsertTransaction {
+            sort_prefix: sort_prefix.to_string(),
+            transaction: transaction.clone(),
+        })
+    }
+
+    pub fn update_transaction(&mut self, sort_prefix: &str, transaction: &Transaction) -> &mut Batch {
+        self.push(Operation::UpdateTransaction {
+            sort_prefix: sort_prefix.to_string(),
+            transaction: transaction.clone(),
+        })
+    }
+
+    pub fn upsert_transaction(&mut self, sort_prefix: &str, transaction: &Transaction) -> &mut Batch {
+        self.push(Operation::UpsertTransaction {
+            sort_prefix: sort_prefix.to_string(),
+            transaction: transaction.clone(),
+        })
+    }
+
+    pub fn delete_transaction(&mut self, sort_prefix: &str, transaction: &Transaction) -> &mut Batch {
+        self.push(Operation::DeleteTransaction {
+            sort_prefix: sort_prefix.to_string(),
+            transaction: transaction.clone(),
+        })
+    }
+
+    pub fn operations(&self) -> &[Operation] {
+        &self.operations
+    }
+
+    pub fn len(&self) -> usize {
+        self.operations.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.operations.is_empty()
+    }
+}
+
+impl From<Operation> for Batch {
+    fn from(operation: Operation) -> Batch {
+        Batch {
+            operations: vec![operation],
+        }
+    }
+}
+
+/// Handler for atomic writes of multiple records
+pub trait BatchHandler: QuoteHandler + TransactionHandler {
+    /// Apply all operations of the batch in order, such that either all or none of
+    /// them are stored
+    ///
+    /// References between records are checked once all operations have been applied,
+    /// see `IntegrityHandler`. Quotes inserted without id are assigned sequence ids as
+    /// by `QuoteHandler::insert_quote`.
+    fn write_batch(&mut self, batch: &Batch) -> Result<(), DataError>;
+
+    /// Delete the quotes the retention rules no longer keep as of `now`, see `retention`
+    fn apply_retention(&mut self, rules: &RetentionRules, now: DateTime<Utc>) -> Result<RetentionReport, DataError> {
+        retention::apply_retention(self, rules, now)
+    }
+}
@@ -0,0 +1,210 @@
+//! Bulk ingestion of quotes
+//!
+//! Loading long histories one `insert_quote` call at a time pays for a separate write,
+//! and for SQLite a separate transaction, per quote. Bulk inserts write many quotes at
+//! once, either as a slice by `QuoteHandler::insert_quotes` or streamed through a
+//! `QuoteWriter`, which writes them in chunks.
+use std::collections::BTreeSet;
+use std::ops::AddAssign;
+
+use chrono::{DateTime, Utc};
+
+use super::changes::{Change, ChangeKind};
+use super::integrity::BatchStore;
+use super::{quote_key_string, DataError, DataType, Operation, QuoteHandler};
+use crate::quote::Quote;
+
+/// Number of quotes a `QuoteWriter` collects before writing them
+pub const DEFAULT_CHUNK_SIZE: usize = 10_000;
+
+/// Handling of bulk inserted quotes with the key of a stored quote
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub enum OnDuplicate {
+    /// Keep the stored quote
+    #[default]
+    Skip,
+    /// Replace the stored quote
+    Replace,
+}
+
+/// Number of quotes stored or skipped by bulk inserts
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct BulkReport {
+    pub inserted: usize,
+    pub replaced: usize,
+    pub skipped: usize,
+}
+
+impl BulkReport {
+    /// Number of quotes written, i.e. inserted or replaced
+    pub fn written(&self) -> usize {
+        self.inserted + self.replaced
+    }
+}
+
+impl AddAssign for BulkReport {
+    fn add_assign(&mut self, other: BulkReport) {
+        self.inserted += other.inserted;
+        self.replaced += other.replaced;
+        self.skipped += other.skipped;
+    }
+}
+
+/// Writer collecting quotes and inserting them in chunks, e.g. to load a file of minute
+/// bars without reading it into memory first:
+///
+/// ```ignore
+/// let mut writer = QuoteWriter::new(&mut db, OnDuplicate::Skip);
+/// for quote in read_bars(file) {
+///     writer.write(quote?)?;
+/// }
+/// let report = writer.finish()?;
+/// ```
+///
+/// Each chunk is written atomically, hence quotes of earlier chunks are kept if a later
+/// chunk fails. Quotes still collected when the writer is dropped without `finish` are
+/// discarded.
+pub struct QuoteWriter<'a, H: QuoteHandler + ?Sized> {
+    handler: &'a mut H,
+    on_duplicate: OnDuplicate,
+    chunk_size: usize,
+    quotes: Vec<Quote>,
+    report: BulkReport,
+}
+
+impl<'a, H: QuoteHandler + ?Sized> QuoteWriter<'a, H> {
+    pub fn new(handler: &'a mut H, on_duplicate: OnDuplicate) -> QuoteWriter<'a, H> {
+        QuoteWriter::with_chunk_size(handler, on_duplicate, DEFAULT_CHUNK_SIZE)
+    }
+
+    pub fn with_chunk_size(handler: &'a mut H, on_duplicate: OnDuplicate, chunk_size: usize) -> QuoteWriter<'a, H> {
+        assert!(chunk_size > 0, "chunks must hold at least one quote");
+        QuoteWriter {
+            handler,
+            on_duplicate,
+            chunk_size,
+            quotes: Vec::with_capacity(chunk_size),
+            report: BulkReport::default(),
+        }
+    }
+
+    /// Add the quote, writing all collected quotes once the chunk is full
+    pub fn write(&mut self, quote: Quote) -> Result<(), DataError> {
+        self.quotes.push(quote);
+        if self.quotes.len() >= self.chunk_size {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Write all collected quotes
+    pub fn flush(&mut self) -> Result<(), DataError> {
+        if !self.quotes.is_empty() {
+            self.report += self.handler.insert_quotes(&self.quotes, self.on_duplicate)?;
+            self.quotes.clear();
+        }
+        Ok(())
+    }
+
+    /// Counts of the quotes written so far
+    pub fn report(&self) -> BulkReport {
+        self.report
+    }
+
+    /// Write the remaining quotes and return the counts of all quotes written
+    pub fn finish(mut self) -> Result<BulkReport, DataError> {
+        self.flush()?;
+        Ok(self.report)
+    }
+}
+
+/// Quotes to write by a bulk insert, each with the id of its key
+pub(crate) struct BulkPlan {
+    pub quotes: Vec<Quote>,
+    /// Whether each quote is inserted or replaces a quote
+    pub kinds: Vec<ChangeKind>,
+    pub report: BulkReport,
+}
+
+impl BulkPlan {
+    /// Changes of the quotes written, in order
+    pub fn changes(&self) -> impl Iterator<Item = Change> + '_ {
+        self.kinds
+            .iter()
+            .zip(&self.quotes)
+            .map(|(kind, quote)| Change::Quote(*kind, quote.clone()))
+    }
+}
+
+/// Check all quotes against the records of the store and decide which ones to write,
+/// without changing the store
+///
+/// Quotes without id are matched with the stored quote of id 0, unlike single inserts,
+/// which assign them the next free id. Loading the same data twice thus skips or
+/// replaces the quotes of the first load instead of storing them twice. A quote whose key
+/// occurs earlier in the same bulk insert counts as duplicate of that quote.
+pub(crate) fn plan_quotes<S: BatchStore>(
+    store: &mut S,
+    quotes: &[Quote],
+    on_duplicate: OnDuplicate,
+) -> Result<BulkPlan, DataError> {
+    let mut tickers = BTreeSet::new();
+    let mut seen: BTreeSet<(&str, DateTime<Utc>, i64)> = BTreeSet::new();
+    let mut plan = BulkPlan {
+        quotes: Vec::with_capacity(quotes.len()),
+        kinds: Vec::with_capacity(quotes.len()),
+        report: BulkReport::default(),
+    };
+
+    for quote in quotes {
+        let seq = quote.id.unwrap_or(0);
+        if !tickers.contains(quote.ticker.as_str()) {
+            if !store.ticker_exists(&quote.ticker)? {
+                let key = quote_key_string(&quote.ticker, &quote.time, seq);
+                let reason = format!("{} '{}' does not exist", DataType::Ticker, quote.ticker);
+                return Err(DataError::validation(DataType::Quote, key, reason));
+            }
+            tickers.insert(quote.ticker.as_str());
+        }
+
+        let keyed = Quote { id: Some(seq), ..quote.clone() };
+        let duplicate = !seen.insert((&quote.ticker, quote.time, seq))
+            || store.record_exists(&Operation::UpsertQuote(keyed.clone()))?;
+        let kind = match (duplicate, on_duplicate) {
+            (false, _) => {
+                plan.report.inserted += 1;
+                ChangeKind::Inserted
+            }
+            (true, OnDuplicate::Replace) => {
+                plan.report.replaced += 1;
+                ChangeKind::Updated
+            }
+            (true, OnDuplicate::Skip) => {
+                plan.report.skipped += 1;
+                continue;
+            }
+        };
+        plan.quotes.push(keyed);
+        plan.kinds.push(kind);
+    }
+
+    Ok(plan)
+}
+
+/// Check and write the quotes to the store, which is left unchanged if any check fails,
+/// collecting the changes of the quotes written if a list to collect them in is given
+pub(crate) fn apply_quotes<S: BatchStore>(
+    store: &mut S,
+    quotes: &[Quote],
+    on_duplicate: OnDuplicate,
+    changes: Option<&mut Vec<Change>>,
+) -> Result<BulkReport, DataError> {
+    let plan = plan_quotes(store, quotes, on_duplicate)?;
+    if let Some(changes) = changes {
+        changes.extend(plan.changes());
+    }
+    for quote in plan.quotes {
+        store.apply(&Operation::UpsertQuote(quote))?;
+    }
+    Ok(plan.report)
+}
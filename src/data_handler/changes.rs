@@ -0,0 +1,205 @@
+//! Subscriptions to the records written through a data handler
+//!
+//! Subscribers receive each change of the records they are interested in through a
+//! channel, e.g. to keep a chart of a ticker up to date while quotes are loaded:
+//!
+//! ```ignore
+//! let changes = db.subscribe(ChangeFilter::Ticker("AAPL".to_string()));
+//! thread::spawn(move || {
+//!     for change in changes {
+//!         redraw(&change);
+//!     }
+//! });
+//! ```
+//!
+//! Changes are sent once the write has been committed, in the order they have been
+//! applied, and not at all for failed writes. Only writes through the handler instance
+//! subscribed to are observed, writes through other connections to the same database or
+//! by other processes are not.
+use std::sync::mpsc::{channel, Receiver, Sender};
+
+use chrono::{DateTime, Utc};
+
+use super::DataType;
+use crate::asset::Asset;
+use crate::quote::{Quote, Ticker};
+use crate::transaction::Transaction;
+
+/// How a record has been changed
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ChangeKind {
+    Inserted,
+    Updated,
+    Deleted,
+}
+
+/// Change of a stored record, carrying the record as written or, for deletes, as passed
+/// to the delete
+///
+/// Deletes of records that do not exist are not reported. Tickers deleted along with
+/// their asset are reported as stored.
+#[derive(Debug, Clone)]
+pub enum Change {
+    Asset(ChangeKind, Asset),
+    Ticker(ChangeKind, Ticker),
+    Quote(ChangeKind, Quote),
+    Transaction { kind: ChangeKind, sort_prefix: String, transaction: Transaction },
+    /// Quotes of the ticker from the start up to, but excluding, the end of the time range
+    /// have been deleted at once, `None` meaning unbounded. Reported for range deletes,
+    /// even if there were no quotes in the range, and for quotes deleted along with their
+    /// ticker.
+    QuotesDeleted {
+        ticker: String,
+        from: Option<DateTime<Utc>>,
+        to: Option<DateTime<Utc>>,
+    },
+}
+
+impl Change {
+    /// Data type of the changed records
+    pub fn data_type(&self) -> DataType {
+        match self {
+            Change::Asset(..) => DataType::Asset,
+            Change::Ticker(..) => DataType::Ticker,
+            Change::Quote(..) | Change::QuotesDeleted { .. } => DataType::Quote,
+            Change::Transaction { .. } => DataType::Transaction,
+        }
+    }
+
+    /// Name of the changed ticker or of the ticker of the changed quotes
+    pub fn ticker(&self) -> Option<&str> {
+        match self {
+            Change::Ticker(_, ticker) => Some(&ticker.name),
+            Change::Quote(_, quote) => Some(&quote.ticker),
+            Change::QuotesDeleted { ticker, .. } => Some(ticker),
+            Change::Asset(..) | Change::Transaction { .. } => None,
+        }
+    }
+
+    /// Sort prefix of the changed transaction
+    pub fn sort_prefix(&self) -> Option<&str> {
+        match self {
+            Change::Transaction { sort_prefix, .. } => Some(sort_prefix),
+            _ => None,
+        }
+    }
+}
+
+/// Changes a subscriber receives
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ChangeFilter {
+    All,
+    /// Changes of records of the data type
+    DataType(DataType),
+    /// Changes of the ticker with the given name and of its quotes
+    Ticker(String),
+    /// Changes of the transactions with the given sort prefix
+    SortPrefix(String),
+}
+
+impl ChangeFilter {
+    pub fn matches(&self, change: &Change) -> bool {
+        match self {
+            ChangeFilter::All => true,
+            ChangeFilter::DataType(data_type) => change.data_type() == *data_type,
+            ChangeFilter::Ticker(name) => change.ticker() == Some(name.as_str()),
+            ChangeFilter::SortPrefix(sort_prefix) => change.sort_prefix() == Some(sort_prefix.as_str()),
+        }
+    }
+}
+
+/// Data handler trait to subscribe to the changes written through the handler
+pub trait SubscriptionHandler {
+    /// Receive all changes matching the filter from now on, until the receiver is dropped
+    fn subscribe(&mut self, filter: ChangeFilter) -> Receiver<Change>;
+}
+
+/// Subscribers of a handler instance
+///
+/// Clones have no subscribers, since changes of a copy of the data are not changes of the
+/// data subscribed to.
+#[derive(Debug, Default)]
+pub(crate) struct ChangeFeed {
+    subscribers: Vec<(ChangeFilter, Sender<Change>)>,
+}
+
+impl Clone for ChangeFeed {
+    fn clone(&self) -> ChangeFeed {
+        ChangeFeed::default()
+    }
+}
+
+impl ChangeFeed {
+    pub fn subscribe(&mut self, filter: ChangeFilter) -> Receiver<Change> {
+        let (sender, receiver) = channel();
+        self.subscribers.push((filter, sender));
+        receiver
+    }
+
+    pub fn is_active(&self) -> bool {
+        !self.subscribers.is_empty()
+    }
+
+    /// List to collect the changes of a write in, unless there are no subscribers, which
+    /// saves copying the records written
+    pub fn collector(&self) -> Option<Vec<Change>> {
+        if self.is_active() {
+            Some(Vec::new())
+        } else {
+            None
+        }
+    }
+
+    /// Send the changes to all subscribers whose filter matches them, dropping those
+    /// whose receiver has been dropped
+    pub fn publish(&mut self, changes: Vec<Change>) {
+        if changes.is_empty() {
+            return;
+        }
+        self.subscribers.retain(|(filter, sender)| {
+            changes
+                .iter()
+                .filter(|change| filter.matches(change))
+                .all(|change| sender.send(change.clone()).is_ok())
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::helpers::make_time;
+
+    fn quote(ticker: &str) -> Quote {
+        Quote {
+            id: Some(0),
+            ticker: ticker.to_string(),
+            price: 1.0,
+            time: make_time(2020, 9, 1, 12, 0, 0).unwrap(),
+            volume: None,
+        }
+    }
+
+    #[test]
+    fn test_publish_filters_and_drops_subscribers() {
+        let mut feed = ChangeFeed::default();
+        assert!(feed.collector().is_none());
+
+        let all = feed.subscribe(ChangeFilter::All);
+        let apple = feed.subscribe(ChangeFilter::Ticker("AAPL".to_string()));
+        let dropped = feed.subscribe(ChangeFilter::DataType(DataType::Quote));
+        drop(dropped);
+        assert!(feed.collector().is_some());
+
+        feed.publish(vec![
+            Change::Quote(ChangeKind::Inserted, quote("AAPL")),
+            Change::Quote(ChangeKind::Inserted, quote("MSFT")),
+        ]);
+        assert_eq!(all.try_iter().count(), 2);
+        let received: Vec<Change> = apple.try_iter().collect();
+        assert_eq!(received.len(), 1);
+        assert_eq!(received[0].ticker(), Some("AAPL"));
+        assert_eq!(feed.subscribers.len(), 2);
+        assert_eq!(feed.clone().subscribers.len(), 0);
+    }
+}
@@ -0,0 +1,947 @@
+//! Backend-agnostic conformance tests for the data handler traits
+//!
+//! Each check panics with a descriptive message if the handler violates the documented
+//! semantics of the traits. The checks expect an empty database and may leave data
+//! behind, so each is given a fresh handler by `check_all`:
+//!
+//! ```ignore
+//! use ticky::data_handler::conformance;
+//! use ticky::memory_handler::MemoryDB;
+//!
+//! conformance::check_all(MyDB::new, MemoryDB::new);
+//! ```
+//!
+//! Handlers implementing only the asset, quote and transaction handlers are checked by
+//! `check_core`. Single checks run on their own as well, e.g.
+//! `conformance::check_quote_handler(&mut MyDB::new())`.
+use std::sync::mpsc::Receiver;
+
+use chrono::{DateTime, Duration, NaiveDate, TimeZone, Timelike, Utc};
+
+use super::{
+    export_jsonl, import_jsonl, AssetHandler, Batch, BatchHandler, BulkReport, Change, ChangeFilter, Cursor,
+    DataError, DataType, DumpReport, IntegrityHandler, IntegrityPolicy, OnDuplicate, Page, QuoteHandler,
+    QuoteWriter, RetentionPolicy, RetentionReport, RetentionRules, SnapshotHandler, SubscriptionHandler,
+    TickerFilter, TransactionHandler,
+};
+use crate::asset::Asset;
+use crate::fiat::{CashFlow, Currency};
+use crate::quote::{Quote, Ticker};
+use crate::transaction::{time_id, Transaction, TransactionType};
+
+fn time(hour: u32) -> DateTime<Utc> {
+    Utc.ymd(2020, 9, 1).and_hms(hour, 0, 0)
+}
+
+fn ticker(name: &str) -> Ticker {
+    Ticker {
+        name: name.to_string(),
+        asset: "Apple".to_string(),
+        currency: Currency::USD,
+        priority: 1,
+        factor: 1.0,
+    }
+}
+
+fn quote(ticker: &str, id: Option<i64>, time: DateTime<Utc>, price: f64) -> Quote {
+    Quote {
+        id,
+        ticker: ticker.to_string(),
+        price,
+        time,
+        volume: None,
+    }
+}
+
+fn transaction(time: DateTime<Utc>, amount: f64) -> Transaction {
+    let mut transaction = Transaction::new(
+        TransactionType::Cash,
+        CashFlow::new(amount, Currency::EUR, NaiveDate::from_ymd(2020, 9, 1)),
+        None,
+    );
+    transaction.id = time_id(time);
+    transaction
+}
+
+/// Midnight of the given day of September 2020
+fn day(day: u32) -> DateTime<Utc> {
+    Utc.ymd(2020, 9, day).and_hms(0, 0, 0)
+}
+
+/// Transaction with the given id, booked on the given day of September 2020
+fn booked(day: u32, id: u128, amount: f64) -> Transaction {
+    let mut transaction = transaction(time(0), amount);
+    transaction.id = id;
+    transaction.cash_flow.date = NaiveDate::from_ymd(2020, 9, day);
+    transaction
+}
+
+fn prices(cursor: Cursor<'_, Quote>) -> Vec<f64> {
+    cursor
+        .map(|quote| quote.expect("quote cursor failed").price)
+        .collect()
+}
+
+fn amounts(cursor: Cursor<'_, Transaction>) -> Vec<f64> {
+    cursor
+        .map(|transaction| transaction.expect("transaction cursor failed").cash_flow.amount.amount)
+        .collect()
+}
+
+/// Run all checks, each on a fresh handler created by `new`
+///
+/// Dumps are imported into a fresh handler created by `new_reference`, see `check_dump`.
+pub fn check_all<H, R, F, G>(mut new: F, new_reference: G)
+where
+    H: BatchHandler + IntegrityHandler + SnapshotHandler + SubscriptionHandler,
+    R: BatchHandler + SnapshotHandler,
+    F: FnMut() -> H,
+    G: FnMut() -> R,
+{
+    check_core(&mut new);
+    check_extended(new, new_reference);
+}
+
+/// Run the checks of the asset, quote and transaction handlers, each on a fresh handler
+/// created by `new`
+pub fn check_core<H, F>(mut new: F)
+where
+    H: QuoteHandler + TransactionHandler,
+    F: FnMut() -> H,
+{
+    check_asset_handler(&mut new());
+    check_asset_indexes(&mut new());
+    check_listings(&mut new());
+    check_quote_handler(&mut new());
+    check_transaction_handler(&mut new());
+    check_bulk_insert(&mut new());
+}
+
+/// Run the checks of batches, integrity, snapshots, dumps and subscriptions, each on a
+/// fresh handler created by `new`
+///
+/// Dumps are imported into a fresh handler created by `new_reference`, see `check_dump`.
+pub fn check_extended<H, R, F, G>(mut new: F, mut new_reference: G)
+where
+    H: BatchHandler + IntegrityHandler + SnapshotHandler + SubscriptionHandler,
+    R: BatchHandler + SnapshotHandler,
+    F: FnMut() -> H,
+    G: FnMut() -> R,
+{
+    check_batch_handler(&mut new());
+    check_integrity(&mut new());
+    check_write_semantics(&mut new());
+    check_retention(&mut new());
+    check_snapshot(&mut new());
+    check_dump(&mut new(), &mut new_reference());
+    check_subscriptions(&mut new());
+}
+
+/// Check storing, updating and deleting assets
+pub fn check_asset_handler<H: AssetHandler>(db: &mut H) {
+    assert!(
+        matches!(db.get_asset_by_name("Apple"), Err(DataError::NotFound { .. })),
+        "missing asset must not be found"
+    );
+
+    let mut asset = Asset::new("Apple", Some("865985".to_string()), None, None);
+    db.insert_asset(&asset).expect("failed to insert asset");
+    let stored = db.get_asset_by_name("Apple").expect("failed to get asset");
+    assert_eq!(stored.wkn, asset.wkn, "asset stored incorrectly");
+
+    asset.isin = Some("US0378331005".to_string());
+    db.update_asset(&asset).expect("failed to update asset");
+    let stored = db.get_asset_by_name("Apple").expect("failed to get updated asset");
+    assert_eq!(stored.isin, asset.isin, "asset not updated");
+
+    db.delete_asset(&asset).expect("failed to delete asset");
+    assert!(
+        db.get_asset_by_name("Apple").is_err(),
+        "deleted asset must not be found"
+    );
+}
+
+/// Check lookups by ISIN and WKN and that both stay unique and consistent on updates
+/// and deletes
+pub fn check_asset_indexes<H: AssetHandler>(db: &mut H) {
+    let isin = "US0378331005";
+    let mut apple = Asset::new("Apple", Some("865985".to_string()), Some(isin.to_string()), None);
+    db.insert_asset(&apple).expect("failed to insert asset");
+    assert_eq!(db.get_asset_by_isin(isin).expect("failed to get asset by ISIN").name, "Apple");
+    assert_eq!(db.get_asset_by_wkn("865985").expect("failed to get asset by WKN").name, "Apple");
+    assert!(
+        matches!(db.get_asset_by_isin("US5949181045"), Err(DataError::NotFound { .. })),
+        "unknown ISIN must not be found"
+    );
+
+    let duplicate = Asset::new("Apple Inc.", None, Some(isin.to_string()), None);
+    assert!(
+        matches!(db.insert_asset(&duplicate), Err(DataError::Conflict { .. })),
+        "duplicate ISIN must be rejected"
+    );
+    assert!(db.get_asset_by_name("Apple Inc.").is_err(), "rejected asset must not be stored");
+    let duplicate = Asset::new("Apple Inc.", Some("865985".to_string()), None, None);
+    assert!(
+        matches!(db.insert_asset(&duplicate), Err(DataError::Conflict { .. })),
+        "duplicate WKN must be rejected"
+    );
+
+    apple.wkn = Some("A0YJ3N".to_string());
+    db.update_asset(&apple).expect("failed to update asset");
+    assert!(db.get_asset_by_wkn("865985").is_err(), "previous WKN must not be found");
+    assert_eq!(db.get_asset_by_wkn("A0YJ3N").expect("failed to get asset by new WKN").name, "Apple");
+    assert_eq!(db.get_asset_by_isin(isin).expect("failed to get asset by ISIN").name, "Apple");
+
+    apple.wkn = None;
+    db.update_asset(&apple).expect("failed to remove WKN");
+    assert!(db.get_asset_by_wkn("A0YJ3N").is_err(), "removed WKN must not be found");
+
+    db.delete_asset(&apple).expect("failed to delete asset");
+    assert!(db.get_asset_by_isin(isin).is_err(), "ISIN of deleted asset must not be found");
+    db.insert_asset(&duplicate).expect("WKN of deleted asset must be available again");
+}
+
+/// Check listings of assets, tickers and sort prefixes, their order, pages and filters
+///
+/// Names of different lengths are used, since some backends don't store them in order.
+pub fn check_listings<H: QuoteHandler + TransactionHandler>(db: &mut H) {
+    assert!(db.list_assets(&Page::first(10)).expect("failed to list assets").is_empty());
+
+    for name in ["Microsoft", "IBM", "Apple", "Alphabet"].iter() {
+        db.insert_asset(&Asset::new(name, None, None, None)).expect("failed to insert asset");
+    }
+    let names = |assets: Vec<Asset>| assets.into_iter().map(|asset| asset.name).collect::<Vec<_>>();
+    assert_eq!(
+        names(db.list_assets(&Page::first(3)).expect("failed to list assets")),
+        vec!["Alphabet", "Apple", "IBM"],
+        "assets not listed in order of names"
+    );
+    assert_eq!(
+        names(db.list_assets(&Page::after("IBM", 3)).expect("failed to list assets")),
+        vec!["Microsoft"],
+        "next page of assets"
+    );
+    assert!(db.list_assets(&Page::after("Microsoft", 3)).expect("failed to list assets").is_empty());
+
+    let mut aapl_de = ticker("AAPL.DE");
+    aapl_de.currency = Currency::EUR;
+    let mut msft = ticker("MSFT");
+    msft.asset = "Microsoft".to_string();
+    for ticker in [ticker("AAPL"), aapl_de, msft].iter() {
+        db.insert_ticker(ticker).expect("failed to insert ticker");
+    }
+    let names = |tickers: Vec<Ticker>| tickers.into_iter().map(|ticker| ticker.name).collect::<Vec<_>>();
+    let list_tickers = |filter: TickerFilter, page: Page| {
+        names(db.list_tickers(&filter, &page).expect("failed to list tickers"))
+    };
+    assert_eq!(list_tickers(TickerFilter::all(), Page::first(10)), vec!["AAPL", "AAPL.DE", "MSFT"]);
+    assert_eq!(list_tickers(TickerFilter::all(), Page::after("AAPL", 1)), vec!["AAPL.DE"]);
+    assert_eq!(
+        list_tickers(TickerFilter::all().asset("Apple"), Page::first(10)),
+        vec!["AAPL", "AAPL.DE"],
+        "tickers of asset"
+    );
+    assert_eq!(
+        list_tickers(TickerFilter::all().currency(Currency::USD), Page::first(10)),
+        vec!["AAPL", "MSFT"],
+        "tickers in currency"
+    );
+    assert_eq!(
+        list_tickers(TickerFilter::all().asset("Apple").currency(Currency::EUR), Page::first(10)),
+        vec!["AAPL.DE"],
+        "tickers of asset in currency"
+    );
+    assert!(list_tickers(TickerFilter::all().asset("IBM"), Page::first(10)).is_empty());
+
+    let deleted = transaction(time(12), 1.0);
+    for (hour, sort_prefix) in [(10, "zz"), (11, "books"), (12, "boo"), (13, "books")].iter() {
+        db.insert_transaction(sort_prefix, &transaction(time(*hour), 1.0))
+            .expect("failed to insert transaction");
+    }
+    db.insert_transaction("deleted", &deleted).expect("failed to insert transaction");
+    db.delete_transaction("deleted", &deleted).expect("failed to delete transaction");
+    assert_eq!(
+        db.list_sort_prefixes(&Page::first(10)).expect("failed to list sort prefixes"),
+        vec!["boo", "books", "zz"],
+        "sort prefixes not listed in order or prefixes without transactions listed"
+    );
+    assert_eq!(
+        db.list_sort_prefixes(&Page::after("boo", 1)).expect("failed to list sort prefixes"),
+        vec!["books"]
+    );
+}
+
+/// Check tickers, latest and oldest quotes and quote cursors
+///
+/// Quotes are inserted out of order and next to tickers whose names share a common
+/// prefix, such that cursors leaking into neighbouring tickers are detected.
+pub fn check_quote_handler<H: QuoteHandler>(db: &mut H) {
+    db.insert_asset(&Asset::new("Apple", None, None, None)).expect("failed to insert asset");
+    let aapl = ticker("AAPL");
+    db.insert_ticker(&aapl).expect("failed to insert ticker");
+    assert_eq!(
+        db.get_ticker_by_name("AAPL").expect("failed to get ticker").currency,
+        Currency::USD,
+    );
+
+    assert!(db.get_latest_quote("AAPL").unwrap().is_none(), "latest quote of empty ticker");
+    assert!(db.get_oldest_quote("AAPL").unwrap().is_none(), "oldest quote of empty ticker");
+
+    for hour in [12, 10, 14, 11, 13].iter() {
+        db.insert_quote(&quote("AAPL", None, time(*hour), *hour as f64))
+            .expect("failed to insert quote");
+    }
+    for name in ["AAP", "AAPLE", "AAPM"].iter() {
+        db.insert_ticker(&ticker(name)).expect("failed to insert ticker");
+    }
+    db.insert_quote(&quote("AAP", None, time(9), 1.0)).unwrap();
+    db.insert_quote(&quote("AAP", None, time(15), 1.0)).unwrap();
+    db.insert_quote(&quote("AAPLE", None, time(9), 2.0)).unwrap();
+    db.insert_quote(&quote("AAPM", None, time(15), 3.0)).unwrap();
+
+    assert_eq!(db.get_latest_quote("AAPL").unwrap().map(|q| q.price), Some(14.0), "latest quote");
+    assert_eq!(db.get_oldest_quote("AAPL").unwrap().map(|q| q.price), Some(10.0), "oldest quote");
+    assert!(db.get_latest_quote("AAPLX").unwrap().is_none(), "latest quote of unknown ticker");
+
+    assert_eq!(
+        prices(db.quote_cursor_forward(&aapl, time(12))),
+        vec![12.0, 13.0, 14.0],
+        "forward cursor includes start and stops at the ticker"
+    );
+    assert_eq!(
+        prices(db.quote_cursor_reverse(&aapl, time(12))),
+        vec![12.0, 11.0, 10.0],
+        "reverse cursor includes start and stops at the ticker"
+    );
+    assert_eq!(prices(db.quote_cursor_forward(&aapl, time(0))).len(), 5);
+    assert_eq!(prices(db.quote_cursor_reverse(&aapl, time(23))).len(), 5);
+    assert!(prices(db.quote_cursor_forward(&aapl, time(15))).is_empty());
+    assert!(prices(db.quote_cursor_forward(&ticker("IBM"), time(0))).is_empty());
+
+    assert_eq!(
+        prices(db.quote_range_forward(&aapl, time(11), time(13))),
+        vec![11.0, 12.0],
+        "forward range is half open"
+    );
+    assert_eq!(
+        prices(db.quote_range_reverse(&aapl, time(11), time(13))),
+        vec![12.0, 11.0],
+        "reverse range is half open"
+    );
+    assert!(prices(db.quote_range_forward(&aapl, time(13), time(11))).is_empty());
+    assert!(prices(db.quote_range_reverse(&aapl, time(12), time(12))).is_empty());
+
+    // quotes at the same time are ordered by id
+    assert_eq!(db.insert_quote(&quote("AAPL", Some(1), time(14), 15.0)).ok(), Some(1), "given id not kept");
+    assert_eq!(db.get_latest_quote("AAPL").unwrap().map(|q| q.price), Some(15.0));
+    assert_eq!(
+        prices(db.quote_cursor_reverse(&aapl, time(14))),
+        vec![15.0, 14.0, 13.0, 12.0, 11.0, 10.0],
+    );
+
+    // quotes without id are kept next to those of the same time with the next id
+    let id = db.insert_quote(&quote("AAPL", None, time(14), 16.0)).expect("failed to insert quote");
+    assert_eq!(id, 2, "next sequence id not assigned");
+    assert_eq!(
+        db.get_quote_by_id("AAPL", time(14), 2).map(|q| (q.id, q.price)).ok(),
+        Some((Some(2), 16.0)),
+        "quote by id"
+    );
+    assert!(
+        matches!(db.get_quote_by_id("AAPL", time(14), 3), Err(DataError::NotFound { .. })),
+        "quote found by unknown id"
+    );
+    db.delete_quote(&quote("AAPL", Some(2), time(14), 16.0)).expect("failed to delete quote by id");
+    assert!(db.get_quote_by_id("AAPL", time(14), 2).is_err(), "quote not deleted by id");
+
+    // quotes before 1970 sort before later ones
+    let early = Utc.ymd(1969, 7, 20).and_hms(20, 17, 0);
+    db.insert_quote(&quote("AAPL", None, early, 1969.0)).unwrap();
+    assert_eq!(db.get_oldest_quote("AAPL").unwrap().map(|q| q.price), Some(1969.0));
+
+    let mut update = quote("AAPL", None, time(12), 120.0);
+    db.update_quote(&update).expect("failed to update quote");
+    assert_eq!(
+        prices(db.quote_range_forward(&aapl, time(12), time(12) + Duration::nanoseconds(1))),
+        vec![120.0],
+        "quote not replaced by update"
+    );
+
+    update.time = time(14);
+    db.delete_quote(&update).expect("failed to delete quote");
+    db.delete_quote(&quote("AAPL", Some(1), time(14), 15.0)).unwrap();
+    assert_eq!(db.get_latest_quote("AAPL").unwrap().map(|q| q.price), Some(13.0), "quote not deleted");
+
+    // tickers can only be deleted without quotes by default
+    let remaining: Vec<Quote> = db
+        .quote_cursor_forward(&aapl, early)
+        .map(|quote| quote.expect("quote cursor failed"))
+        .collect();
+    for quote in remaining.iter() {
+        db.delete_quote(quote).expect("failed to delete quote");
+    }
+    db.delete_ticker(&aapl).expect("failed to delete ticker");
+    assert!(db.get_ticker_by_name("AAPL").is_err(), "deleted ticker must not be found");
+}
+
+/// Check latest and oldest transactions and transaction cursors
+///
+/// Transactions are booked in a different order than they are created and stored next to
+/// sort prefixes extending the tested one, such that cursors following ids or leaking
+/// into neighbouring sort prefixes are detected.
+pub fn check_transaction_handler<H: TransactionHandler>(db: &mut H) {
+    assert!(db.get_latest_transaction("book").unwrap().is_none(), "latest transaction of empty book");
+    assert!(db.get_oldest_transaction("book").unwrap().is_none(), "oldest transaction of empty book");
+
+    for (id, day) in [12, 10, 14, 11, 13].iter().enumerate() {
+        db.insert_transaction("book", &booked(*day, id as u128 + 1, *day as f64))
+            .expect("failed to insert transaction");
+    }
+    db.insert_transaction("book", &booked(12, 6, 12.5)).unwrap();
+    db.insert_transaction("boo", &booked(15, 7, 1.0)).unwrap();
+    db.insert_transaction("books", &booked(9, 8, 2.0)).unwrap();
+    db.insert_transaction("books", &booked(15, 9, 2.0)).unwrap();
+
+    let latest = db.get_latest_transaction("book").unwrap().map(|t| t.cash_flow.amount.amount);
+    assert_eq!(latest, Some(14.0), "latest transaction by booking date");
+    let oldest = db.get_oldest_transaction("book").unwrap().map(|t| t.cash_flow.amount.amount);
+    assert_eq!(oldest, Some(10.0), "oldest transaction by booking date");
+    assert!(db.get_latest_transaction("bookx").unwrap().is_none(), "latest transaction of unknown book");
+
+    assert_eq!(
+        db.get_transaction_by_id("book", 4).map(|t| t.cash_flow.amount.amount).ok(),
+        Some(11.0),
+        "transaction by id"
+    );
+    assert!(
+        matches!(db.get_transaction_by_id("books", 4), Err(DataError::NotFound { .. })),
+        "transaction found under wrong sort prefix"
+    );
+
+    assert_eq!(
+        amounts(db.transaction_cursor_forward("book", day(12))),
+        vec![12.0, 12.5, 13.0, 14.0],
+        "forward cursor includes start, orders by id within a date and stops at the sort prefix"
+    );
+    assert_eq!(
+        amounts(db.transaction_cursor_forward("book", day(12) + Duration::hours(1))),
+        vec![13.0, 14.0],
+        "transactions count as booked at midnight"
+    );
+    assert_eq!(
+        amounts(db.transaction_cursor_reverse("book", day(12) + Duration::hours(1))),
+        vec![12.5, 12.0, 11.0, 10.0],
+        "reverse cursor includes start and stops at the sort prefix"
+    );
+    assert_eq!(amounts(db.transaction_cursor_forward("book", day(1))).len(), 6);
+    assert_eq!(amounts(db.transaction_cursor_reverse("book", day(30))).len(), 6);
+
+    assert_eq!(
+        amounts(db.transaction_range_forward("book", day(11), day(13))),
+        vec![11.0, 12.0, 12.5],
+        "forward range is half open"
+    );
+    assert_eq!(
+        amounts(db.transaction_range_reverse("book", day(11), day(13))),
+        vec![12.5, 12.0, 11.0],
+        "reverse range is half open"
+    );
+    assert!(amounts(db.transaction_range_forward("book", day(13), day(11))).is_empty());
+
+    db.update_transaction("book", &booked(9, 3, 140.0))
+        .expect("failed to update transaction");
+    let oldest = db.get_oldest_transaction("book").unwrap().map(|t| t.cash_flow.amount.amount);
+    assert_eq!(oldest, Some(140.0), "transaction not moved to its new booking date");
+    let latest = db.get_latest_transaction("book").unwrap().map(|t| t.cash_flow.amount.amount);
+    assert_eq!(latest, Some(13.0), "transaction left behind at its old booking date");
+
+    db.delete_transaction("book", &booked(13, 5, 13.0))
+        .expect("failed to delete transaction");
+    let latest = db.get_latest_transaction("book").unwrap().map(|t| t.cash_flow.amount.amount);
+    assert_eq!(latest, Some(12.5), "transaction not deleted");
+}
+
+/// Check that all operations of a batch are applied in order
+pub fn check_batch_handler<H: BatchHandler>(db: &mut H) {
+    let asset = Asset::new("Apple", None, Some("US0378331005".to_string()), None);
+    let purchase = transaction(time(10), -1000.0);
+    let mut fee = transaction(time(10) + Duration::nanoseconds(1), -5.0);
+    fee.transaction_type = TransactionType::Fee {
+        transaction_ref: Some(purchase.id),
+    };
+    let mut tax = transaction(time(10) + Duration::nanoseconds(2), -2.0);
+    tax.transaction_type = TransactionType::Tax {
+        transaction_ref: Some(purchase.id),
+    };
+    let stale = quote("AAPL", None, time(9), 1.0);
+
+    let mut batch = Batch::new();
+    batch
+        .insert_asset(&asset)
+        .insert_ticker(&ticker("AAPL"))
+        .insert_quote(&stale)
+        .insert_quote(&quote("AAPL", None, time(10), 100.0))
+        .delete_quote(&stale)
+        .insert_transaction("book", &purchase)
+        .insert_transaction("book", &fee)
+        .insert_transaction("book", &tax);
+    assert_eq!(batch.len(), 8);
+    db.write_batch(&batch).expect("failed to write batch");
+
+    assert!(db.get_asset_by_name("Apple").is_ok(), "asset of batch not stored");
+    assert!(db.get_ticker_by_name("AAPL").is_ok(), "ticker of batch not stored");
+    assert_eq!(
+        prices(db.quote_cursor_forward(&ticker("AAPL"), time(0))),
+        vec![100.0],
+        "operations of batch not applied in order"
+    );
+    assert_eq!(
+        amounts(db.transaction_cursor_forward("book", time(0))),
+        vec![-1000.0, -5.0, -2.0],
+        "transactions of batch not stored"
+    );
+
+    let mut batch = Batch::new();
+    batch
+        .delete_transaction("book", &purchase)
+        .delete_transaction("book", &fee)
+        .delete_transaction("book", &tax);
+    db.write_batch(&batch).expect("failed to write batch");
+    assert!(db.get_latest_transaction("book").unwrap().is_none(), "transactions of batch not deleted");
+
+    let mut renamed = asset.clone();
+    renamed.name = "Apple Inc.".to_string();
+    let mut renamed_ticker = ticker("AAPL");
+    renamed_ticker.asset = renamed.name.clone();
+    let mut batch = Batch::new();
+    batch
+        .delete_asset(&asset)
+        .insert_asset(&renamed)
+        .update_ticker(&renamed_ticker);
+    db.write_batch(&batch).expect("failed to rename asset");
+    assert_eq!(
+        db.get_asset_by_isin("US0378331005").expect("failed to get renamed asset").name,
+        "Apple Inc.",
+        "index not updated on rename"
+    );
+
+    db.write_batch(&Batch::new()).expect("failed to write empty batch");
+}
+
+/// Check that references to missing records are rejected, that deletes cascade according
+/// to the integrity policy and that a handler enforcing references reports no orphans
+///
+/// References are only checked once all operations of a batch have been applied.
+pub fn check_integrity<H: IntegrityHandler + BatchHandler>(db: &mut H) {
+    assert_eq!(db.integrity_policy(), IntegrityPolicy::Restrict, "default integrity policy");
+    let apple = Asset::new("Apple", None, None, None);
+    let aapl = ticker("AAPL");
+    let aapl_quote = quote("AAPL", None, time(10), 100.0);
+    let mut purchase = transaction(time(10), -1000.0);
+    purchase.transaction_type = TransactionType::Asset {
+        asset_name: apple.name.clone(),
+        position: 10.0,
+    };
+    let is_invalid = |result: Result<(), DataError>| matches!(result, Err(DataError::Validation { .. }));
+
+    assert!(is_invalid(db.insert_ticker(&aapl)), "ticker of missing asset stored");
+    assert!(db.get_ticker_by_name("AAPL").is_err(), "rejected ticker must not be stored");
+    assert!(is_invalid(db.insert_quote(&aapl_quote).map(|_| ())), "quote of missing ticker stored");
+    assert!(is_invalid(db.insert_transaction("book", &purchase)), "transaction of missing asset stored");
+    assert!(db.get_latest_transaction("book").unwrap().is_none(), "rejected transaction must not be stored");
+
+    let mut batch = Batch::new();
+    batch
+        .insert_quote(&aapl_quote)
+        .insert_ticker(&aapl)
+        .insert_transaction("book", &purchase)
+        .insert_asset(&apple);
+    db.write_batch(&batch).expect("failed to write records before those they refer to");
+
+    assert!(is_invalid(db.delete_ticker(&aapl)), "ticker with quotes deleted");
+    assert!(is_invalid(db.delete_asset(&apple)), "asset with tickers deleted");
+    assert!(db.get_latest_quote("AAPL").unwrap().is_some(), "quotes of rejected delete deleted");
+
+    db.set_integrity_policy(IntegrityPolicy::Cascade);
+    assert!(is_invalid(db.delete_asset(&apple)), "asset with transactions deleted");
+    assert!(db.get_ticker_by_name("AAPL").is_ok(), "ticker of rejected delete deleted");
+    db.delete_transaction("book", &purchase).expect("failed to delete transaction");
+    db.delete_asset(&apple).expect("failed to delete asset with tickers");
+    assert!(db.get_ticker_by_name("AAPL").is_err(), "ticker of deleted asset not deleted");
+    assert!(db.get_latest_quote("AAPL").unwrap().is_none(), "quotes of deleted asset not deleted");
+
+    // quotes written after deleting their ticker in the same batch are kept
+    let mut batch = Batch::new();
+    batch
+        .insert_asset(&apple)
+        .insert_ticker(&aapl)
+        .insert_quote(&aapl_quote)
+        .delete_ticker(&aapl)
+        .insert_ticker(&aapl)
+        .insert_quote(&quote("AAPL", None, time(11), 110.0));
+    db.write_batch(&batch).expect("failed to write batch with cascading delete");
+    assert_eq!(
+        prices(db.quote_cursor_forward(&aapl, time(0))),
+        vec![110.0],
+        "cascading delete not applied in order"
+    );
+
+    let report = db.check_integrity().expect("failed to check integrity");
+    assert!(report.is_clean(), "orphans reported: {:?}", report);
+}
+
+/// Check that inserts fail on existing records, updates fail on missing records, upserts
+/// succeed in both cases and a failing batch leaves no trace
+pub fn check_write_semantics<H: BatchHandler>(db: &mut H) {
+    let apple = Asset::new("Apple", None, None, None);
+    let aapl = ticker("AAPL");
+    let aapl_quote = quote("AAPL", None, time(10), 100.0);
+    let sale = transaction(time(10), 1000.0);
+    let is_conflict = |result: Result<(), DataError>| matches!(result, Err(DataError::Conflict { .. }));
+    let is_not_found = |result: Result<(), DataError>| matches!(result, Err(DataError::NotFound { .. }));
+
+    assert!(is_not_found(db.update_asset(&apple)), "missing asset updated");
+    assert!(is_not_found(db.update_transaction("book", &sale)), "missing transaction updated");
+    assert!(db.get_latest_transaction("book").unwrap().is_none(), "rejected update must not store");
+
+    db.upsert_asset(&apple).expect("failed to upsert missing asset");
+    db.upsert_ticker(&aapl).expect("failed to upsert missing ticker");
+    db.insert_quote(&aapl_quote).expect("failed to insert quote");
+    db.insert_transaction("book", &sale).expect("failed to insert transaction");
+
+    assert!(is_conflict(db.insert_asset(&apple)), "existing asset inserted");
+    assert!(is_conflict(db.insert_ticker(&aapl)), "existing ticker inserted");
+    let existing = quote("AAPL", Some(0), time(10), 1.0);
+    assert!(is_conflict(db.insert_quote(&existing).map(|_| ())), "existing quote inserted");
+    assert!(is_conflict(db.insert_transaction("book", &sale)), "existing transaction inserted");
+    assert_eq!(
+        prices(db.quote_cursor_forward(&aapl, time(0))),
+        vec![100.0],
+        "rejected insert must not overwrite"
+    );
+
+    let mut revised = aapl_quote.clone();
+    revised.price = 101.0;
+    db.update_quote(&revised).expect("failed to update existing quote");
+    revised.price = 102.0;
+    db.upsert_quote(&revised).expect("failed to upsert existing quote");
+    assert_eq!(prices(db.quote_cursor_forward(&aapl, time(0))), vec![102.0], "quote not updated");
+
+    let mut batch = Batch::new();
+    batch
+        .insert_quote(&quote("AAPL", Some(0), time(11), 110.0))
+        .insert_quote(&quote("AAPL", Some(0), time(11), 111.0));
+    assert!(is_conflict(db.write_batch(&batch)), "record inserted twice in one batch");
+    assert_eq!(
+        prices(db.quote_cursor_forward(&aapl, time(0))),
+        vec![102.0],
+        "failed batch must not be applied partially"
+    );
+
+    let mut batch = Batch::new();
+    batch
+        .insert_quote(&quote("AAPL", None, time(11), 110.0))
+        .insert_quote(&quote("AAPL", None, time(11), 111.0));
+    db.write_batch(&batch).expect("failed to insert quotes without id");
+    assert_eq!(
+        db.get_quote_by_id("AAPL", time(11), 1).map(|q| q.price).ok(),
+        Some(111.0),
+        "quotes without id in one batch must get consecutive ids"
+    );
+}
+
+/// Check that bulk inserts count inserted, replaced and skipped quotes, match quotes
+/// without id with the stored quote of id 0 and store nothing if any quote is rejected
+pub fn check_bulk_insert<H: QuoteHandler>(db: &mut H) {
+    let aapl = ticker("AAPL");
+    let report = |inserted, replaced, skipped| BulkReport { inserted, replaced, skipped };
+    db.insert_asset(&Asset::new("Apple", None, None, None)).expect("failed to insert asset");
+    db.insert_ticker(&aapl).expect("failed to insert ticker");
+    db.insert_quote(&quote("AAPL", None, time(10), 10.0)).expect("failed to insert quote");
+
+    let quotes = vec![
+        quote("AAPL", None, time(10), 100.0),
+        quote("AAPL", None, time(11), 110.0),
+        quote("AAPL", Some(0), time(11), 111.0),
+        quote("AAPL", Some(1), time(11), 112.0),
+    ];
+    assert_eq!(
+        db.insert_quotes(&quotes, OnDuplicate::Skip).ok(),
+        Some(report(2, 0, 2)),
+        "duplicates not skipped"
+    );
+    assert_eq!(prices(db.quote_cursor_forward(&aapl, time(0))), vec![10.0, 110.0, 112.0]);
+    assert_eq!(
+        db.get_quote_by_id("AAPL", time(11), 0).map(|q| q.id).ok(),
+        Some(Some(0)),
+        "quote without id not stored with id 0"
+    );
+
+    assert_eq!(
+        db.insert_quotes(&quotes, OnDuplicate::Replace).ok(),
+        Some(report(0, 4, 0)),
+        "duplicates not replaced"
+    );
+    assert_eq!(prices(db.quote_cursor_forward(&aapl, time(0))), vec![100.0, 111.0, 112.0]);
+
+    let orphaned = vec![quote("AAPL", None, time(12), 120.0), quote("MSFT", None, time(12), 200.0)];
+    assert!(
+        matches!(db.insert_quotes(&orphaned, OnDuplicate::Skip), Err(DataError::Validation { .. })),
+        "quote of missing ticker stored"
+    );
+    assert!(db.get_quote_by_id("AAPL", time(12), 0).is_err(), "rejected bulk insert must not store");
+
+    let mut writer = QuoteWriter::with_chunk_size(db, OnDuplicate::Skip, 2);
+    for hour in 11..16 {
+        writer.write(quote("AAPL", None, time(hour), hour as f64)).expect("failed to write quote");
+    }
+    assert_eq!(writer.report(), report(3, 0, 1), "full chunks not written");
+    assert_eq!(writer.finish().ok(), Some(report(4, 0, 1)), "remaining quotes not written");
+    assert_eq!(
+        prices(db.quote_cursor_forward(&aapl, time(0))),
+        vec![100.0, 111.0, 112.0, 12.0, 13.0, 14.0, 15.0]
+    );
+}
+
+/// Check that range deletes delete the quotes in `[from, to)` only and retention rules
+/// keep the last quote of each interval of the quotes older than their age
+pub fn check_retention<H: BatchHandler>(db: &mut H) {
+    let aapl = ticker("AAPL");
+    let msft = ticker("MSFT");
+    let at = |day: u32, hour: u32, minute: u32| Utc.ymd(2020, 9, day).and_hms(hour, minute, 0);
+    db.insert_asset(&Asset::new("Apple", None, None, None)).expect("failed to insert asset");
+    db.insert_ticker(&aapl).expect("failed to insert ticker");
+    db.insert_ticker(&msft).expect("failed to insert ticker");
+
+    for hour in 10..15 {
+        db.insert_quote(&quote("AAPL", None, time(hour), hour as f64)).expect("failed to insert quote");
+    }
+    db.delete_quotes("AAPL", time(11), time(13)).expect("failed to delete quotes");
+    assert_eq!(prices(db.quote_cursor_forward(&aapl, time(0))), vec![10.0, 13.0, 14.0]);
+    db.delete_quotes("IBM", time(0), day(2)).expect("deleting quotes of missing ticker failed");
+    db.delete_quotes("AAPL", time(0), day(2)).expect("failed to delete quotes");
+    assert!(db.get_oldest_quote("AAPL").unwrap().is_none(), "quotes not deleted");
+
+    let aapl_times = [
+        at(2, 10, 0), at(2, 12, 0), at(3, 8, 0),
+        at(7, 10, 0), at(7, 10, 15), at(7, 10, 45), at(7, 11, 0),
+        at(9, 9, 0), at(9, 9, 30),
+    ];
+    for (index, time) in aapl_times.iter().enumerate() {
+        db.insert_quote(&quote("AAPL", None, *time, index as f64)).expect("failed to insert quote");
+    }
+    db.insert_quote(&quote("MSFT", None, at(2, 10, 0), 1.0)).expect("failed to insert quote");
+    db.insert_quote(&quote("MSFT", None, at(9, 10, 0), 2.0)).expect("failed to insert quote");
+
+    let intraday = RetentionPolicy::new()
+        .thin_after(Duration::days(5), Duration::days(1))
+        .thin_after(Duration::days(2), Duration::hours(1));
+    let rules = RetentionRules::new()
+        .default_policy(intraday)
+        .ticker("MSFT", RetentionPolicy::new().delete_after(Duration::days(5)));
+    assert_eq!(
+        db.apply_retention(&rules, at(10, 0, 0)).ok(),
+        Some(RetentionReport { tickers: 2, deleted: 4 }),
+        "retention report"
+    );
+    assert_eq!(
+        prices(db.quote_cursor_forward(&aapl, at(1, 0, 0))),
+        vec![1.0, 2.0, 5.0, 6.0, 7.0, 8.0],
+        "last quote of each interval not kept"
+    );
+    assert_eq!(prices(db.quote_cursor_forward(&msft, at(1, 0, 0))), vec![2.0], "old quotes not deleted");
+
+    assert_eq!(
+        db.apply_retention(&rules, at(10, 0, 0)).ok(),
+        Some(RetentionReport { tickers: 2, deleted: 0 }),
+        "retention must be idempotent"
+    );
+}
+
+/// Check that snapshots read the same records as the handler and see the writes made
+/// before they were taken
+pub fn check_snapshot<H: SnapshotHandler + BatchHandler>(db: &mut H) {
+    let aapl = ticker("AAPL");
+    db.insert_asset(&Asset::new("Apple", None, Some("US0378331005".to_string()), None))
+        .expect("failed to insert asset");
+    db.insert_ticker(&aapl).expect("failed to insert ticker");
+    for hour in 10..13 {
+        db.insert_quote(&quote("AAPL", None, time(hour), hour as f64)).expect("failed to insert quote");
+    }
+    db.insert_transaction("book", &booked(1, 1, 10.0)).expect("failed to insert transaction");
+    db.insert_transaction("book", &booked(2, 2, 20.0)).expect("failed to insert transaction");
+
+    {
+        let snapshot = db.snapshot().expect("failed to take snapshot");
+        assert!(snapshot.get_asset_by_isin("US0378331005").is_ok(), "asset not found by ISIN");
+        assert_eq!(snapshot.list_assets(&Page::first(10)).map(|assets| assets.len()).ok(), Some(1));
+        assert_eq!(
+            snapshot
+                .list_tickers(&TickerFilter::all(), &Page::first(10))
+                .map(|tickers| tickers.len())
+                .ok(),
+            Some(1)
+        );
+        assert_eq!(snapshot.get_quote_by_id("AAPL", time(11), 0).map(|q| q.price).ok(), Some(11.0));
+        assert_eq!(snapshot.get_latest_quote("AAPL").unwrap().map(|q| q.price), Some(12.0));
+        assert_eq!(snapshot.get_oldest_quote("AAPL").unwrap().map(|q| q.price), Some(10.0));
+        assert_eq!(prices(snapshot.quote_cursor_forward(&aapl, time(11))), vec![11.0, 12.0]);
+        assert_eq!(prices(snapshot.quote_cursor_reverse(&aapl, time(11))), vec![11.0, 10.0]);
+        assert_eq!(prices(snapshot.quote_range_forward(&aapl, time(10), time(12))), vec![10.0, 11.0]);
+        assert_eq!(prices(snapshot.quote_range_reverse(&aapl, time(10), time(12))), vec![11.0, 10.0]);
+
+        assert_eq!(snapshot.get_transaction_by_id("book", 2).map(|t| t.id).ok(), Some(2));
+        assert_eq!(snapshot.list_sort_prefixes(&Page::first(10)).ok(), Some(vec!["book".to_string()]));
+        assert_eq!(snapshot.get_latest_transaction("book").unwrap().map(|t| t.id), Some(2));
+        assert_eq!(snapshot.get_oldest_transaction("book").unwrap().map(|t| t.id), Some(1));
+        assert_eq!(amounts(snapshot.transaction_cursor_forward("book", day(2))), vec![20.0]);
+        assert_eq!(amounts(snapshot.transaction_cursor_reverse("book", day(1))), vec![10.0]);
+        assert_eq!(amounts(snapshot.transaction_range_forward("book", day(1), day(3))), vec![10.0, 20.0]);
+        assert_eq!(amounts(snapshot.transaction_range_reverse("book", day(1), day(3))), vec![20.0, 10.0]);
+    }
+
+    db.delete_quotes("AAPL", time(10), time(11)).expect("failed to delete quotes");
+    let snapshot = db.snapshot().expect("failed to take snapshot");
+    assert_eq!(
+        prices(snapshot.quote_cursor_forward(&aapl, time(0))),
+        vec![11.0, 12.0],
+        "snapshot must see earlier writes"
+    );
+}
+
+/// Check that dumps hold all records and import into the empty `reference` handler, whose
+/// dump is the same
+pub fn check_dump<H, R>(db: &mut H, reference: &mut R)
+where
+    H: SnapshotHandler + BatchHandler,
+    R: SnapshotHandler + BatchHandler,
+{
+    let expected = DumpReport { assets: 2, tickers: 2, quotes: 4, transactions: 3 };
+    db.insert_asset(&Asset::new("Apple", None, Some("US0378331005".to_string()), None))
+        .expect("failed to insert asset");
+    db.insert_asset(&Asset::new("Microsoft", Some("870747".to_string()), None, None))
+        .expect("failed to insert asset");
+    db.insert_ticker(&ticker("AAPL")).expect("failed to insert ticker");
+    db.insert_ticker(&Ticker { asset: "Microsoft".to_string(), ..ticker("MSFT") })
+        .expect("failed to insert ticker");
+    db.insert_quote(&quote("AAPL", None, time(10), 10.0)).expect("failed to insert quote");
+    db.insert_quote(&quote("AAPL", None, time(10), 10.5)).expect("failed to insert quote");
+    db.insert_quote(&quote("AAPL", None, time(11), 11.0)).expect("failed to insert quote");
+    db.insert_quote(&quote("MSFT", None, time(10), 200.0)).expect("failed to insert quote");
+    db.insert_transaction("book", &booked(2, 1, 10.0)).expect("failed to insert transaction");
+    db.insert_transaction("book", &booked(1, 2, 20.0)).expect("failed to insert transaction");
+    db.insert_transaction("savings", &booked(1, 3, 30.0)).expect("failed to insert transaction");
+
+    let mut dump = Vec::new();
+    assert_eq!(export_jsonl(db, &mut dump).ok(), Some(expected), "export report");
+    let dump = String::from_utf8(dump).expect("dump is not UTF-8");
+    assert_eq!(dump.lines().count(), 12, "dump must hold a header and a line per record");
+
+    assert_eq!(import_jsonl(reference, dump.as_bytes()).ok(), Some(expected), "import report");
+    assert_eq!(
+        reference.get_asset_by_wkn("870747").map(|asset| asset.name).ok(),
+        Some("Microsoft".to_string())
+    );
+    assert_eq!(prices(reference.quote_cursor_forward(&ticker("AAPL"), time(0))), vec![10.0, 10.5, 11.0]);
+    let mut copied = Vec::new();
+    export_jsonl(reference, &mut copied).expect("failed to export copy");
+    assert_eq!(String::from_utf8(copied).ok(), Some(dump.clone()), "dump of imported records differs");
+
+    assert_eq!(import_jsonl(db, dump.as_bytes()).ok(), Some(expected), "import must replace records");
+    assert_eq!(prices(db.quote_cursor_forward(&ticker("AAPL"), time(0))), vec![10.0, 10.5, 11.0]);
+}
+
+/// Short description of the change, since records can't be compared
+fn describe(change: &Change) -> String {
+    match change {
+        Change::Asset(kind, asset) => format!("{:?} asset {}", kind, asset.name),
+        Change::Ticker(kind, ticker) => format!("{:?} ticker {}", kind, ticker.name),
+        Change::Quote(kind, quote) =>
+            format!("{:?} quote {}@{}#{}", kind, quote.ticker, quote.time.hour(), quote.id.unwrap_or(-1)),
+        Change::Transaction { kind, sort_prefix, transaction } =>
+            format!("{:?} transaction {}/{}", kind, sort_prefix, transaction.id),
+        Change::QuotesDeleted { ticker, from, to } => format!(
+            "Deleted quotes {}@{:?}..{:?}",
+            ticker,
+            from.map(|time| time.hour()),
+            to.map(|time| time.hour())
+        ),
+    }
+}
+
+fn received(changes: &Receiver<Change>) -> Vec<String> {
+    changes.try_iter().map(|change| describe(&change)).collect()
+}
+
+/// Check that subscribers receive the changes matching their filter once written, in
+/// order, including those of bulk inserts, range deletes and cascading deletes
+pub fn check_subscriptions<H: SubscriptionHandler + IntegrityHandler + BatchHandler>(db: &mut H) {
+    let apple = Asset::new("Apple", None, None, None);
+    let all = db.subscribe(ChangeFilter::All);
+    let aapl = db.subscribe(ChangeFilter::Ticker("AAPL".to_string()));
+    let book = db.subscribe(ChangeFilter::SortPrefix("book".to_string()));
+    let assets = db.subscribe(ChangeFilter::DataType(DataType::Asset));
+
+    db.insert_asset(&apple).expect("failed to insert asset");
+    db.insert_ticker(&ticker("AAPL")).expect("failed to insert ticker");
+    db.insert_ticker(&ticker("MSFT")).expect("failed to insert ticker");
+    db.insert_quote(&quote("AAPL", None, time(10), 10.0)).expect("failed to insert quote");
+    db.upsert_quote(&quote("AAPL", Some(0), time(10), 11.0)).expect("failed to upsert quote");
+    db.upsert_quote(&quote("MSFT", Some(0), time(10), 20.0)).expect("failed to upsert quote");
+    db.insert_transaction("book", &booked(1, 1, 10.0)).expect("failed to insert transaction");
+    db.insert_transaction("cash", &booked(1, 2, 20.0)).expect("failed to insert transaction");
+    db.delete_quote(&quote("AAPL", Some(5), time(12), 0.0)).expect("failed to delete missing quote");
+
+    assert_eq!(
+        received(&all),
+        vec![
+            "Inserted asset Apple",
+            "Inserted ticker AAPL",
+            "Inserted ticker MSFT",
+            "Inserted quote AAPL@10#0",
+            "Updated quote AAPL@10#0",
+            "Inserted quote MSFT@10#0",
+            "Inserted transaction book/1",
+            "Inserted transaction cash/2",
+        ],
+        "changes of all records"
+    );
+    assert_eq!(
+        received(&aapl),
+        vec!["Inserted ticker AAPL", "Inserted quote AAPL@10#0", "Updated quote AAPL@10#0"],
+        "changes of a ticker"
+    );
+    assert_eq!(received(&book), vec!["Inserted transaction book/1"], "changes of a sort prefix");
+    assert_eq!(received(&assets), vec!["Inserted asset Apple"], "changes of a data type");
+
+    let mut batch = Batch::new();
+    batch
+        .insert_quote(&quote("AAPL", None, time(11), 12.0))
+        .insert_ticker(&ticker("AAPL"));
+    assert!(db.write_batch(&batch).is_err(), "batch inserting an existing ticker written");
+    assert!(received(&all).is_empty(), "changes of failed batch published");
+
+    let quotes = [quote("AAPL", None, time(10), 13.0), quote("AAPL", None, time(11), 14.0)];
+    db.insert_quotes(&quotes, OnDuplicate::Replace).expect("failed to insert quotes");
+    db.delete_quotes("AAPL", time(11), time(12)).expect("failed to delete quotes");
+    assert_eq!(
+        received(&aapl),
+        vec!["Updated quote AAPL@10#0", "Inserted quote AAPL@11#0", "Deleted quotes AAPL@Some(11)..Some(12)"],
+        "changes of bulk insert and range delete"
+    );
+
+    drop(aapl);
+    db.set_integrity_policy(IntegrityPolicy::Cascade);
+    db.delete_asset(&apple).expect("failed to delete asset with tickers");
+    assert_eq!(
+        received(&all)[3..],
+        [
+            "Deleted asset Apple",
+            "Deleted ticker AAPL",
+            "Deleted quotes AAPL@None..None",
+            "Deleted ticker MSFT",
+            "Deleted quotes MSFT@None..None",
+        ],
+        "changes of cascading delete"
+    );
+}
@@ -0,0 +1,249 @@
+//! Backend independent dumps of all records as JSON Lines
+//!
+//! The values stored by the backends are tied to their layout, e.g. bincode encoded
+//! structs in RocksDB. Dumps hold one JSON object per line instead, starting with a
+//! header carrying the versions of the dump format and of the schema of its records,
+//! followed by all assets, tickers, quotes and transactions, in that order:
+//!
+//! ```text
+//! {"header":{"version":1,"schema_version":1}}
+//! {"asset":{"name":"Apple","wkn":null,"isin":"US0378331005","note":null}}
+//! {"ticker":{"name":"AAPL","asset":"Apple","currency":"USD","priority":1,"factor":1.0}}
+//! {"quote":{"id":0,"ticker":"AAPL","price":115.0,"time":"2020-09-01T10:00:00Z","volume":null}}
+//! {"transaction":{"sort_prefix":"book","transaction":{...}}}
+//! ```
+//!
+//! Records are ordered by name, time and id, hence dumps of the same records are equal
+//! regardless of the backend they are taken from, and differences between dumps are
+//! readable with any diff tool.
+use std::io::{BufRead, BufWriter, Write};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use super::bulk::DEFAULT_CHUNK_SIZE;
+use super::{Batch, BatchHandler, DataError, Operation, Page, SnapshotHandler, TickerFilter};
+use crate::asset::Asset;
+use crate::quote::{Quote, Ticker};
+use crate::transaction::Transaction;
+
+/// Version of the dump format written by this version
+pub const DUMP_VERSION: u32 = 1;
+
+/// Version of the schema of the assets, tickers, quotes and transactions written by this
+/// version, to be increased whenever their fields change
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// Number of names listed at once while exporting
+const PAGE_SIZE: usize = 100;
+
+/// Line of a dump
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum Record {
+    Header {
+        version: u32,
+        /// Missing in dumps written before the schema version was added, which hold
+        /// records of the first schema
+        #[serde(default)]
+        schema_version: Option<u32>,
+    },
+    Asset(Asset),
+    Ticker(Ticker),
+    Quote(Quote),
+    Transaction { sort_prefix: String, transaction: Transaction },
+}
+
+/// Number of records exported or imported
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct DumpReport {
+    pub assets: usize,
+    pub tickers: usize,
+    pub quotes: usize,
+    pub transactions: usize,
+}
+
+/// Write all records of the handler to the writer, as of a snapshot taken at the start
+pub fn export_jsonl<H: SnapshotHandler + ?Sized, W: Write>(db: &mut H, writer: W) -> Result<DumpReport, DataError> {
+    let snapshot = db.snapshot()?;
+    let mut writer = BufWriter::new(writer);
+    let mut report = DumpReport::default();
+
+    let header = Record::Header {
+        version: DUMP_VERSION,
+        schema_version: Some(SCHEMA_VERSION),
+    };
+    write_record(&mut writer, &header)?;
+
+    for asset in all_pages(|page| snapshot.list_assets(page), |asset| &asset.name)? {
+        write_record(&mut writer, &Record::Asset(asset))?;
+        report.assets += 1;
+    }
+
+    let tickers = all_pages(|page| snapshot.list_tickers(&TickerFilter::all(), page), |ticker| &ticker.name)?;
+    for ticker in &tickers {
+        write_record(&mut writer, &Record::Ticker(ticker.clone()))?;
+        report.tickers += 1;
+    }
+    for ticker in &tickers {
+        let oldest = match snapshot.get_oldest_quote(&ticker.name)? {
+            Some(quote) => quote.time,
+            None => continue,
+        };
+        for quote in snapshot.quote_cursor_forward(ticker, oldest) {
+            write_record(&mut writer, &Record::Quote(quote?))?;
+            report.quotes += 1;
+        }
+    }
+
+    for sort_prefix in all_pages(|page| snapshot.list_sort_prefixes(page), |sort_prefix| sort_prefix)? {
+        let oldest = match snapshot.get_oldest_transaction(&sort_prefix)? {
+            Some(transaction) => DateTime::<Utc>::from_utc(transaction.cash_flow.date.and_hms(0, 0, 0), Utc),
+            None => continue,
+        };
+        for transaction in snapshot.transaction_cursor_forward(&sort_prefix, oldest) {
+            let record = Record::Transaction {
+                sort_prefix: sort_prefix.clone(),
+                transaction: transaction?,
+            };
+            write_record(&mut writer, &record)?;
+            report.transactions += 1;
+        }
+    }
+
+    writer.flush().map_err(|e| DataError::storage("write dump", e))?;
+    Ok(report)
+}
+
+/// Read a dump and write its records to the handler, replacing stored records with the
+/// same keys
+///
+/// Records are written in chunks, hence a failure leaves the records of earlier chunks
+/// written. Importing the same dump again completes the import. Dumps of a later
+/// version of the format or of the schema of the records are rejected.
+pub fn import_jsonl<H: BatchHandler + ?Sized, R: BufRead>(db: &mut H, reader: R) -> Result<DumpReport, DataError> {
+    let mut report = DumpReport::default();
+    let mut batch = Batch::new();
+    let mut header = false;
+
+    for (index, line) in reader.lines().enumerate() {
+        let line = line.map_err(|e| DataError::storage("read dump", e))?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let record: Record =
+            serde_json::from_str(&line).map_err(|e| DataError::parse(format!("dump line {}", index + 1), e))?;
+
+        let operation = match record {
+            Record::Header { version, schema_version } if !header => {
+                if version > DUMP_VERSION {
+                    return Err(DataError::IncompatibleSchema { found: version, supported: DUMP_VERSION });
+                }
+                let schema_version = schema_version.unwrap_or(1);
+                if schema_version > SCHEMA_VERSION {
+                    return Err(DataError::IncompatibleSchema { found: schema_version, supported: SCHEMA_VERSION });
+                }
+                header = true;
+                continue;
+            }
+            Record::Header { .. } => {
+                return Err(DataError::parse(format!("dump line {}", index + 1), "header after the first record"))
+            }
+            _ if !header => {
+                return Err(DataError::parse(format!("dump line {}", index + 1), "dump does not start with a header"))
+            }
+            Record::Asset(asset) => {
+                report.assets += 1;
+                Operation::UpsertAsset(asset)
+            }
+            Record::Ticker(ticker) => {
+                report.tickers += 1;
+                Operation::UpsertTicker(ticker)
+            }
+            Record::Quote(quote) => {
+                report.quotes += 1;
+                Operation::UpsertQuote(quote)
+            }
+            Record::Transaction { sort_prefix, transaction } => {
+                report.transactions += 1;
+                Operation::UpsertTransaction { sort_prefix, transaction }
+            }
+        };
+
+        batch.push(operation);
+        if batch.len() >= DEFAULT_CHUNK_SIZE {
+            db.write_batch(&batch)?;
+            batch = Batch::new();
+        }
+    }
+
+    if !header {
+        return Err(DataError::parse("dump", "dump is empty"));
+    }
+    if !batch.is_empty() {
+        db.write_batch(&batch)?;
+    }
+    Ok(report)
+}
+
+fn write_record<W: Write>(writer: &mut W, record: &Record) -> Result<(), DataError> {
+    serde_json::to_writer(&mut *writer, record).map_err(|e| DataError::storage("write dump", e))?;
+    writer.write_all(b"\n").map_err(|e| DataError::storage("write dump", e))
+}
+
+/// Items of all pages of a listing, in the order of their names
+fn all_pages<T, L, N>(mut list: L, name: N) -> Result<Vec<T>, DataError>
+where
+    L: FnMut(&Page) -> Result<Vec<T>, DataError>,
+    N: Fn(&T) -> &str,
+{
+    let mut items = Vec::new();
+    let mut page = Page::first(PAGE_SIZE);
+    loop {
+        let listed = list(&page)?;
+        let next = match listed.last() {
+            Some(last) if listed.len() == page.limit => Some(Page::after(name(last), PAGE_SIZE)),
+            _ => None,
+        };
+        items.extend(listed);
+        match next {
+            Some(next) => page = next,
+            None => return Ok(items),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data_handler::AssetHandler;
+    use crate::memory_handler::MemoryDB;
+
+    #[test]
+    fn test_import_checks_header() {
+        let asset = r#"{"asset":{"name":"Apple","wkn":null,"isin":null,"note":null}}"#;
+        let mut db = MemoryDB::new();
+
+        let dump = format!("{{\"header\":{{\"version\":1}}}}\n\n{}\n", asset);
+        let report = import_jsonl(&mut db, dump.as_bytes()).unwrap();
+        assert_eq!(report, DumpReport { assets: 1, ..DumpReport::default() });
+        assert!(db.get_asset_by_name("Apple").is_ok());
+
+        assert!(matches!(import_jsonl(&mut db, asset.as_bytes()), Err(DataError::Parse { .. })));
+        assert!(matches!(import_jsonl(&mut db, &b""[..]), Err(DataError::Parse { .. })));
+        assert!(matches!(
+            import_jsonl(&mut db, &b"{\"header\":{\"version\":2}}\n"[..]),
+            Err(DataError::IncompatibleSchema { found: 2, supported: DUMP_VERSION })
+        ));
+        assert!(matches!(
+            import_jsonl(&mut db, &b"{\"header\":{\"version\":1,\"schema_version\":2}}\n"[..]),
+            Err(DataError::IncompatibleSchema { found: 2, supported: SCHEMA_VERSION })
+        ));
+        let current = format!("{{\"header\":{{\"version\":1,\"schema_version\":{}}}}}\n", SCHEMA_VERSION);
+        assert!(import_jsonl(&mut db, current.as_bytes()).is_ok());
+        assert!(matches!(
+            import_jsonl(&mut db, &b"{\"header\":{\"version\":1}}\n{\"asset\":1}\n"[..]),
+            Err(DataError::Parse { value, .. }) if value == "dump line 2"
+        ));
+    }
+}
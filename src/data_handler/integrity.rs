@@ -0,0 +1,322 @@
+//! Referential integrity between assets, tickers, quotes and transactions
+//!
+//! Tickers refer to their asset and quotes to their ticker by name, as do asset,
+//! dividend and interest transactions to their asset. Handlers check these references
+//! once all operations of a batch have been applied, such that a batch may e.g. rename
+//! an asset and move its tickers to the new name in any order.
+use std::collections::{BTreeMap, BTreeSet};
+
+use chrono::{DateTime, Utc};
+
+use super::changes::{Change, ChangeKind};
+use super::{quote_key_string, transaction_key_string, Batch, DataError, DataType, Operation};
+use super::{QuoteHandler, TransactionHandler};
+use crate::quote::{Quote, Ticker};
+
+/// How deleting a record that is still referred to is handled
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub enum IntegrityPolicy {
+    /// Reject deleting assets with tickers and tickers with quotes
+    #[default]
+    Restrict,
+    /// Delete the tickers of a deleted asset and the quotes of a deleted ticker as well
+    Cascade,
+}
+
+/// Ticker of an asset that does not exist
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OrphanedTicker {
+    pub ticker: String,
+    pub asset: String,
+}
+
+/// Quotes of a ticker that does not exist
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OrphanedQuotes {
+    pub ticker: String,
+    pub count: usize,
+}
+
+/// Transaction of an asset that does not exist
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OrphanedTransaction {
+    pub sort_prefix: String,
+    pub id: u128,
+    pub asset: String,
+}
+
+/// Records referring to missing records, each list ordered by ticker name or sort
+/// prefix and id
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct IntegrityReport {
+    pub tickers: Vec<OrphanedTicker>,
+    pub quotes: Vec<OrphanedQuotes>,
+    pub transactions: Vec<OrphanedTransaction>,
+}
+
+impl IntegrityReport {
+    /// Whether no orphaned records have been found
+    pub fn is_clean(&self) -> bool {
+        self.tickers.is_empty() && self.quotes.is_empty() && self.transactions.is_empty()
+    }
+}
+
+/// Handler enforcing the references between records on writes
+///
+/// Writes leaving a ticker, quote or transaction behind that refers to a missing record
+/// fail with `DataError::Validation`. Transactions are never deleted along with their
+/// asset, hence deleting an asset with transactions fails regardless of the policy.
+pub trait IntegrityHandler: QuoteHandler + TransactionHandler {
+    fn integrity_policy(&self) -> IntegrityPolicy;
+    fn set_integrity_policy(&mut self, policy: IntegrityPolicy);
+
+    /// Find records referring to missing records, e.g. those written by earlier versions,
+    /// which did not enforce references
+    fn check_integrity(&mut self) -> Result<IntegrityReport, DataError>;
+}
+
+/// Records of a backend as changed by the operations of a batch applied so far
+pub(crate) trait BatchStore {
+    fn asset_exists(&mut self, name: &str) -> Result<bool, DataError>;
+    fn ticker_exists(&mut self, name: &str) -> Result<bool, DataError>;
+    fn has_quotes(&mut self, ticker: &str) -> Result<bool, DataError>;
+    /// Tickers of the asset, ordered by name
+    fn asset_tickers(&mut self, asset: &str) -> Result<Vec<Ticker>, DataError>;
+    /// Readable key of any transaction referring to the asset
+    fn asset_transaction(&mut self, asset: &str) -> Result<Option<String>, DataError>;
+    /// Whether the record written or deleted by the operation exists
+    fn record_exists(&mut self, operation: &Operation) -> Result<bool, DataError>;
+    /// Highest sequence id of the ticker's quotes at the given time, if any
+    fn max_quote_seq(&mut self, ticker: &str, time: &DateTime<Utc>) -> Result<Option<i64>, DataError>;
+
+    /// Apply the operation without checking any references
+    fn apply(&mut self, operation: &Operation) -> Result<(), DataError>;
+    /// Delete the ticker together with all its quotes
+    fn delete_ticker_quotes(&mut self, ticker: &str) -> Result<(), DataError>;
+}
+
+/// Apply all operations of the batch in order, cascading deletes according to the
+/// policy, and check the references of the records written or deleted afterwards
+///
+/// Inserts and updates are checked against the records as changed by the preceding
+/// operations. Quotes inserted without id are assigned the next sequence id of their
+/// ticker and time. Returns the sequence ids of all inserted quotes, in order.
+///
+/// The changes of the records are collected, if a list to collect them in is given.
+/// The store is left with a partially applied batch on error, which the caller must
+/// discard.
+pub(crate) fn apply_batch<S: BatchStore>(
+    store: &mut S,
+    batch: &Batch,
+    policy: IntegrityPolicy,
+    mut changes: Option<&mut Vec<Change>>,
+) -> Result<Vec<i64>, DataError> {
+    let mut checks = PendingChecks::default();
+    let mut quote_ids = Vec::new();
+
+    for operation in batch.operations() {
+        let assigned;
+        let operation = match operation {
+            Operation::InsertQuote(quote) => {
+                let id = match quote.id {
+                    Some(id) => id,
+                    None => next_quote_seq(store, quote)?,
+                };
+                quote_ids.push(id);
+                assigned = Operation::InsertQuote(Quote { id: Some(id), ..quote.clone() });
+                &assigned
+            }
+            _ => operation,
+        };
+
+        let mut exists = None;
+        if operation.is_checked() || changes.is_some() {
+            let found = store.record_exists(operation)?;
+            operation.check_existing(found)?;
+            exists = Some(found);
+        }
+
+        // tickers deleted together with their quotes, the ticker of the operation itself
+        // being reported as its change
+        let cascaded = match (policy, operation) {
+            (IntegrityPolicy::Cascade, Operation::DeleteAsset(asset)) => store
+                .asset_tickers(&asset.name)?
+                .into_iter()
+                .map(|ticker| (ticker.name.clone(), Some(ticker)))
+                .collect(),
+            (IntegrityPolicy::Cascade, Operation::DeleteTicker(ticker)) => vec![(ticker.name.clone(), None)],
+            _ => Vec::new(),
+        };
+
+        store.apply(operation)?;
+        if let (Some(changes), Some(exists)) = (changes.as_deref_mut(), exists) {
+            changes.extend(operation_change(operation, exists));
+        }
+        for (name, ticker) in cascaded {
+            if let Some(changes) = changes.as_deref_mut() {
+                changes.extend(ticker.map(|ticker| Change::Ticker(ChangeKind::Deleted, ticker)));
+                if store.has_quotes(&name)? {
+                    changes.push(Change::QuotesDeleted { ticker: name.clone(), from: None, to: None });
+                }
+            }
+            store.delete_ticker_quotes(&name)?;
+            checks.forget_ticker(&name);
+        }
+        checks.record(operation);
+    }
+
+    checks.verify(store)?;
+    Ok(quote_ids)
+}
+
+/// Change of the record written or deleted by the operation, given whether the record
+/// existed before, if there is any change at all
+fn operation_change(operation: &Operation, exists: bool) -> Option<Change> {
+    let kind = match operation {
+        Operation::DeleteAsset(_)
+        | Operation::DeleteTicker(_)
+        | Operation::DeleteQuote(_)
+        | Operation::DeleteTransaction { .. } if !exists => return None,
+        Operation::DeleteAsset(_)
+        | Operation::DeleteTicker(_)
+        | Operation::DeleteQuote(_)
+        | Operation::DeleteTransaction { .. } => ChangeKind::Deleted,
+        _ if exists => ChangeKind::Updated,
+        _ => ChangeKind::Inserted,
+    };
+
+    Some(match operation {
+        Operation::InsertAsset(asset)
+        | Operation::UpdateAsset(asset)
+        | Operation::UpsertAsset(asset)
+        | Operation::DeleteAsset(asset) => Change::Asset(kind, asset.clone()),
+        Operation::InsertTicker(ticker)
+        | Operation::UpdateTicker(ticker)
+        | Operation::UpsertTicker(ticker)
+        | Operation::DeleteTicker(ticker) => Change::Ticker(kind, ticker.clone()),
+        Operation::InsertQuote(quote)
+        | Operation::UpdateQuote(quote)
+        | Operation::UpsertQuote(quote)
+        | Operation::DeleteQuote(quote) => Change::Quote(kind, quote.clone()),
+        Operation::InsertTransaction { sort_prefix, transaction }
+        | Operation::UpdateTransaction { sort_prefix, transaction }
+        | Operation::UpsertTransaction { sort_prefix, transaction }
+        | Operation::DeleteTransaction { sort_prefix, transaction } => Change::Transaction {
+            kind,
+            sort_prefix: sort_prefix.clone(),
+            transaction: transaction.clone(),
+        },
+    })
+}
+
+fn next_quote_seq<S: BatchStore>(store: &mut S, quote: &Quote) -> Result<i64, DataError> {
+    match store.max_quote_seq(&quote.ticker, &quote.time)? {
+        Some(seq) => seq.checked_add(1).ok_or_else(|| {
+            let key = quote_key_string(&quote.ticker, &quote.time, seq);
+            DataError::conflict(DataType::Quote, key, "no sequence id left at this time")
+        }),
+        None => Ok(0),
+    }
+}
+
+/// References to check after applying a batch
+#[derive(Debug, Default)]
+struct PendingChecks {
+    /// Assets of the tickers written, by ticker name
+    tickers: BTreeMap<String, String>,
+    /// Ticker, time and sequence id of the quotes written
+    quotes: BTreeSet<(String, DateTime<Utc>, i64)>,
+    /// Assets of the transactions written, by sort prefix and id
+    transactions: BTreeMap<(String, u128), String>,
+    deleted_assets: BTreeSet<String>,
+    deleted_tickers: BTreeSet<String>,
+}
+
+impl PendingChecks {
+    fn record(&mut self, operation: &Operation) {
+        match operation {
+            Operation::InsertAsset(_) | Operation::UpdateAsset(_) | Operation::UpsertAsset(_) => {}
+            Operation::DeleteAsset(asset) => {
+                self.deleted_assets.insert(asset.name.clone());
+            }
+            Operation::InsertTicker(ticker) | Operation::UpdateTicker(ticker) | Operation::UpsertTicker(ticker) => {
+                self.tickers.insert(ticker.name.clone(), ticker.asset.clone());
+            }
+            Operation::DeleteTicker(ticker) => {
+                self.tickers.remove(&ticker.name);
+                self.deleted_tickers.insert(ticker.name.clone());
+            }
+            Operation::InsertQuote(quote) | Operation::UpdateQuote(quote) | Operation::UpsertQuote(quote) => {
+                self.quotes.insert((quote.ticker.clone(), quote.time, quote.id.unwrap_or(0)));
+            }
+            Operation::DeleteQuote(quote) => {
+                self.quotes.remove(&(quote.ticker.clone(), quote.time, quote.id.unwrap_or(0)));
+            }
+            Operation::InsertTransaction { sort_prefix, transaction }
+            | Operation::UpdateTransaction { sort_prefix, transaction }
+            | Operation::UpsertTransaction { sort_prefix, transaction } => {
+                let key = (sort_prefix.clone(), transaction.id);
+                match transaction.asset_name() {
+                    Some(asset) => self.transactions.insert(key, asset.to_string()),
+                    None => self.transactions.remove(&key),
+                };
+            }
+            Operation::DeleteTransaction { sort_prefix, transaction } => {
+                self.transactions.remove(&(sort_prefix.clone(), transaction.id));
+            }
+        }
+    }
+
+    /// Drop the checks of a ticker deleted together with its quotes
+    fn forget_ticker(&mut self, name: &str) {
+        self.tickers.remove(name);
+        self.quotes.retain(|(ticker, _, _)| ticker != name);
+    }
+
+    fn verify<S: BatchStore>(&self, store: &mut S) -> Result<(), DataError> {
+        for (name, asset) in &self.tickers {
+            if !store.asset_exists(asset)? {
+                return Err(missing(DataType::Ticker, name, DataType::Asset, asset));
+            }
+        }
+        for (ticker, time, seq) in &self.quotes {
+            if !store.ticker_exists(ticker)? {
+                let key = quote_key_string(ticker, time, *seq);
+                return Err(missing(DataType::Quote, key, DataType::Ticker, ticker));
+            }
+        }
+        for ((sort_prefix, id), asset) in &self.transactions {
+            if !store.asset_exists(asset)? {
+                let key = transaction_key_string(sort_prefix, *id);
+                return Err(missing(DataType::Transaction, key, DataType::Asset, asset));
+            }
+        }
+
+        for name in &self.deleted_assets {
+            if store.asset_exists(name)? {
+                continue;
+            }
+            if let Some(ticker) = store.asset_tickers(name)?.first() {
+                return Err(referenced(DataType::Asset, name, DataType::Ticker, &ticker.name));
+            }
+            if let Some(transaction) = store.asset_transaction(name)? {
+                return Err(referenced(DataType::Asset, name, DataType::Transaction, &transaction));
+            }
+        }
+        for name in &self.deleted_tickers {
+            if !store.ticker_exists(name)? && store.has_quotes(name)? {
+                return Err(DataError::validation(DataType::Ticker, name, "ticker still has quotes"));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn missing<K: ToString>(data_type: DataType, key: K, target_type: DataType, target: &str) -> DataError {
+    DataError::validation(data_type, key, format!("{} '{}' does not exist", target_type, target))
+}
+
+fn referenced(data_type: DataType, key: &str, source_type: DataType, source: &str) -> DataError {
+    DataError::validation(data_type, key, format!("still referred to by {} '{}'", source_type, source))
+}
@@ -0,0 +1,99 @@
+//! Pagination and filters of listings of assets, tickers and sort prefixes
+use crate::fiat::Currency;
+use crate::quote::Ticker;
+
+/// Page of a listing ordered by name
+///
+/// Listings are paged by the last name seen instead of an offset, such that writes
+/// between two pages neither skip nor repeat items. The next page starts after the last
+/// item of the current page and a page with fewer items than the limit is the last one:
+///
+/// ```ignore
+/// let mut page = Page::first(100);
+/// loop {
+///     let assets = db.list_assets(&page)?;
+///     render(&assets);
+///     match assets.last() {
+///         Some(last) if assets.len() == page.limit => page = Page::after(&last.name, 100),
+///         _ => break,
+///     }
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct Page {
+    /// Only list items with names greater than this name
+    pub after: Option<String>,
+    /// Maximum number of items of the page
+    pub limit: usize,
+}
+
+impl Page {
+    pub fn first(limit: usize) -> Page {
+        Page { after: None, limit }
+    }
+
+    pub fn after(name: &str, limit: usize) -> Page {
+        Page {
+            after: Some(name.to_string()),
+            limit,
+        }
+    }
+
+    /// Whether the name belongs to a page of a listing, not counting the limit
+    pub fn follows(&self, name: &str) -> bool {
+        match &self.after {
+            Some(after) => name > after.as_str(),
+            None => true,
+        }
+    }
+
+    /// Select the page of items, which must be ordered by name
+    pub fn select<T, I, F>(&self, items: I, name: F) -> Vec<T>
+    where
+        I: IntoIterator<Item = T>,
+        F: Fn(&T) -> &str,
+    {
+        items
+            .into_iter()
+            .filter(|item| self.follows(name(item)))
+            .take(self.limit)
+            .collect()
+    }
+}
+
+/// Filter of ticker listings, which lists all tickers by default
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TickerFilter {
+    /// Only list the tickers of the asset with this name
+    pub asset: Option<String>,
+    /// Only list the tickers quoted in this currency
+    pub currency: Option<Currency>,
+}
+
+impl TickerFilter {
+    pub fn all() -> TickerFilter {
+        TickerFilter::default()
+    }
+
+    pub fn asset(mut self, asset: &str) -> TickerFilter {
+        self.asset = Some(asset.to_string());
+        self
+    }
+
+    pub fn currency(mut self, currency: Currency) -> TickerFilter {
+        self.currency = Some(currency);
+        self
+    }
+
+    pub fn matches(&self, ticker: &Ticker) -> bool {
+        let asset_matches = match &self.asset {
+            Some(asset) => *asset == ticker.asset,
+            None => true,
+        };
+        let currency_matches = match self.currency {
+            Some(currency) => currency == ticker.currency,
+            None => true,
+        };
+        asset_matches && currency_matches
+    }
+}
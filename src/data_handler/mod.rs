@@ -1,15 +1,35 @@
 ///! Implementation of a data handler trait to deal with global data
+use std::error::Error;
+use std::fmt;
 
+use chrono::{DateTime, Utc};
 
 pub mod asset_handler;
+pub mod batch_handler;
+pub mod bulk;
+pub mod changes;
+pub mod conformance;
+pub mod dump;
+pub mod integrity;
+pub mod listing;
 pub mod quote_handler;
+pub mod retention;
+pub mod snapshot;
 pub mod transaction_handler;
 
 pub use asset_handler::AssetHandler;
+pub use batch_handler::{Batch, BatchHandler, Operation};
+pub use bulk::{BulkReport, OnDuplicate, QuoteWriter};
+pub use changes::{Change, ChangeFilter, ChangeKind, SubscriptionHandler};
+pub use dump::{export_jsonl, import_jsonl, DumpReport};
+pub use integrity::{IntegrityHandler, IntegrityPolicy, IntegrityReport};
+pub use listing::{Page, TickerFilter};
 pub use quote_handler::QuoteHandler;
+pub use retention::{RetentionPolicy, RetentionReport, RetentionRules};
+pub use snapshot::{Snapshot, SnapshotHandler};
 pub use transaction_handler::TransactionHandler;
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum DataType {
     Asset,
     Quote,
@@ -17,19 +37,161 @@ pub enum DataType {
     Transaction,
 }
 
+impl fmt::Display for DataType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            DataType::Asset => "asset",
+            DataType::Quote => "quote",
+            DataType::Ticker => "ticker",
+            DataType::Transaction => "transaction",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Underlying cause of a `DataError`
+pub type BoxError = Box<dyn Error + Send + Sync>;
+
+/// Error of data handlers, carrying the record involved and the underlying cause
+///
+/// Records are identified by their data type and a readable key, e.g. the asset name,
+/// `AAPL@2020-09-01T12:00:00+00:00#0` for quotes or `book/42` for transactions.
 #[derive(Debug)]
 pub enum DataError {
-    DataAccessFailure,
-    NotFound,
-    UpdateFailed,
-    DeleteFailed,
-    InsertFailed,
-    InvalidTransaction,
+    /// There is no record with the given key
+    NotFound { data_type: DataType, key: String },
+    /// The storage backend failed to read or write data
+    Storage { context: String, source: BoxError },
+    /// A record could not be serialized or deserialized
+    Serialization { data_type: DataType, key: String, source: BoxError },
+    /// A raw value could not be parsed
+    Parse { value: String, source: BoxError },
+    /// A record is invalid, e.g. a transaction referring to a missing transaction
+    Validation { data_type: DataType, key: String, reason: String },
+    /// A write conflicts with the stored data, e.g. inserting a record that exists already
+    Conflict { data_type: DataType, key: String, reason: String },
+    /// The database has a schema version this version can't read, either because it was
+    /// written by a newer version or because it has not been migrated yet
+    IncompatibleSchema { found: u32, supported: u32 },
+}
+
+impl DataError {
+    pub fn not_found<K: ToString>(data_type: DataType, key: K) -> DataError {
+        DataError::NotFound {
+            data_type,
+            key: key.to_string(),
+        }
+    }
+
+    pub fn storage<C: Into<String>, E: Into<BoxError>>(context: C, source: E) -> DataError {
+        DataError::Storage {
+            context: context.into(),
+            source: source.into(),
+        }
+    }
+
+    pub fn serialization<K: ToString, E: Into<BoxError>>(data_type: DataType, key: K, source: E) -> DataError {
+        DataError::Serialization {
+            data_type,
+            key: key.to_string(),
+            source: source.into(),
+        }
+    }
+
+    pub fn parse<V: ToString, E: Into<BoxError>>(value: V, source: E) -> DataError {
+        DataError::Parse {
+            value: value.to_string(),
+            source: source.into(),
+        }
+    }
+
+    pub fn validation<K: ToString, R: Into<String>>(data_type: DataType, key: K, reason: R) -> DataError {
+        DataError::Validation {
+            data_type,
+            key: key.to_string(),
+            reason: reason.into(),
+        }
+    }
+
+    pub fn conflict<K: ToString, R: Into<String>>(data_type: DataType, key: K, reason: R) -> DataError {
+        DataError::Conflict {
+            data_type,
+            key: key.to_string(),
+            reason: reason.into(),
+        }
+    }
+}
+
+impl fmt::Display for DataError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DataError::NotFound { data_type, key } =>
+                write!(f, "{} '{}' not found", data_type, key),
+            DataError::Storage { context, .. } =>
+                write!(f, "storage failure: {}", context),
+            DataError::Serialization { data_type, key, .. } =>
+                write!(f, "failed to serialize or deserialize {} '{}'", data_type, key),
+            DataError::Parse { value, .. } =>
+                write!(f, "failed to parse '{}'", value),
+            DataError::Validation { data_type, key, reason } =>
+                write!(f, "invalid {} '{}': {}", data_type, key, reason),
+            DataError::Conflict { data_type, key, reason } =>
+                write!(f, "conflicting write of {} '{}': {}", data_type, key, reason),
+            DataError::IncompatibleSchema { found, supported } =>
+                write!(f, "database has schema version {}, but version {} is required", found, supported),
+        }
+    }
 }
 
+impl Error for DataError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            DataError::Storage { source, .. }
+            | DataError::Serialization { source, .. }
+            | DataError::Parse { source, .. } => Some(source.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+/// Readable key of a quote, as used in errors
+pub fn quote_key_string(ticker: &str, time: &DateTime<Utc>, seq: i64) -> String {
+    format!("{}@{}#{}", ticker, time.to_rfc3339(), seq)
+}
+
+/// Readable key of a transaction, as used in errors
+pub fn transaction_key_string(sort_prefix: &str, id: u128) -> String {
+    format!("{}/{}", sort_prefix, id)
+}
+
+/// Boxed iterator over stored items, yielding an error for items that can't be read
+pub type Cursor<'a, T> = Box<dyn Iterator<Item = Result<T, DataError>> + 'a>;
+
 pub trait DataItem {
     // get id or return error if id hasn't been set yet
     fn get_id(&self) -> Result<usize, DataError>;
     // set id or return error if id has already been set
     fn set_id(&mut self, id: usize) -> Result<(), DataError>;
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::helpers::raw_to_cash_flow;
+
+    #[test]
+    fn test_error_chain() {
+        let err = raw_to_cash_flow(1.0, "EUR", "2020-13-01").unwrap_err();
+        assert!(matches!(&err, DataError::Parse { value, .. } if value == "2020-13-01"));
+        assert_eq!(err.to_string(), "failed to parse '2020-13-01'");
+        assert!(err.source().is_some());
+
+        let err = DataError::storage("write asset 'Apple'", "disk full");
+        assert_eq!(err.to_string(), "storage failure: write asset 'Apple'");
+        assert_eq!(err.source().unwrap().to_string(), "disk full");
+
+        let err = DataError::not_found(DataType::Transaction, transaction_key_string("book", 42));
+        assert_eq!(err.to_string(), "transaction 'book/42' not found");
+        assert!(err.source().is_none());
+    }
+}
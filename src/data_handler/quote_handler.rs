@@ -1,24 +1,56 @@
 use super::AssetHandler;
 ///! Data handler trait for market quotes
-use super::DataError;
+use super::{BulkReport, Cursor, DataError, OnDuplicate, Page, TickerFilter};
 use crate::quote::{Quote, Ticker};
 use chrono::{DateTime, Utc};
 
 /// Handler for globally available market quotes data
+///
+/// Quotes of a ticker are ordered by time and, for quotes with the same time, by their
+/// sequence id, which tells apart quotes of the same ticker and time. Quotes inserted
+/// without id are assigned the next sequence id of their ticker and time, starting at 0,
+/// hence quotes with identical ticker and time are all kept rather than deduplicated.
+/// A quote is replaced by updating or upserting it with its id. Quotes stored without
+/// id by earlier versions are treated like quotes with id 0.
 pub trait QuoteHandler: AssetHandler {
-    fn get_ticker_by_name(&mut self, name: &str) -> Result<Ticker, DataError>;
-    fn get_latest_quote(&mut self, ticker_name: &str) -> Option<Quote>;
-    fn get_oldest_quote(&mut self, ticker_name: &str) -> Option<Quote>;
+    fn get_ticker_by_name(&self, name: &str) -> Result<Ticker, DataError>;
+    /// List the page of tickers matching the filter, ordered by name
+    fn list_tickers(&self, filter: &TickerFilter, page: &Page) -> Result<Vec<Ticker>, DataError>;
+    /// Get the ticker's quote with the given time and sequence id
+    fn get_quote_by_id(&self, ticker_name: &str, time: DateTime<Utc>, id: i64) -> Result<Quote, DataError>;
+    /// Get the ticker's quote with the most recent time, or `None` if it has no quotes
+    fn get_latest_quote(&self, ticker_name: &str) -> Result<Option<Quote>, DataError>;
+    /// Get the ticker's quote with the earliest time, or `None` if it has no quotes
+    fn get_oldest_quote(&self, ticker_name: &str) -> Result<Option<Quote>, DataError>;
 
     fn insert_ticker(&mut self, ticker: &Ticker) -> Result<(), DataError>;
     fn update_ticker(&mut self, ticker: &Ticker) -> Result<(), DataError>;
+    fn upsert_ticker(&mut self, ticker: &Ticker) -> Result<(), DataError>;
     fn delete_ticker(&mut self, ticker: &Ticker) -> Result<(), DataError>;
 
-    fn insert_quote(&mut self, quote: &Quote) -> Result<(), DataError>;
-
+    /// Insert the quote and return its sequence id, which is assigned if the quote has
+    /// none
+    fn insert_quote(&mut self, quote: &Quote) -> Result<i64, DataError>;
     fn update_quote(&mut self, quote: &Quote) -> Result<(), DataError>;
+    fn upsert_quote(&mut self, quote: &Quote) -> Result<(), DataError>;
     fn delete_quote(&mut self, quote: &Quote) -> Result<(), DataError>;
+    /// Delete the ticker's quotes in `[from, to)`, e.g. all quotes of a delisted ticker
+    fn delete_quotes(&mut self, ticker_name: &str, from: DateTime<Utc>, to: DateTime<Utc>) -> Result<(), DataError>;
+    /// Insert the quotes at once and count the quotes inserted, replaced and skipped
+    ///
+    /// Quotes without id are matched with the stored quote of id 0 rather than assigned
+    /// the next free id, such that loading the same quotes twice does not store them
+    /// twice. The quotes are written atomically, a `QuoteWriter` writes large numbers of
+    /// quotes in chunks.
+    fn insert_quotes(&mut self, quotes: &[Quote], on_duplicate: OnDuplicate) -> Result<BulkReport, DataError>;
+
+    /// Iterate over the ticker's quotes at or after `time` in ascending time order
+    fn quote_cursor_forward(&self, ticker: &Ticker, time: DateTime<Utc>) -> Cursor<'_, Quote>;
+    /// Iterate over the ticker's quotes at or before `time` in descending time order
+    fn quote_cursor_reverse(&self, ticker: &Ticker, time: DateTime<Utc>) -> Cursor<'_, Quote>;
 
-    fn quote_cursor_forward(&mut self, ticker: &Ticker, time: DateTime<Utc>) -> Box<dyn Iterator<Item=Quote> + '_>;
-    fn quote_cursor_reverse(&mut self, ticker: &Ticker, time: DateTime<Utc>) -> Box<dyn Iterator<Item=Quote> + '_>;
+    /// Iterate over the ticker's quotes in `[from, to)` in ascending time order
+    fn quote_range_forward(&self, ticker: &Ticker, from: DateTime<Utc>, to: DateTime<Utc>) -> Cursor<'_, Quote>;
+    /// Iterate over the ticker's quotes in `[from, to)` in descending time order
+    fn quote_range_reverse(&self, ticker: &Ticker, from: DateTime<Utc>, to: DateTime<Utc>) -> Cursor<'_, Quote>;
 }
@@ -0,0 +1,252 @@
+//! Retention of quotes
+//!
+//! Long quote histories are rarely needed at full resolution. Retention policies thin out
+//! the quotes of a ticker once they reach a given age, keeping only the last quote of
+//! each interval, or delete them altogether. E.g. keeping ticks for 30 days, minute bars
+//! for 2 years and daily closes forever:
+//!
+//! ```ignore
+//! let intraday = RetentionPolicy::new()
+//!     .thin_after(Duration::days(30), Duration::minutes(1))
+//!     .thin_after(Duration::days(730), Duration::days(1));
+//! let rules = RetentionRules::new().default_policy(intraday);
+//! let report = db.apply_retention(&rules, Utc::now())?;
+//! ```
+//!
+//! Policies are not stored, they are applied by explicit maintenance calls only.
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, Duration, Utc};
+
+use super::bulk::DEFAULT_CHUNK_SIZE;
+use super::{Batch, BatchHandler, DataError, Page, TickerFilter};
+use crate::quote::{Quote, Ticker};
+
+/// Number of tickers listed at once while applying retention rules to all tickers
+const TICKER_PAGE_SIZE: usize = 100;
+
+/// Quotes kept by a retention rule
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Keep {
+    /// The last quote of each interval, e.g. the daily close for an interval of one day
+    ///
+    /// Intervals are whole seconds aligned to the Unix epoch, i.e. days start at
+    /// midnight UTC.
+    LastPer(Duration),
+    /// No quotes at all
+    Nothing,
+}
+
+/// Rule applying to the quotes older than its age
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct RetentionRule {
+    pub age: Duration,
+    pub keep: Keep,
+}
+
+/// Retention rules of a ticker, ordered by age
+///
+/// Each quote is subject to the rule with the greatest age the quote exceeds, quotes
+/// younger than all rules are kept.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RetentionPolicy {
+    rules: Vec<RetentionRule>,
+}
+
+impl RetentionPolicy {
+    pub fn new() -> RetentionPolicy {
+        RetentionPolicy::default()
+    }
+
+    /// Keep only the last quote of each interval of the quotes older than `age`
+    pub fn thin_after(self, age: Duration, interval: Duration) -> RetentionPolicy {
+        assert!(interval >= Duration::seconds(1), "intervals must be at least one second");
+        self.rule(RetentionRule { age, keep: Keep::LastPer(interval) })
+    }
+
+    /// Delete the quotes older than `age`
+    pub fn delete_after(self, age: Duration) -> RetentionPolicy {
+        self.rule(RetentionRule { age, keep: Keep::Nothing })
+    }
+
+    /// Add the rule, replacing any rule of the same age
+    pub fn rule(mut self, rule: RetentionRule) -> RetentionPolicy {
+        assert!(rule.age >= Duration::zero(), "ages must not be negative");
+        match self.rules.binary_search_by_key(&rule.age, |rule| rule.age) {
+            Ok(index) => self.rules[index] = rule,
+            Err(index) => self.rules.insert(index, rule),
+        }
+        self
+    }
+
+    pub fn rules(&self) -> &[RetentionRule] {
+        &self.rules
+    }
+}
+
+/// Retention policies by ticker name, with an optional default policy for all other
+/// tickers
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RetentionRules {
+    default: Option<RetentionPolicy>,
+    tickers: BTreeMap<String, RetentionPolicy>,
+}
+
+impl RetentionRules {
+    pub fn new() -> RetentionRules {
+        RetentionRules::default()
+    }
+
+    /// Apply the policy to all tickers without a policy of their own
+    pub fn default_policy(mut self, policy: RetentionPolicy) -> RetentionRules {
+        self.default = Some(policy);
+        self
+    }
+
+    /// Apply the policy to the ticker with the given name
+    pub fn ticker(mut self, name: &str, policy: RetentionPolicy) -> RetentionRules {
+        self.tickers.insert(name.to_string(), policy);
+        self
+    }
+
+    /// Policy applying to the ticker with the given name, if any
+    pub fn policy(&self, ticker: &str) -> Option<&RetentionPolicy> {
+        self.tickers.get(ticker).or(self.default.as_ref())
+    }
+}
+
+/// Outcome of applying retention rules
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct RetentionReport {
+    /// Number of tickers a policy has been applied to
+    pub tickers: usize,
+    /// Number of quotes deleted
+    pub deleted: usize,
+}
+
+/// Apply the retention rules to the quotes of the tickers as of `now`
+///
+/// Quotes are deleted in chunks, hence a failure leaves the quotes deleted so far
+/// deleted. Applying the same rules again completes the maintenance.
+pub(crate) fn apply_retention<H: BatchHandler + ?Sized>(
+    db: &mut H,
+    rules: &RetentionRules,
+    now: DateTime<Utc>,
+) -> Result<RetentionReport, DataError> {
+    let mut report = RetentionReport::default();
+
+    for ticker in tickers(db, rules)? {
+        if let Some(policy) = rules.policy(&ticker.name) {
+            report.deleted += apply_policy(db, &ticker, policy, now)?;
+            report.tickers += 1;
+        }
+    }
+
+    Ok(report)
+}
+
+/// Tickers the rules may apply to, i.e. all tickers if there is a default policy
+fn tickers<H: BatchHandler + ?Sized>(db: &mut H, rules: &RetentionRules) -> Result<Vec<Ticker>, DataError> {
+    let mut tickers = Vec::new();
+
+    if rules.default.is_none() {
+        for name in rules.tickers.keys() {
+            match db.get_ticker_by_name(name) {
+                Ok(ticker) => tickers.push(ticker),
+                Err(DataError::NotFound { .. }) => {}
+                Err(err) => return Err(err),
+            }
+        }
+        return Ok(tickers);
+    }
+
+    let mut page = Page::first(TICKER_PAGE_SIZE);
+    loop {
+        let listed = db.list_tickers(&TickerFilter::all(), &page)?;
+        let next = match listed.last() {
+            Some(last) if listed.len() == page.limit => Some(Page::after(&last.name, TICKER_PAGE_SIZE)),
+            _ => None,
+        };
+        tickers.extend(listed);
+        match next {
+            Some(next) => page = next,
+            None => return Ok(tickers),
+        }
+    }
+}
+
+fn apply_policy<H: BatchHandler + ?Sized>(
+    db: &mut H,
+    ticker: &Ticker,
+    policy: &RetentionPolicy,
+    now: DateTime<Utc>,
+) -> Result<usize, DataError> {
+    let oldest = match db.get_oldest_quote(&ticker.name)? {
+        Some(quote) => quote.time,
+        None => return Ok(0),
+    };
+    let mut deleted = 0;
+
+    // each rule covers the quotes older than its age, but not older than the age of
+    // the next rule
+    let rules = policy.rules();
+    for (index, rule) in rules.iter().enumerate() {
+        let to = match now.checked_sub_signed(rule.age) {
+            Some(to) => to,
+            None => continue,
+        };
+        let from = match rules.get(index + 1).and_then(|next| now.checked_sub_signed(next.age)) {
+            Some(from) => from.max(oldest),
+            None => oldest,
+        };
+        if from >= to {
+            continue;
+        }
+
+        deleted += match rule.keep {
+            Keep::Nothing => {
+                let count = db.quote_range_forward(ticker, from, to).count();
+                db.delete_quotes(&ticker.name, from, to)?;
+                count
+            }
+            Keep::LastPer(interval) => {
+                let stale = superseded_quotes(db, ticker, from, to, interval)?;
+                for chunk in stale.chunks(DEFAULT_CHUNK_SIZE) {
+                    let mut batch = Batch::new();
+                    for quote in chunk {
+                        batch.delete_quote(quote);
+                    }
+                    db.write_batch(&batch)?;
+                }
+                stale.len()
+            }
+        };
+    }
+
+    Ok(deleted)
+}
+
+/// Quotes in `[from, to)` followed by a later quote of the same interval
+fn superseded_quotes<H: BatchHandler + ?Sized>(
+    db: &mut H,
+    ticker: &Ticker,
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+    interval: Duration,
+) -> Result<Vec<Quote>, DataError> {
+    let seconds = interval.num_seconds().max(1);
+    let mut stale = Vec::new();
+    let mut last: Option<(i64, Quote)> = None;
+
+    for quote in db.quote_range_forward(ticker, from, to) {
+        let quote = quote?;
+        let slot = quote.time.timestamp().div_euclid(seconds);
+        if let Some((last_slot, last_quote)) = last.replace((slot, quote)) {
+            if last_slot == slot {
+                stale.push(last_quote);
+            }
+        }
+    }
+
+    Ok(stale)
+}
@@ -0,0 +1,145 @@
+//! Consistent reads of a single point in time
+//!
+//! Reads walking many records, e.g. a report over the quotes of several tickers, see a
+//! mix of old and new records if another handler writes in between. All reads through a
+//! snapshot see the records as of the moment the snapshot was taken instead:
+//!
+//! ```ignore
+//! let snapshot = db.snapshot()?;
+//! for ticker in snapshot.list_tickers(&TickerFilter::all(), &Page::first(100))? {
+//!     for quote in snapshot.quote_cursor_forward(&ticker, start) {
+//!         report.add(quote?);
+//!     }
+//! }
+//! ```
+use chrono::{DateTime, Utc};
+
+use super::{Cursor, DataError, Page, TickerFilter};
+use crate::asset::Asset;
+use crate::quote::{Quote, Ticker};
+use crate::transaction::Transaction;
+
+/// Read-only view of all records as of the moment it was taken
+///
+/// The methods behave like those of the same name of `AssetHandler`, `QuoteHandler` and
+/// `TransactionHandler`.
+pub trait Snapshot {
+    fn get_asset_by_name(&self, name: &str) -> Result<Asset, DataError>;
+    fn get_asset_by_isin(&self, isin: &str) -> Result<Asset, DataError>;
+    fn get_asset_by_wkn(&self, wkn: &str) -> Result<Asset, DataError>;
+    fn list_assets(&self, page: &Page) -> Result<Vec<Asset>, DataError>;
+
+    fn get_ticker_by_name(&self, name: &str) -> Result<Ticker, DataError>;
+    fn list_tickers(&self, filter: &TickerFilter, page: &Page) -> Result<Vec<Ticker>, DataError>;
+    fn get_quote_by_id(&self, ticker_name: &str, time: DateTime<Utc>, id: i64) -> Result<Quote, DataError>;
+    fn get_latest_quote(&self, ticker_name: &str) -> Result<Option<Quote>, DataError>;
+    fn get_oldest_quote(&self, ticker_name: &str) -> Result<Option<Quote>, DataError>;
+    fn quote_cursor_forward(&self, ticker: &Ticker, time: DateTime<Utc>) -> Cursor<'_, Quote>;
+    fn quote_cursor_reverse(&self, ticker: &Ticker, time: DateTime<Utc>) -> Cursor<'_, Quote>;
+    fn quote_range_forward(&self, ticker: &Ticker, from: DateTime<Utc>, to: DateTime<Utc>) -> Cursor<'_, Quote>;
+    fn quote_range_reverse(&self, ticker: &Ticker, from: DateTime<Utc>, to: DateTime<Utc>) -> Cursor<'_, Quote>;
+
+    fn get_transaction_by_id(&self, sort_prefix: &str, id: u128) -> Result<Transaction, DataError>;
+    fn list_sort_prefixes(&self, page: &Page) -> Result<Vec<String>, DataError>;
+    fn get_latest_transaction(&self, sort_prefix: &str) -> Result<Option<Transaction>, DataError>;
+    fn get_oldest_transaction(&self, sort_prefix: &str) -> Result<Option<Transaction>, DataError>;
+    fn transaction_cursor_forward(&self, sort_prefix: &str, time: DateTime<Utc>) -> Cursor<'_, Transaction>;
+    fn transaction_cursor_reverse(&self, sort_prefix: &str, time: DateTime<Utc>) -> Cursor<'_, Transaction>;
+    fn transaction_range_forward(&self, sort_prefix: &str, from: DateTime<Utc>, to: DateTime<Utc>) -> Cursor<'_, Transaction>;
+    fn transaction_range_reverse(&self, sort_prefix: &str, from: DateTime<Utc>, to: DateTime<Utc>) -> Cursor<'_, Transaction>;
+}
+
+/// Handler able to take snapshots of its records
+pub trait SnapshotHandler {
+    /// Take a snapshot of all records, which later writes of any handler of the same
+    /// database don't change
+    ///
+    /// The snapshot borrows the handler, hence writes must go through another handler
+    /// while it is alive.
+    fn snapshot(&mut self) -> Result<Box<dyn Snapshot + '_>, DataError>;
+}
+
+/// Implement `Snapshot` for a type by calling the methods of the same name on `self.$db`,
+/// e.g. a field holding a handler, whose traits must be in scope
+macro_rules! delegate_snapshot {
+    ($snapshot:ty, $($db:tt)+) => {
+        const _: () = {
+            use chrono::{DateTime, Utc};
+            use $crate::asset::Asset;
+            use $crate::data_handler::snapshot::Snapshot;
+            use $crate::data_handler::{Cursor, DataError, Page, TickerFilter};
+            use $crate::quote::{Quote, Ticker};
+            use $crate::transaction::Transaction;
+
+            impl Snapshot for $snapshot {
+                fn get_asset_by_name(&self, name: &str) -> Result<Asset, DataError> {
+                    self.$($db)+.get_asset_by_name(name)
+                }
+                fn get_asset_by_isin(&self, isin: &str) -> Result<Asset, DataError> {
+                    self.$($db)+.get_asset_by_isin(isin)
+                }
+                fn get_asset_by_wkn(&self, wkn: &str) -> Result<Asset, DataError> {
+                    self.$($db)+.get_asset_by_wkn(wkn)
+                }
+                fn list_assets(&self, page: &Page) -> Result<Vec<Asset>, DataError> {
+                    self.$($db)+.list_assets(page)
+                }
+
+                fn get_ticker_by_name(&self, name: &str) -> Result<Ticker, DataError> {
+                    self.$($db)+.get_ticker_by_name(name)
+                }
+                fn list_tickers(&self, filter: &TickerFilter, page: &Page) -> Result<Vec<Ticker>, DataError> {
+                    self.$($db)+.list_tickers(filter, page)
+                }
+                fn get_quote_by_id(&self, ticker_name: &str, time: DateTime<Utc>, id: i64) -> Result<Quote, DataError> {
+                    self.$($db)+.get_quote_by_id(ticker_name, time, id)
+                }
+                fn get_latest_quote(&self, ticker_name: &str) -> Result<Option<Quote>, DataError> {
+                    self.$($db)+.get_latest_quote(ticker_name)
+                }
+                fn get_oldest_quote(&self, ticker_name: &str) -> Result<Option<Quote>, DataError> {
+                    self.$($db)+.get_oldest_quote(ticker_name)
+                }
+                fn quote_cursor_forward(&self, ticker: &Ticker, time: DateTime<Utc>) -> Cursor<'_, Quote> {
+                    self.$($db)+.quote_cursor_forward(ticker, time)
+                }
+                fn quote_cursor_reverse(&self, ticker: &Ticker, time: DateTime<Utc>) -> Cursor<'_, Quote> {
+                    self.$($db)+.quote_cursor_reverse(ticker, time)
+                }
+                fn quote_range_forward(&self, ticker: &Ticker, from: DateTime<Utc>, to: DateTime<Utc>) -> Cursor<'_, Quote> {
+                    self.$($db)+.quote_range_forward(ticker, from, to)
+                }
+                fn quote_range_reverse(&self, ticker: &Ticker, from: DateTime<Utc>, to: DateTime<Utc>) -> Cursor<'_, Quote> {
+                    self.$($db)+.quote_range_reverse(ticker, from, to)
+                }
+
+                fn get_transaction_by_id(&self, sort_prefix: &str, id: u128) -> Result<Transaction, DataError> {
+                    self.$($db)+.get_transaction_by_id(sort_prefix, id)
+                }
+                fn list_sort_prefixes(&self, page: &Page) -> Result<Vec<String>, DataError> {
+                    self.$($db)+.list_sort_prefixes(page)
+                }
+                fn get_latest_transaction(&self, sort_prefix: &str) -> Result<Option<Transaction>, DataError> {
+                    self.$($db)+.get_latest_transaction(sort_prefix)
+                }
+                fn get_oldest_transaction(&self, sort_prefix: &str) -> Result<Option<Transaction>, DataError> {
+                    self.$($db)+.get_oldest_transaction(sort_prefix)
+                }
+                fn transaction_cursor_forward(&self, sort_prefix: &str, time: DateTime<Utc>) -> Cursor<'_, Transaction> {
+                    self.$($db)+.transaction_cursor_forward(sort_prefix, time)
+                }
+                fn transaction_cursor_reverse(&self, sort_prefix: &str, time: DateTime<Utc>) -> Cursor<'_, Transaction> {
+                    self.$($db)+.transaction_cursor_reverse(sort_prefix, time)
+                }
+                fn transaction_range_forward(&self, sort_prefix: &str, from: DateTime<Utc>, to: DateTime<Utc>) -> Cursor<'_, Transaction> {
+                    self.$($db)+.transaction_range_forward(sort_prefix, from, to)
+                }
+                fn transaction_range_reverse(&self, sort_prefix: &str, from: DateTime<Utc>, to: DateTime<Utc>) -> Cursor<'_, Transaction> {
+                    self.$($db)+.transaction_range_reverse(sort_prefix, from, to)
+                }
+            }
+        };
+    };
+}
+
+pub(crate) use delegate_snapshot;
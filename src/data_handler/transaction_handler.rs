@@ -1,19 +1,50 @@
 use super::AssetHandler;
-use super::DataError;
+use super::{Cursor, DataError, Page};
 use crate::transaction::Transaction;
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, Duration, NaiveDate, Utc};
 
 /// Handler for globally available data of transactions and related data
+///
+/// Transactions are grouped by a sort prefix and ordered by their booking date, i.e. the
+/// date of their cash flow, and by id within the same date. Cursors and ranges consider
+/// transactions to be booked at midnight UTC of their booking date, hence backdated
+/// transactions take their place among those booked earlier.
 pub trait TransactionHandler: AssetHandler {
-    fn get_transaction_by_id(&mut self, sort_prefix: &str, id: u128) -> Result<Transaction, DataError>;
+    fn get_transaction_by_id(&self, sort_prefix: &str, id: u128) -> Result<Transaction, DataError>;
+    /// List the page of sort prefixes with at least one transaction, in ascending order
+    fn list_sort_prefixes(&self, page: &Page) -> Result<Vec<String>, DataError>;
 
-    fn get_latest_transaction(&mut self, sort_prefix: &str) -> Option<Transaction>;
-    fn get_oldest_transaction(&mut self, sort_prefix: &str) -> Option<Transaction>;
+    /// Get the transaction with the latest booking date, or `None` if there are no
+    /// transactions with the given sort prefix
+    fn get_latest_transaction(&self, sort_prefix: &str) -> Result<Option<Transaction>, DataError>;
+    /// Get the transaction with the earliest booking date, or `None` if there are no
+    /// transactions with the given sort prefix
+    fn get_oldest_transaction(&self, sort_prefix: &str) -> Result<Option<Transaction>, DataError>;
 
     fn insert_transaction(&mut self, sort_prefix: &str, transaction: &Transaction) -> Result<(), DataError>;
     fn update_transaction(&mut self, sort_prefix: &str, transaction: &Transaction) -> Result<(), DataError>;
+    fn upsert_transaction(&mut self, sort_prefix: &str, transaction: &Transaction) -> Result<(), DataError>;
     fn delete_transaction(&mut self, sort_prefix: &str, transaction: &Transaction) -> Result<(), DataError>;
 
-    fn transaction_cursor_forward(&mut self, sort_prefix: &str, time: DateTime<Utc>) -> Box<dyn Iterator<Item=Transaction> + '_>;
-    fn transaction_cursor_reverse(&mut self, sort_prefix: &str, time: DateTime<Utc>) -> Box<dyn Iterator<Item=Transaction> + '_>;
+    /// Iterate over the transactions with the given sort prefix at or after `time` in ascending order
+    fn transaction_cursor_forward(&self, sort_prefix: &str, time: DateTime<Utc>) -> Cursor<'_, Transaction>;
+    /// Iterate over the transactions with the given sort prefix at or before `time` in descending order
+    fn transaction_cursor_reverse(&self, sort_prefix: &str, time: DateTime<Utc>) -> Cursor<'_, Transaction>;
+
+    /// Iterate over the transactions with the given sort prefix in `[from, to)` in ascending order
+    fn transaction_range_forward(&self, sort_prefix: &str, from: DateTime<Utc>, to: DateTime<Utc>) -> Cursor<'_, Transaction>;
+    /// Iterate over the transactions with the given sort prefix in `[from, to)` in descending order
+    fn transaction_range_reverse(&self, sort_prefix: &str, from: DateTime<Utc>, to: DateTime<Utc>) -> Cursor<'_, Transaction>;
+}
+
+/// First booking date at or after `time`, or strictly after it if `inclusive` is not set,
+/// since transactions count as booked at midnight UTC of their booking date
+///
+/// Returns `None` if there is no such date.
+pub(crate) fn booking_date_bound(time: DateTime<Utc>, inclusive: bool) -> Option<NaiveDate> {
+    let date = time.date().naive_utc();
+    if inclusive && time == DateTime::<Utc>::from_utc(date.and_hms(0, 0, 0), Utc) {
+        return Some(date);
+    }
+    date.checked_add_signed(Duration::days(1))
 }
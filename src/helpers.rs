@@ -46,16 +46,16 @@ pub fn i32_to_usize(val: Option<i32>) -> Option<usize> {
 }
 /// Construct cash flow from raw strings
 pub fn raw_to_cash_flow(amount: f64, currency: &str, date: &str) -> Result<CashFlow, DataError> {
-    let currency = Currency::from_str(currency).map_err(|_e| DataError::NotFound)?;
+    let currency = Currency::from_str(currency).map_err(|e| DataError::parse(currency, e))?;
     let date = NaiveDate::parse_from_str(date, "%Y-%m-%d")
-        .map_err(|_e| DataError::NotFound)?;
+        .map_err(|e| DataError::parse(date, e))?;
     Ok(CashFlow::new(amount, currency, date))
 }
 
 /// Convert string to DateTime<Utc>
 pub fn to_time(time: &str) -> Result<DateTime<Utc>, DataError> {
     let time =
-        DateTime::parse_from_rfc3339(time).map_err(|_e| DataError::NotFound)?;
+        DateTime::parse_from_rfc3339(time).map_err(|e| DataError::parse(time, e))?;
     let time: DateTime<Utc> = DateTime::from(time);
     Ok(time)
 }
@@ -10,10 +10,16 @@ pub mod macros;
 
 // module exports
 pub mod asset;
+#[cfg(feature = "async")]
+pub mod async_handler;
+pub mod cache_handler;
 pub mod fiat;
 pub mod data_handler;
 pub mod date_time_helper;
 pub mod helpers;
+pub mod memory_handler;
 pub mod quote;
 pub mod rocksdb_handler;
+#[cfg(feature = "sqlite")]
+pub mod sqlite_handler;
 pub mod transaction;
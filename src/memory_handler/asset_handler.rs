@@ -0,0 +1,102 @@
+//! Implementation of in-memory asset handler
+use std::collections::BTreeMap;
+
+use super::MemoryDB;
+
+use crate::asset::Asset;
+use crate::data_handler::asset_handler::duplicate_id;
+use crate::data_handler::{AssetHandler, BatchHandler, DataError, DataType, Operation, Page};
+
+
+/// Fail if the ISIN or WKN `id` of the asset is used by another asset
+fn check_unique(
+    index: &BTreeMap<String, String>,
+    asset: &Asset,
+    id_kind: &str,
+    id: Option<&str>,
+) -> Result<(), DataError> {
+    match id.and_then(|id| index.get(id).map(|owner| (id, owner))) {
+        Some((id, owner)) if *owner != asset.name => Err(duplicate_id(asset, id_kind, id, owner)),
+        _ => Ok(()),
+    }
+}
+
+impl MemoryDB {
+    fn get_asset_by_index(&self, index: &BTreeMap<String, String>, id: &str) -> Result<Asset, DataError> {
+        index
+            .get(id)
+            .and_then(|name| self.assets.get(name))
+            .cloned()
+            .ok_or_else(|| DataError::not_found(DataType::Asset, id))
+    }
+
+    /// Store the asset and its index entries, rejecting an ISIN or WKN of another asset
+    pub(super) fn put_asset(&mut self, asset: &Asset) -> Result<(), DataError> {
+        check_unique(&self.isin_index, asset, "ISIN", asset.isin.as_deref())?;
+        check_unique(&self.wkn_index, asset, "WKN", asset.wkn.as_deref())?;
+        self.store_asset(asset);
+        Ok(())
+    }
+
+    /// Store the asset together with its index entries, without checking the uniqueness
+    /// of its ISIN and WKN
+    pub(super) fn store_asset(&mut self, asset: &Asset) {
+        self.remove_asset(&asset.name);
+        if let Some(isin) = &asset.isin {
+            self.isin_index.insert(isin.clone(), asset.name.clone());
+        }
+        if let Some(wkn) = &asset.wkn {
+            self.wkn_index.insert(wkn.clone(), asset.name.clone());
+        }
+        self.assets.insert(asset.name.clone(), asset.clone());
+    }
+
+    /// Remove the asset with the given name together with its index entries
+    pub(super) fn remove_asset(&mut self, name: &str) {
+        if let Some(asset) = self.assets.remove(name) {
+            if let Some(isin) = &asset.isin {
+                self.isin_index.remove(isin);
+            }
+            if let Some(wkn) = &asset.wkn {
+                self.wkn_index.remove(wkn);
+            }
+        }
+    }
+}
+
+impl AssetHandler for MemoryDB {
+    fn get_asset_by_name(&self, name: &str) -> Result<Asset, DataError> {
+        self.assets
+            .get(name)
+            .cloned()
+            .ok_or_else(|| DataError::not_found(DataType::Asset, name))
+    }
+
+    fn get_asset_by_isin(&self, isin: &str) -> Result<Asset, DataError> {
+        self.get_asset_by_index(&self.isin_index, isin)
+    }
+
+    fn get_asset_by_wkn(&self, wkn: &str) -> Result<Asset, DataError> {
+        self.get_asset_by_index(&self.wkn_index, wkn)
+    }
+
+    fn list_assets(&self, page: &Page) -> Result<Vec<Asset>, DataError> {
+        Ok(page.select(self.assets.values().cloned(), |asset| &asset.name))
+    }
+
+    fn insert_asset(&mut self, asset: &Asset) -> Result<(), DataError> {
+        self.write_batch(&Operation::InsertAsset(asset.clone()).into())
+    }
+
+    fn update_asset(&mut self, asset: &Asset) -> Result<(), DataError> {
+        self.write_batch(&Operation::UpdateAsset(asset.clone()).into())
+    }
+
+    fn upsert_asset(&mut self, asset: &Asset) -> Result<(), DataError> {
+        self.write_batch(&Operation::UpsertAsset(asset.clone()).into())
+    }
+
+    fn delete_asset(&mut self, asset: &Asset) -> Result<(), DataError> {
+        self.write_batch(&Operation::DeleteAsset(asset.clone()).into())
+    }
+}
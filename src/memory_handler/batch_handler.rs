@@ -0,0 +1,279 @@
+//! Implementation of in-memory batch handler
+use std::collections::BTreeMap;
+use std::sync::mpsc::Receiver;
+
+use super::MemoryDB;
+
+use crate::data_handler::bulk::apply_quotes;
+use crate::data_handler::integrity::{apply_batch, BatchStore};
+use crate::data_handler::{
+    transaction_key_string, Batch, BatchHandler, BulkReport, Change, ChangeFilter, DataError, OnDuplicate, Operation,
+    SubscriptionHandler,
+};
+use crate::asset::Asset;
+use crate::quote::{Quote, Ticker};
+use crate::transaction::Transaction;
+use chrono::{DateTime, Utc};
+
+impl BatchStore for MemoryDB {
+    fn asset_exists(&mut self, name: &str) -> Result<bool, DataError> {
+        Ok(self.assets.contains_key(name))
+    }
+
+    fn ticker_exists(&mut self, name: &str) -> Result<bool, DataError> {
+        Ok(self.tickers.contains_key(name))
+    }
+
+    fn has_quotes(&mut self, ticker: &str) -> Result<bool, DataError> {
+        Ok(self.quotes.contains_key(ticker))
+    }
+
+    fn asset_tickers(&mut self, asset: &str) -> Result<Vec<Ticker>, DataError> {
+        Ok(self.tickers.values().filter(|ticker| ticker.asset == asset).cloned().collect())
+    }
+
+    fn asset_transaction(&mut self, asset: &str) -> Result<Option<String>, DataError> {
+        Ok(self.transactions.iter().find_map(|(sort_prefix, transactions)| {
+            transactions
+                .values()
+                .find(|transaction| transaction.asset_name() == Some(asset))
+                .map(|transaction| transaction_key_string(sort_prefix, transaction.id))
+        }))
+    }
+
+    fn record_exists(&mut self, operation: &Operation) -> Result<bool, DataError> {
+        Ok(match operation {
+            Operation::InsertAsset(asset)
+            | Operation::UpdateAsset(asset)
+            | Operation::UpsertAsset(asset)
+            | Operation::DeleteAsset(asset) => self.assets.contains_key(&asset.name),
+            Operation::InsertTicker(ticker)
+            | Operation::UpdateTicker(ticker)
+            | Operation::UpsertTicker(ticker)
+            | Operation::DeleteTicker(ticker) => self.tickers.contains_key(&ticker.name),
+            Operation::InsertQuote(quote)
+            | Operation::UpdateQuote(quote)
+            | Operation::UpsertQuote(quote)
+            | Operation::DeleteQuote(quote) => match self.quotes.get(&quote.ticker) {
+                Some(quotes) => quotes.contains_key(&(quote.time, quote.id.unwrap_or(0))),
+                None => false,
+            },
+            Operation::InsertTransaction { sort_prefix, transaction }
+            | Operation::UpdateTransaction { sort_prefix, transaction }
+            | Operation::UpsertTransaction { sort_prefix, transaction }
+            | Operation::DeleteTransaction { sort_prefix, transaction } =>
+                self.transaction_date(sort_prefix, transaction.id).is_some(),
+        })
+    }
+
+    fn max_quote_seq(&mut self, ticker: &str, time: &DateTime<Utc>) -> Result<Option<i64>, DataError> {
+        Ok(self
+            .quotes
+            .get(ticker)
+            .and_then(|quotes| quotes.range((*time, i64::MIN)..=(*time, i64::MAX)).next_back())
+            .map(|((_, seq), _)| *seq))
+    }
+
+    fn apply(&mut self, operation: &Operation) -> Result<(), DataError> {
+        match operation {
+            Operation::InsertAsset(asset) | Operation::UpdateAsset(asset) | Operation::UpsertAsset(asset) =>
+                return self.put_asset(asset),
+            Operation::DeleteAsset(asset) => self.remove_asset(&asset.name),
+            Operation::InsertTicker(ticker) | Operation::UpdateTicker(ticker) | Operation::UpsertTicker(ticker) => {
+                self.tickers.insert(ticker.name.clone(), ticker.clone());
+            }
+            Operation::DeleteTicker(ticker) => {
+                self.tickers.remove(&ticker.name);
+            }
+            Operation::InsertQuote(quote) | Operation::UpdateQuote(quote) | Operation::UpsertQuote(quote) =>
+                self.put_quote(quote),
+            Operation::DeleteQuote(quote) => self.remove_quote(quote),
+            Operation::InsertTransaction { sort_prefix, transaction }
+            | Operation::UpdateTransaction { sort_prefix, transaction }
+            | Operation::UpsertTransaction { sort_prefix, transaction } =>
+                self.put_transaction(sort_prefix, transaction),
+            Operation::DeleteTransaction { sort_prefix, transaction } =>
+                self.remove_transaction(sort_prefix, transaction.id),
+        }
+        Ok(())
+    }
+
+    fn delete_ticker_quotes(&mut self, ticker: &str) -> Result<(), DataError> {
+        self.tickers.remove(ticker);
+        self.quotes.remove(ticker);
+        Ok(())
+    }
+}
+
+impl MemoryDB {
+    /// Write the batch and return the sequence ids of the quotes it inserts
+    pub(super) fn write(&mut self, batch: &Batch) -> Result<Vec<i64>, DataError> {
+        let policy = self.integrity_policy;
+        let mut changes = self.feed.collector();
+        let mut journal = Journal { db: self, undo: Vec::new() };
+        match apply_batch(&mut journal, batch, policy, changes.as_mut()) {
+            Ok(quote_ids) => {
+                self.feed.publish(changes.unwrap_or_default());
+                Ok(quote_ids)
+            }
+            Err(err) => {
+                // a failing operation leaves all data untouched
+                journal.rollback();
+                Err(err)
+            }
+        }
+    }
+
+    /// Write the quotes in place, which is safe since all quotes are checked before the
+    /// first one is stored
+    pub(super) fn write_quotes(&mut self, quotes: &[Quote], on_duplicate: OnDuplicate) -> Result<BulkReport, DataError> {
+        let mut changes = self.feed.collector();
+        let report = apply_quotes(self, quotes, on_duplicate, changes.as_mut())?;
+
+        self.feed.publish(changes.unwrap_or_default());
+        Ok(report)
+    }
+
+}
+
+impl BatchHandler for MemoryDB {
+    fn write_batch(&mut self, batch: &Batch) -> Result<(), DataError> {
+        self.write(batch).map(|_| ())
+    }
+}
+
+impl SubscriptionHandler for MemoryDB {
+    fn subscribe(&mut self, filter: ChangeFilter) -> Receiver<Change> {
+        self.feed.subscribe(filter)
+    }
+}
+
+/// Record as stored before a write changed it, `None` if there was none
+enum Undo {
+    Asset(String, Option<Asset>),
+    Ticker(String, Option<Ticker>),
+    Quote(String, (DateTime<Utc>, i64), Option<Quote>),
+    TickerQuotes(String, Option<BTreeMap<(DateTime<Utc>, i64), Quote>>),
+    Transaction(String, u128, Option<Transaction>),
+}
+
+/// Store applying the operations of a batch to the data in place, while logging the
+/// records they replace, such that a failed batch can be rolled back
+struct Journal<'a> {
+    db: &'a mut MemoryDB,
+    undo: Vec<Undo>,
+}
+
+impl Journal<'_> {
+    /// Log the record the operation is about to change
+    fn record(&mut self, operation: &Operation) {
+        let db = &*self.db;
+        let undo = match operation {
+            Operation::InsertAsset(asset)
+            | Operation::UpdateAsset(asset)
+            | Operation::UpsertAsset(asset)
+            | Operation::DeleteAsset(asset) => Undo::Asset(asset.name.clone(), db.assets.get(&asset.name).cloned()),
+            Operation::InsertTicker(ticker)
+            | Operation::UpdateTicker(ticker)
+            | Operation::UpsertTicker(ticker)
+            | Operation::DeleteTicker(ticker) =>
+                Undo::Ticker(ticker.name.clone(), db.tickers.get(&ticker.name).cloned()),
+            Operation::InsertQuote(quote)
+            | Operation::UpdateQuote(quote)
+            | Operation::UpsertQuote(quote)
+            | Operation::DeleteQuote(quote) => {
+                let key = (quote.time, quote.id.unwrap_or(0));
+                let stored = db.quotes.get(&quote.ticker).and_then(|quotes| quotes.get(&key)).cloned();
+                Undo::Quote(quote.ticker.clone(), key, stored)
+            }
+            Operation::InsertTransaction { sort_prefix, transaction }
+            | Operation::UpdateTransaction { sort_prefix, transaction }
+            | Operation::UpsertTransaction { sort_prefix, transaction }
+            | Operation::DeleteTransaction { sort_prefix, transaction } => {
+                let stored = db.transaction_date(sort_prefix, transaction.id).and_then(|date| {
+                    db.transactions[sort_prefix.as_str()].get(&(date, transaction.id)).cloned()
+                });
+                Undo::Transaction(sort_prefix.clone(), transaction.id, stored)
+            }
+        };
+        self.undo.push(undo);
+    }
+
+    /// Restore all logged records, latest first
+    fn rollback(self) {
+        let db = self.db;
+        for undo in self.undo.into_iter().rev() {
+            match undo {
+                Undo::Asset(_, Some(asset)) => db.store_asset(&asset),
+                Undo::Asset(name, None) => db.remove_asset(&name),
+                Undo::Ticker(name, Some(ticker)) => {
+                    db.tickers.insert(name, ticker);
+                }
+                Undo::Ticker(name, None) => {
+                    db.tickers.remove(&name);
+                }
+                Undo::Quote(_, _, Some(quote)) => db.put_quote(&quote),
+                Undo::Quote(ticker, key, None) => {
+                    if let Some(quotes) = db.quotes.get_mut(&ticker) {
+                        quotes.remove(&key);
+                        if quotes.is_empty() {
+                            db.quotes.remove(&ticker);
+                        }
+                    }
+                }
+                Undo::TickerQuotes(ticker, Some(quotes)) => {
+                    db.quotes.insert(ticker, quotes);
+                }
+                Undo::TickerQuotes(ticker, None) => {
+                    db.quotes.remove(&ticker);
+                }
+                Undo::Transaction(sort_prefix, _, Some(transaction)) => db.put_transaction(&sort_prefix, &transaction),
+                Undo::Transaction(sort_prefix, id, None) => db.remove_transaction(&sort_prefix, id),
+            }
+        }
+    }
+}
+
+impl BatchStore for Journal<'_> {
+    fn asset_exists(&mut self, name: &str) -> Result<bool, DataError> {
+        self.db.asset_exists(name)
+    }
+
+    fn ticker_exists(&mut self, name: &str) -> Result<bool, DataError> {
+        self.db.ticker_exists(name)
+    }
+
+    fn has_quotes(&mut self, ticker: &str) -> Result<bool, DataError> {
+        self.db.has_quotes(ticker)
+    }
+
+    fn asset_tickers(&mut self, asset: &str) -> Result<Vec<Ticker>, DataError> {
+        self.db.asset_tickers(asset)
+    }
+
+    fn asset_transaction(&mut self, asset: &str) -> Result<Option<String>, DataError> {
+        self.db.asset_transaction(asset)
+    }
+
+    fn record_exists(&mut self, operation: &Operation) -> Result<bool, DataError> {
+        self.db.record_exists(operation)
+    }
+
+    fn max_quote_seq(&mut self, ticker: &str, time: &DateTime<Utc>) -> Result<Option<i64>, DataError> {
+        self.db.max_quote_seq(ticker, time)
+    }
+
+    fn apply(&mut self, operation: &Operation) -> Result<(), DataError> {
+        self.record(operation);
+        self.db.apply(operation)
+    }
+
+    fn delete_ticker_quotes(&mut self, ticker: &str) -> Result<(), DataError> {
+        // move the quotes into the log instead of copying them
+        let stored = self.db.tickers.remove(ticker);
+        self.undo.push(Undo::Ticker(ticker.to_string(), stored));
+        let quotes = self.db.quotes.remove(ticker);
+        self.undo.push(Undo::TickerQuotes(ticker.to_string(), quotes));
+        Ok(())
+    }
+}
@@ -0,0 +1,54 @@
+//! Implementation of in-memory integrity handler
+use super::MemoryDB;
+
+use crate::data_handler::integrity::{OrphanedQuotes, OrphanedTicker, OrphanedTransaction};
+use crate::data_handler::{DataError, IntegrityHandler, IntegrityPolicy, IntegrityReport};
+
+
+impl IntegrityHandler for MemoryDB {
+    fn integrity_policy(&self) -> IntegrityPolicy {
+        self.integrity_policy
+    }
+
+    fn set_integrity_policy(&mut self, policy: IntegrityPolicy) {
+        self.integrity_policy = policy;
+    }
+
+    fn check_integrity(&mut self) -> Result<IntegrityReport, DataError> {
+        let mut report = IntegrityReport::default();
+
+        for ticker in self.tickers.values() {
+            if !self.assets.contains_key(&ticker.asset) {
+                report.tickers.push(OrphanedTicker {
+                    ticker: ticker.name.clone(),
+                    asset: ticker.asset.clone(),
+                });
+            }
+        }
+        for (ticker, quotes) in &self.quotes {
+            if !self.tickers.contains_key(ticker) {
+                report.quotes.push(OrphanedQuotes {
+                    ticker: ticker.clone(),
+                    count: quotes.len(),
+                });
+            }
+        }
+        for (sort_prefix, transactions) in &self.transactions {
+            for transaction in transactions.values() {
+                match transaction.asset_name() {
+                    Some(asset) if !self.assets.contains_key(asset) => {
+                        report.transactions.push(OrphanedTransaction {
+                            sort_prefix: sort_prefix.clone(),
+                            id: transaction.id,
+                            asset: asset.to_string(),
+                        })
+                    }
+                    _ => {}
+                }
+            }
+        }
+        report.transactions.sort_by(|a, b| (&a.sort_prefix, a.id).cmp(&(&b.sort_prefix, b.id)));
+
+        Ok(report)
+    }
+}
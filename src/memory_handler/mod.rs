@@ -0,0 +1,267 @@
+//! Implementation of an in-memory data handler
+use std::collections::BTreeMap;
+use std::iter;
+use std::ops::RangeBounds;
+
+use chrono::{DateTime, NaiveDate, Utc};
+
+use crate::asset::Asset;
+use crate::data_handler::changes::ChangeFeed;
+use crate::data_handler::{Cursor, IntegrityPolicy};
+use crate::quote::{Quote, Ticker};
+use crate::transaction::Transaction;
+
+mod asset_handler;
+mod batch_handler;
+mod integrity;
+mod quote_handler;
+mod snapshot;
+mod transaction_handler;
+
+pub use snapshot::MemorySnapshot;
+
+/// Data handler keeping all data in ordered maps in memory
+///
+/// Quotes are ordered by ticker, time and sequence id and transactions by sort prefix,
+/// booking date and id, i.e. the same way the keys of the RocksDB backend are ordered. Nothing is
+/// persisted, which makes this handler a good fit for tests and prototypes.
+///
+/// Writes change the data in place and log the records they replace, from which a write
+/// failing its reference checks is rolled back.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryDB {
+    assets: BTreeMap<String, Asset>,
+    /// Names of the assets by ISIN
+    isin_index: BTreeMap<String, String>,
+    /// Names of the assets by WKN
+    wkn_index: BTreeMap<String, String>,
+    tickers: BTreeMap<String, Ticker>,
+    quotes: BTreeMap<String, BTreeMap<(DateTime<Utc>, i64), Quote>>,
+    transactions: BTreeMap<String, BTreeMap<(NaiveDate, u128), Transaction>>,
+    /// Booking dates of the transactions by sort prefix and id
+    transaction_dates: BTreeMap<String, BTreeMap<u128, NaiveDate>>,
+    integrity_policy: IntegrityPolicy,
+    feed: ChangeFeed,
+}
+
+impl MemoryDB {
+    pub fn new() -> MemoryDB {
+        MemoryDB::default()
+    }
+}
+
+/// Iterate over the given range of an optional map, in descending order if `reverse` is set
+fn range_cursor<'a, K, T, R>(map: Option<&'a BTreeMap<K, T>>, range: R, reverse: bool) -> Cursor<'a, T>
+where
+    K: Ord,
+    T: Clone,
+    R: RangeBounds<K>,
+{
+    match map {
+        Some(map) if reverse => Box::new(map.range(range).rev().map(|(_, item)| Ok(item.clone()))),
+        Some(map) => Box::new(map.range(range).map(|(_, item)| Ok(item.clone()))),
+        None => Box::new(iter::empty()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data_handler::integrity::{OrphanedQuotes, OrphanedTicker, OrphanedTransaction};
+    use crate::data_handler::{
+        conformance, AssetHandler, Batch, BatchHandler, IntegrityHandler, IntegrityPolicy, QuoteHandler,
+        TransactionHandler,
+    };
+    use crate::fiat::{CashFlow, Currency};
+    use crate::helpers::make_time;
+    use crate::transaction::TransactionType;
+    use chrono::NaiveDate;
+
+    fn quote(ticker: &str, hour: u32, price: f64) -> Quote {
+        Quote {
+            id: None,
+            ticker: ticker.to_string(),
+            price,
+            time: make_time(2020, 9, 1, hour, 0, 0).unwrap(),
+            volume: None,
+        }
+    }
+
+    fn ticker(name: &str) -> Ticker {
+        Ticker {
+            name: name.to_string(),
+            asset: "Apple".to_string(),
+            currency: Currency::USD,
+            priority: 1,
+            factor: 1.0,
+        }
+    }
+
+    #[test]
+    fn test_conformance() {
+        conformance::check_all(MemoryDB::new, MemoryDB::new);
+    }
+
+    #[test]
+    fn test_asset_roundtrip() {
+        let mut db = MemoryDB::new();
+        let asset = Asset::new("Apple", None, Some("US0378331005".to_string()), None);
+        db.insert_asset(&asset).unwrap();
+        assert_eq!(
+            db.get_asset_by_name("Apple").unwrap().isin,
+            Some("US0378331005".to_string())
+        );
+
+        db.delete_asset(&asset).unwrap();
+        assert!(db.get_asset_by_name("Apple").is_err());
+    }
+
+    #[test]
+    fn test_quote_cursors() {
+        let mut db = MemoryDB::new();
+        db.insert_asset(&Asset::new("Apple", None, None, None)).unwrap();
+        db.insert_ticker(&ticker("AAPL")).unwrap();
+        db.insert_ticker(&ticker("MSFT")).unwrap();
+        for (hour, price) in [(10, 1.0), (12, 2.0), (14, 3.0)].iter() {
+            db.insert_quote(&quote("AAPL", *hour, *price)).unwrap();
+        }
+        db.insert_quote(&quote("MSFT", 11, 10.0)).unwrap();
+
+        assert_eq!(db.get_latest_quote("AAPL").unwrap().unwrap().price, 3.0);
+        assert_eq!(db.get_oldest_quote("AAPL").unwrap().unwrap().price, 1.0);
+        assert!(db.get_latest_quote("IBM").unwrap().is_none());
+
+        let aapl = ticker("AAPL");
+        let time = make_time(2020, 9, 1, 12, 0, 0).unwrap();
+        let forward: Vec<f64> = db
+            .quote_cursor_forward(&aapl, time)
+            .map(|q| q.unwrap().price)
+            .collect();
+        assert_eq!(forward, vec![2.0, 3.0]);
+        let reverse: Vec<f64> = db
+            .quote_cursor_reverse(&aapl, time)
+            .map(|q| q.unwrap().price)
+            .collect();
+        assert_eq!(reverse, vec![2.0, 1.0]);
+
+        let from = make_time(2020, 9, 1, 10, 0, 0).unwrap();
+        let range: Vec<f64> = db
+            .quote_range_forward(&aapl, from, time)
+            .map(|q| q.unwrap().price)
+            .collect();
+        assert_eq!(range, vec![1.0]);
+        let range: Vec<f64> = db
+            .quote_range_reverse(&aapl, from, time + chrono::Duration::hours(3))
+            .map(|q| q.unwrap().price)
+            .collect();
+        assert_eq!(range, vec![3.0, 2.0, 1.0]);
+        assert_eq!(db.quote_range_forward(&aapl, time, from).count(), 0);
+    }
+
+    #[test]
+    fn test_transactions() {
+        let mut db = MemoryDB::new();
+        let date = NaiveDate::from_ymd(2020, 9, 1);
+        let mut first = Transaction::new(
+            TransactionType::Cash,
+            CashFlow::new(100.0, Currency::EUR, date),
+            None,
+        );
+        first.id = 1;
+        let mut second = first.clone();
+        second.id = 2;
+        db.insert_transaction("book", &first).unwrap();
+        db.insert_transaction("book", &second).unwrap();
+
+        assert_eq!(db.get_latest_transaction("book").unwrap().unwrap().id, 2);
+        assert_eq!(db.get_oldest_transaction("book").unwrap().unwrap().id, 1);
+        assert_eq!(db.get_transaction_by_id("book", 1).unwrap().id, 1);
+        assert!(db.get_transaction_by_id("other", 1).is_err());
+
+        db.delete_transaction("book", &second).unwrap();
+        assert_eq!(db.get_latest_transaction("book").unwrap().unwrap().id, 1);
+    }
+
+    #[test]
+    fn test_failed_write_rolls_back() {
+        let mut db = MemoryDB::new();
+        db.set_integrity_policy(IntegrityPolicy::Cascade);
+        let apple = Asset::new("Apple", None, Some("US0378331005".to_string()), None);
+        db.insert_asset(&apple).unwrap();
+        db.insert_ticker(&ticker("AAPL")).unwrap();
+        db.insert_quote(&quote("AAPL", 10, 1.0)).unwrap();
+        db.insert_quote(&quote("AAPL", 11, 2.0)).unwrap();
+        let mut cash = Transaction::new(
+            TransactionType::Cash,
+            CashFlow::new(100.0, Currency::EUR, NaiveDate::from_ymd(2020, 9, 1)),
+            None,
+        );
+        cash.id = 1;
+        db.insert_transaction("book", &cash).unwrap();
+
+        let mut renamed = apple.clone();
+        renamed.isin = Some("US5949181045".to_string());
+        let mut moved = cash.clone();
+        moved.cash_flow.date = NaiveDate::from_ymd(2020, 9, 2);
+        let mut orphan = ticker("IBM");
+        orphan.asset = "IBM".to_string();
+        let mut batch = Batch::new();
+        batch
+            .update_asset(&renamed)
+            .insert_quote(&quote("AAPL", 12, 3.0))
+            .delete_ticker(&ticker("AAPL"))
+            .update_transaction("book", &moved)
+            .insert_ticker(&orphan);
+        assert!(db.write_batch(&batch).is_err());
+
+        assert_eq!(
+            db.get_asset_by_isin("US0378331005").unwrap().name,
+            "Apple",
+            "index entry not restored"
+        );
+        assert!(db.get_asset_by_isin("US5949181045").is_err());
+        assert!(db.get_ticker_by_name("AAPL").is_ok());
+        let aapl = ticker("AAPL");
+        let prices: Vec<f64> = db
+            .quote_cursor_forward(&aapl, make_time(2020, 9, 1, 0, 0, 0).unwrap())
+            .map(|q| q.unwrap().price)
+            .collect();
+        assert_eq!(prices, vec![1.0, 2.0]);
+        assert_eq!(
+            db.get_transaction_by_id("book", 1).unwrap().cash_flow.date,
+            NaiveDate::from_ymd(2020, 9, 1)
+        );
+        assert!(db.get_ticker_by_name("IBM").is_err());
+    }
+
+    #[test]
+    fn test_integrity_report() {
+        // orphans can only be stored by bypassing the checks of the handler
+        let mut db = MemoryDB::new();
+        db.tickers.insert("AAPL".to_string(), ticker("AAPL"));
+        db.put_quote(&quote("AAPL", 10, 1.0));
+        db.put_quote(&quote("MSFT", 10, 1.0));
+        db.put_quote(&quote("MSFT", 11, 2.0));
+        let mut dividend = Transaction::new(
+            TransactionType::Dividend { asset_name: "Apple".to_string() },
+            CashFlow::new(10.0, Currency::USD, NaiveDate::from_ymd(2020, 9, 1)),
+            None,
+        );
+        dividend.id = 1;
+        db.put_transaction("book", &dividend);
+
+        let report = db.check_integrity().unwrap();
+        assert_eq!(
+            report.tickers,
+            vec![OrphanedTicker { ticker: "AAPL".to_string(), asset: "Apple".to_string() }]
+        );
+        assert_eq!(report.quotes, vec![OrphanedQuotes { ticker: "MSFT".to_string(), count: 2 }]);
+        assert_eq!(
+            report.transactions,
+            vec![OrphanedTransaction { sort_prefix: "book".to_string(), id: 1, asset: "Apple".to_string() }]
+        );
+
+        db.insert_asset(&Asset::new("Apple", None, None, None)).unwrap();
+        assert!(db.check_integrity().unwrap().tickers.is_empty());
+    }
+}
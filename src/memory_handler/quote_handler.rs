@@ -0,0 +1,150 @@
+//! Implementation for quote handler with in-memory maps as backend
+use super::{range_cursor, MemoryDB};
+
+use crate::data_handler::{
+    quote_key_string, BatchHandler, BulkReport, Change, Cursor, DataError, DataType, OnDuplicate, Operation, Page,
+    QuoteHandler, TickerFilter,
+};
+
+use crate::quote::{Quote, Ticker};
+use chrono::{DateTime, Utc};
+
+use std::iter;
+
+
+impl MemoryDB {
+    pub(super) fn put_quote(&mut self, quote: &Quote) {
+        self.quotes
+            .entry(quote.ticker.clone())
+            .or_default()
+            .insert((quote.time, quote.id.unwrap_or(0)), quote.clone());
+    }
+
+    pub(super) fn remove_quote(&mut self, quote: &Quote) {
+        if let Some(quotes) = self.quotes.get_mut(&quote.ticker) {
+            quotes.remove(&(quote.time, quote.id.unwrap_or(0)));
+
+            if quotes.is_empty() {
+                self.quotes.remove(&quote.ticker);
+            }
+        }
+    }
+}
+
+/// In-memory implementation of quote handler
+impl QuoteHandler for MemoryDB {
+    fn get_ticker_by_name(&self, name: &str) -> Result<Ticker, DataError> {
+        self.tickers
+            .get(name)
+            .cloned()
+            .ok_or_else(|| DataError::not_found(DataType::Ticker, name))
+    }
+
+    fn list_tickers(&self, filter: &TickerFilter, page: &Page) -> Result<Vec<Ticker>, DataError> {
+        let tickers = self.tickers.values().filter(|ticker| filter.matches(ticker)).cloned();
+        Ok(page.select(tickers, |ticker| &ticker.name))
+    }
+
+    fn get_quote_by_id(&self, ticker_name: &str, time: DateTime<Utc>, id: i64) -> Result<Quote, DataError> {
+        self.quotes
+            .get(ticker_name)
+            .and_then(|quotes| quotes.get(&(time, id)))
+            .cloned()
+            .ok_or_else(|| DataError::not_found(DataType::Quote, quote_key_string(ticker_name, &time, id)))
+    }
+
+    fn get_latest_quote(&self, ticker_name: &str) -> Result<Option<Quote>, DataError> {
+        Ok(self
+            .quotes
+            .get(ticker_name)
+            .and_then(|quotes| quotes.values().next_back())
+            .cloned())
+    }
+
+    fn get_oldest_quote(&self, ticker_name: &str) -> Result<Option<Quote>, DataError> {
+        Ok(self
+            .quotes
+            .get(ticker_name)
+            .and_then(|quotes| quotes.values().next())
+            .cloned())
+    }
+
+    fn insert_ticker(&mut self, ticker: &Ticker) -> Result<(), DataError> {
+        self.write_batch(&Operation::InsertTicker(ticker.clone()).into())
+    }
+
+    fn update_ticker(&mut self, ticker: &Ticker) -> Result<(), DataError> {
+        self.write_batch(&Operation::UpdateTicker(ticker.clone()).into())
+    }
+
+    fn upsert_ticker(&mut self, ticker: &Ticker) -> Result<(), DataError> {
+        self.write_batch(&Operation::UpsertTicker(ticker.clone()).into())
+    }
+
+    fn delete_ticker(&mut self, ticker: &Ticker) -> Result<(), DataError> {
+        self.write_batch(&Operation::DeleteTicker(ticker.clone()).into())
+    }
+
+    fn insert_quote(&mut self, quote: &Quote) -> Result<i64, DataError> {
+        // a batch of a single insert returns a single id
+        self.write(&Operation::InsertQuote(quote.clone()).into())
+            .map(|quote_ids| quote_ids[0])
+    }
+
+    fn update_quote(&mut self, quote: &Quote) -> Result<(), DataError> {
+        self.write_batch(&Operation::UpdateQuote(quote.clone()).into())
+    }
+
+    fn upsert_quote(&mut self, quote: &Quote) -> Result<(), DataError> {
+        self.write_batch(&Operation::UpsertQuote(quote.clone()).into())
+    }
+
+    fn delete_quote(&mut self, quote: &Quote) -> Result<(), DataError> {
+        self.write_batch(&Operation::DeleteQuote(quote.clone()).into())
+    }
+
+    fn delete_quotes(&mut self, ticker_name: &str, from: DateTime<Utc>, to: DateTime<Utc>) -> Result<(), DataError> {
+        if let (Some(quotes), true) = (self.quotes.get_mut(ticker_name), from < to) {
+            let mut deleted = quotes.split_off(&(from, i64::MIN));
+            quotes.append(&mut deleted.split_off(&(to, i64::MIN)));
+
+            if quotes.is_empty() {
+                self.quotes.remove(ticker_name);
+            }
+        }
+        if from < to {
+            self.feed.publish(vec![Change::QuotesDeleted {
+                ticker: ticker_name.to_string(),
+                from: Some(from),
+                to: Some(to),
+            }]);
+        }
+        Ok(())
+    }
+
+    fn insert_quotes(&mut self, quotes: &[Quote], on_duplicate: OnDuplicate) -> Result<BulkReport, DataError> {
+        self.write_quotes(quotes, on_duplicate)
+    }
+
+    fn quote_cursor_forward(&self, ticker: &Ticker, time: DateTime<Utc>) -> Cursor<'_, Quote> {
+        range_cursor(self.quotes.get(&ticker.name), (time, i64::MIN).., false)
+    }
+
+    fn quote_cursor_reverse(&self, ticker: &Ticker, time: DateTime<Utc>) -> Cursor<'_, Quote> {
+        range_cursor(self.quotes.get(&ticker.name), ..=(time, i64::MAX), true)
+    }
+
+    fn quote_range_forward(&self, ticker: &Ticker, from: DateTime<Utc>, to: DateTime<Utc>) -> Cursor<'_, Quote> {
+        if from >= to {
+            return Box::new(iter::empty());
+        }
+        range_cursor(self.quotes.get(&ticker.name), (from, i64::MIN)..(to, i64::MIN), false)
+    }
+
+    fn quote_range_reverse(&self, ticker: &Ticker, from: DateTime<Utc>, to: DateTime<Utc>) -> Cursor<'_, Quote> {
+        if from >= to {
+            return Box::new(iter::empty());
+        }
+        range_cursor(self.quotes.get(&ticker.name), (from, i64::MIN)..(to, i64::MIN), true)
+    }
+}
@@ -0,0 +1,18 @@
+//! Implementation of in-memory snapshots
+use super::MemoryDB;
+
+use crate::data_handler::snapshot::delegate_snapshot;
+use crate::data_handler::{AssetHandler, DataError, QuoteHandler, Snapshot, SnapshotHandler, TransactionHandler};
+
+/// Copy of all records of a `MemoryDB`
+pub struct MemorySnapshot {
+    db: MemoryDB,
+}
+
+delegate_snapshot!(MemorySnapshot, db);
+
+impl SnapshotHandler for MemoryDB {
+    fn snapshot(&mut self) -> Result<Box<dyn Snapshot + '_>, DataError> {
+        Ok(Box::new(MemorySnapshot { db: self.clone() }))
+    }
+}
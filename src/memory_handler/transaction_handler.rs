@@ -0,0 +1,151 @@
+//! Implementation of in-memory transaction handler
+use crate::data_handler::transaction_handler::booking_date_bound;
+use crate::data_handler::{
+    transaction_key_string, BatchHandler, Cursor, DataError, DataType, Operation, Page, TransactionHandler,
+};
+use crate::transaction::Transaction;
+
+use super::{range_cursor, MemoryDB};
+use chrono::naive::MIN_DATE;
+use chrono::{Utc, DateTime, NaiveDate};
+
+use std::iter;
+use std::ops::Bound;
+
+impl MemoryDB {
+    /// Booking date of the stored transaction with the given id
+    pub(super) fn transaction_date(&self, sort_prefix: &str, id: u128) -> Option<NaiveDate> {
+        self.transaction_dates
+            .get(sort_prefix)
+            .and_then(|dates| dates.get(&id))
+            .copied()
+    }
+
+    pub(super) fn put_transaction(&mut self, sort_prefix: &str, transaction: &Transaction) {
+        // the booking date of the stored transaction may differ
+        self.remove_transaction(sort_prefix, transaction.id);
+
+        let date = transaction.cash_flow.date;
+        self.transactions
+            .entry(sort_prefix.to_string())
+            .or_default()
+            .insert((date, transaction.id), transaction.clone());
+        self.transaction_dates
+            .entry(sort_prefix.to_string())
+            .or_default()
+            .insert(transaction.id, date);
+    }
+
+    pub(super) fn remove_transaction(&mut self, sort_prefix: &str, id: u128) {
+        let date = match self.transaction_date(sort_prefix, id) {
+            Some(date) => date,
+            None => return,
+        };
+
+        if let Some(transactions) = self.transactions.get_mut(sort_prefix) {
+            transactions.remove(&(date, id));
+            if transactions.is_empty() {
+                self.transactions.remove(sort_prefix);
+            }
+        }
+        if let Some(dates) = self.transaction_dates.get_mut(sort_prefix) {
+            dates.remove(&id);
+            if dates.is_empty() {
+                self.transaction_dates.remove(sort_prefix);
+            }
+        }
+    }
+
+    /// Iterate over the transactions with booking dates in `[from, to)`, where `None` is
+    /// beyond any date
+    fn transactions_between(
+        &self,
+        sort_prefix: &str,
+        from: Option<NaiveDate>,
+        to: Option<NaiveDate>,
+        reverse: bool,
+    ) -> Cursor<'_, Transaction> {
+        let range = match (from, to) {
+            (Some(from), Some(to)) if from < to => (Bound::Included((from, 0)), Bound::Excluded((to, 0))),
+            (Some(from), None) => (Bound::Included((from, 0)), Bound::Unbounded),
+            _ => return Box::new(iter::empty()),
+        };
+        range_cursor(self.transactions.get(sort_prefix), range, reverse)
+    }
+}
+
+impl TransactionHandler for MemoryDB {
+    fn get_transaction_by_id(&self, sort_prefix: &str, id: u128) -> Result<Transaction, DataError> {
+        self.transaction_date(sort_prefix, id)
+            .and_then(|date| self.transactions.get(sort_prefix)?.get(&(date, id)))
+            .cloned()
+            .ok_or_else(|| {
+                DataError::not_found(DataType::Transaction, transaction_key_string(sort_prefix, id))
+            })
+    }
+
+    fn list_sort_prefixes(&self, page: &Page) -> Result<Vec<String>, DataError> {
+        let sort_prefixes = self.transactions.keys().cloned();
+        Ok(page.select(sort_prefixes, |sort_prefix| sort_prefix))
+    }
+
+    fn get_latest_transaction(&self, sort_prefix: &str) -> Result<Option<Transaction>, DataError> {
+        Ok(self
+            .transactions
+            .get(sort_prefix)
+            .and_then(|transactions| transactions.values().next_back())
+            .cloned())
+    }
+
+    fn get_oldest_transaction(&self, sort_prefix: &str) -> Result<Option<Transaction>, DataError> {
+        Ok(self
+            .transactions
+            .get(sort_prefix)
+            .and_then(|transactions| transactions.values().next())
+            .cloned())
+    }
+
+    fn insert_transaction(&mut self, sort_prefix: &str, transaction: &Transaction) -> Result<(), DataError> {
+        self.write_batch(&Operation::InsertTransaction {
+            sort_prefix: sort_prefix.to_string(),
+            transaction: transaction.clone(),
+        }.into())
+    }
+
+    fn update_transaction(&mut self, sort_prefix: &str, transaction: &Transaction) -> Result<(), DataError> {
+        self.write_batch(&Operation::UpdateTransaction {
+            sort_prefix: sort_prefix.to_string(),
+            transaction: transaction.clone(),
+        }.into())
+    }
+
+    fn upsert_transaction(&mut self, sort_prefix: &str, transaction: &Transaction) -> Result<(), DataError> {
+        self.write_batch(&Operation::UpsertTransaction {
+            sort_prefix: sort_prefix.to_string(),
+            transaction: transaction.clone(),
+        }.into())
+    }
+
+    fn delete_transaction(&mut self, sort_prefix: &str, transaction: &Transaction) -> Result<(), DataError> {
+        self.write_batch(&Operation::DeleteTransaction {
+            sort_prefix: sort_prefix.to_string(),
+            transaction: transaction.clone(),
+        }.into())
+    }
+
+    fn transaction_cursor_forward(&self, sort_prefix: &str, time: DateTime<Utc>) -> Cursor<'_, Transaction> {
+        self.transactions_between(sort_prefix, booking_date_bound(time, true), None, false)
+    }
+
+    fn transaction_cursor_reverse(&self, sort_prefix: &str, time: DateTime<Utc>) -> Cursor<'_, Transaction> {
+        self.transactions_between(sort_prefix, Some(MIN_DATE), booking_date_bound(time, false), true)
+    }
+
+    fn transaction_range_forward(&self, sort_prefix: &str, from: DateTime<Utc>, to: DateTime<Utc>) -> Cursor<'_, Transaction> {
+        self.transactions_between(sort_prefix, booking_date_bound(from, true), booking_date_bound(to, true), false)
+    }
+
+    fn transaction_range_reverse(&self, sort_prefix: &str, from: DateTime<Utc>, to: DateTime<Utc>) -> Cursor<'_, Transaction> {
+        self.transactions_between(sort_prefix, booking_date_bound(from, true), booking_date_bound(to, true), true)
+    }
+}
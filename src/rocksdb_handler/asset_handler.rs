@@ -1,56 +1,82 @@
-///! Implemenation of sqlite3 data handler
+///! Implemenation of rocksdb asset handler
+use super::column_family::ASSET_INDEX_CF;
+use super::key_codec::{asset_index_key, prefix_key, AssetIndex};
+use super::reader::Reader;
 use super::RocksDB;
 
 use crate::asset::Asset;
-use crate::data_handler::{AssetHandler, DataError, DataType};
+use crate::data_handler::{AssetHandler, BatchHandler, DataError, DataType, Operation, Page};
 
 
+/// Indexed ids of the asset, i.e. its ISIN and WKN if set, with their names
+pub(super) fn asset_ids(asset: &Asset) -> impl Iterator<Item = (AssetIndex, &'static str, &str)> {
+    let isin = asset.isin.as_deref().map(|isin| (AssetIndex::Isin, "ISIN", isin));
+    let wkn = asset.wkn.as_deref().map(|wkn| (AssetIndex::Wkn, "WKN", wkn));
+    isin.into_iter().chain(wkn)
+}
+
+impl<'a> Reader<'a> {
+    pub(super) fn get_asset_by_name(self, name: &str) -> Result<Asset, DataError> {
+        let key = prefix_key(DataType::Asset, name);
+
+        self.get_record(DataType::Asset, key, name)
+    }
+
+    fn get_asset_by_index(self, index: AssetIndex, id: &str) -> Result<Asset, DataError> {
+        let name = self
+            .get_cf(self.cf_handle(ASSET_INDEX_CF)?, &asset_index_key(index, id))
+            .map_err(|e| DataError::storage(format!("read asset index entry '{}'", id), e))?
+            .ok_or_else(|| DataError::not_found(DataType::Asset, id))?;
+
+        self.get_asset_by_name(&String::from_utf8_lossy(&name))
+    }
+
+    pub(super) fn get_asset_by_isin(self, isin: &str) -> Result<Asset, DataError> {
+        self.get_asset_by_index(AssetIndex::Isin, isin)
+    }
+
+    pub(super) fn get_asset_by_wkn(self, wkn: &str) -> Result<Asset, DataError> {
+        self.get_asset_by_index(AssetIndex::Wkn, wkn)
+    }
+
+    pub(super) fn list_assets(self, page: &Page) -> Result<Vec<Asset>, DataError> {
+        // there are few assets, hence they are sorted by name instead of scanning an index
+        let mut assets: Vec<Asset> = self.all_records(DataType::Asset)?;
+        assets.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(page.select(assets, |asset| &asset.name))
+    }
+}
+
 impl AssetHandler for RocksDB {
-    fn get_asset_by_name(&mut self, name: &str) -> Result<Asset, DataError> {
-        let key = self.build_key(
-            &DataType::Asset,
-            &name,
-            "",
-        );
-
-        match self.db.get(key) {
-            Ok(Some(data)) =>
-                match bincode::deserialize(&data) {
-                    Ok(asset) => Ok(asset),
-                    _ => Err(DataError::DataAccessFailure),
-                },
-            _ => Err(DataError::NotFound),
-        }
+    fn get_asset_by_name(&self, name: &str) -> Result<Asset, DataError> {
+        self.reader().get_asset_by_name(name)
+    }
+
+    fn get_asset_by_isin(&self, isin: &str) -> Result<Asset, DataError> {
+        self.reader().get_asset_by_isin(isin)
+    }
+
+    fn get_asset_by_wkn(&self, wkn: &str) -> Result<Asset, DataError> {
+        self.reader().get_asset_by_wkn(wkn)
+    }
+
+    fn list_assets(&self, page: &Page) -> Result<Vec<Asset>, DataError> {
+        self.reader().list_assets(page)
     }
 
     fn insert_asset(&mut self, asset: &Asset) -> Result<(), DataError> {
-        self.update_asset(asset)
+        self.write_batch(&Operation::InsertAsset(asset.clone()).into())
     }
 
     fn update_asset(&mut self, asset: &Asset) -> Result<(), DataError> {
-        let key = self.build_key(
-            &DataType::Asset,
-            &asset.name,
-            "",
-        );
+        self.write_batch(&Operation::UpdateAsset(asset.clone()).into())
+    }
 
-        self.db
-            .put(
-                key,
-                bincode::serialize(&asset).unwrap(),
-            )
-            .map_err(|_| DataError::InsertFailed)
+    fn upsert_asset(&mut self, asset: &Asset) -> Result<(), DataError> {
+        self.write_batch(&Operation::UpsertAsset(asset.clone()).into())
     }
 
     fn delete_asset(&mut self, asset: &Asset) -> Result<(), DataError> {
-        let key = self.build_key(
-            &DataType::Asset,
-            &asset.name,
-            "",
-        );
-
-        self.db
-            .delete(key)
-            .map_err(|_| DataError::DeleteFailed)
+        self.write_batch(&Operation::DeleteAsset(asset.clone()).into())
     }
 }
@@ -0,0 +1,142 @@
+//! Checkpoints, backups and restores of a database in use
+//!
+//! A checkpoint is a copy of the database in another directory on the same file system,
+//! which shares the immutable SST files by hard links and can be opened like any other
+//! database. Backups are written by the RocksDB backup engine into a backup directory,
+//! each backup only copies the files missing from earlier backups:
+//!
+//! ```ignore
+//! db.create_backup("/backup/ticky")?;
+//! RocksDB::purge_backups("/backup/ticky", 7)?;
+//!
+//! let restored = RocksDB::restore_backup("/backup/ticky", "/data/ticky", &RocksDBOptions::default())?;
+//! ```
+use std::fs;
+use std::path::Path;
+
+use chrono::{DateTime, TimeZone, Utc};
+use rocksdb::backup::{BackupEngine, BackupEngineInfo, BackupEngineOptions, RestoreOptions};
+use rocksdb::checkpoint::Checkpoint;
+
+use super::column_family::cf_names;
+use super::options::{AccessMode, RocksDBOptions};
+use super::RocksDB;
+
+use crate::data_handler::{BoxError, DataError};
+
+/// Backup in a backup directory
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BackupInfo {
+    pub id: u32,
+    pub time: DateTime<Utc>,
+    /// Size in bytes of the files of the backup, including those shared with other backups
+    pub size: u64,
+    pub num_files: u32,
+}
+
+impl From<&BackupEngineInfo> for BackupInfo {
+    fn from(info: &BackupEngineInfo) -> BackupInfo {
+        BackupInfo {
+            id: info.backup_id,
+            time: Utc.timestamp(info.timestamp, 0),
+            size: info.size,
+            num_files: info.num_files,
+        }
+    }
+}
+
+fn open_backup_engine(backup_dir: &Path) -> Result<BackupEngine, DataError> {
+    BackupEngine::open(&BackupEngineOptions::default(), backup_dir)
+        .map_err(|e| DataError::storage(format!("open backup directory '{}'", backup_dir.display()), e))
+}
+
+impl RocksDB {
+    /// Write a checkpoint of the database to the given path, which must not exist yet
+    ///
+    /// The path should be on the file system of the database, otherwise all files are
+    /// copied instead of linked.
+    pub fn create_checkpoint<P: AsRef<Path>>(&self, path: P) -> Result<(), DataError> {
+        let path = path.as_ref();
+        Checkpoint::new(&self.db)
+            .and_then(|checkpoint| checkpoint.create_checkpoint(path))
+            .map_err(|e| DataError::storage(format!("create checkpoint at '{}'", path.display()), e))
+    }
+
+    /// Back up the database into the backup directory, which is created if missing, and
+    /// return the new backup
+    ///
+    /// Writes may continue while the backup is written, the backup holds the database as
+    /// of its start. Only read-write instances can be backed up, since the files of the
+    /// database must not be deleted while they are copied.
+    pub fn create_backup<P: AsRef<Path>>(&self, backup_dir: P) -> Result<BackupInfo, DataError> {
+        let backup_dir = backup_dir.as_ref();
+        let context = format!("create backup in '{}'", backup_dir.display());
+        let backup_error = |e| DataError::storage(context.clone(), e);
+
+        if self.options.access_mode != AccessMode::ReadWrite {
+            return Err(DataError::storage(context.clone(), "backups need a read-write instance"));
+        }
+
+        if self.options.disable_wal {
+            // writes without write-ahead log only reach the backup once they are flushed
+            for name in cf_names() {
+                self.db.flush_cf(self.cf_handle(name)?).map_err(backup_error)?;
+            }
+        }
+
+        let mut engine = open_backup_engine(backup_dir)?;
+        engine.create_new_backup(&self.db).map_err(backup_error)?;
+        engine
+            .get_backup_info()
+            .last()
+            .map(BackupInfo::from)
+            .ok_or_else(|| DataError::storage(context.clone(), "backup not found after writing it"))
+    }
+
+    /// Backups in the backup directory, oldest first
+    pub fn list_backups<P: AsRef<Path>>(backup_dir: P) -> Result<Vec<BackupInfo>, DataError> {
+        let engine = open_backup_engine(backup_dir.as_ref())?;
+        Ok(engine.get_backup_info().iter().map(BackupInfo::from).collect())
+    }
+
+    /// Delete all but the latest `keep` backups of the backup directory
+    pub fn purge_backups<P: AsRef<Path>>(backup_dir: P, keep: usize) -> Result<(), DataError> {
+        let backup_dir = backup_dir.as_ref();
+        open_backup_engine(backup_dir)?
+            .purge_old_backups(keep)
+            .map_err(|e| DataError::storage(format!("purge backups in '{}'", backup_dir.display()), e))
+    }
+
+    /// Restore the latest backup of the backup directory to the given path and open the
+    /// restored database with the given options
+    ///
+    /// The path must not hold any files, such that a database in use is never replaced.
+    /// The restored database is not checked beyond its schema version, see
+    /// `IntegrityHandler::check_integrity`.
+    pub fn restore_backup<P: AsRef<Path>, Q: AsRef<Path>>(
+        backup_dir: P,
+        path: Q,
+        options: &RocksDBOptions,
+    ) -> Result<RocksDB, DataError> {
+        let (backup_dir, path) = (backup_dir.as_ref(), path.as_ref());
+        let restore_error = |e: BoxError| {
+            DataError::storage(
+                format!("restore backup of '{}' to '{}'", backup_dir.display(), path.display()),
+                e,
+            )
+        };
+
+        if let Ok(mut entries) = fs::read_dir(path) {
+            if entries.next().is_some() {
+                return Err(restore_error("directory is not empty".into()));
+            }
+        }
+
+        let wal_dir = options.wal_dir.as_deref().unwrap_or(path);
+        open_backup_engine(backup_dir)?
+            .restore_from_latest_backup(path, wal_dir, &RestoreOptions::default())
+            .map_err(|e| restore_error(e.into()))?;
+
+        RocksDB::open(path, options)
+    }
+}
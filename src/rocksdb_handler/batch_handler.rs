@@ -0,0 +1,405 @@
+//! Implementation of rocksdb batch handler
+use std::collections::{BTreeMap, HashMap};
+use std::sync::mpsc::Receiver;
+
+use super::asset_handler::asset_ids;
+use super::column_family::{cf_name, ASSET_INDEX_CF, ASSET_TRANSACTION_INDEX_CF, TRANSACTION_INDEX_CF};
+use super::key_codec::{
+    asset_index_key, asset_transaction_index_key, asset_transaction_prefix, decode_key, key_to_string, next_key,
+    prefix_end, prefix_key, quote_key, transaction_index_key, transaction_key, Key, KEY_VERSION,
+};
+use super::value_codec;
+use super::RocksDB;
+
+use crate::asset::Asset;
+use crate::data_handler::asset_handler::duplicate_id;
+use crate::data_handler::bulk::apply_quotes;
+use crate::data_handler::integrity::{apply_batch, BatchStore};
+use crate::data_handler::{
+    quote_key_string, transaction_key_string, Batch, BatchHandler, BulkReport, Change, ChangeFilter, DataError,
+    DataType, OnDuplicate, Operation, SubscriptionHandler,
+};
+use crate::quote::{Quote, Ticker};
+use crate::transaction::Transaction;
+
+use chrono::{DateTime, Utc};
+use rocksdb::{IteratorMode, ReadOptions, WriteBatch};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+
+/// Key and value as read by iterators
+type KeyValue = (Box<[u8]>, Box<[u8]>);
+
+pub(super) fn serialize<T: Serialize>(value: &T, data_type: DataType, record_key: &str) -> Result<Vec<u8>, DataError> {
+    value_codec::encode(value).map_err(|e| DataError::serialization(data_type, record_key, e))
+}
+
+/// Write batch that also keeps the values it writes, such that operations read the
+/// changes of earlier operations of the same batch
+pub(super) struct PendingWrites<'a> {
+    db: &'a RocksDB,
+    batch: WriteBatch,
+    /// Values by column family and key, `None` for deleted keys
+    values: HashMap<(&'static str, Vec<u8>), Option<Vec<u8>>>,
+    /// Key ranges `[from, to)` deleted by column family, except for keys in `values`
+    deleted_ranges: Vec<(&'static str, Vec<u8>, Vec<u8>)>,
+}
+
+impl<'a> PendingWrites<'a> {
+    pub(super) fn new(db: &'a RocksDB) -> PendingWrites<'a> {
+        PendingWrites {
+            db,
+            batch: WriteBatch::default(),
+            values: HashMap::new(),
+            deleted_ranges: Vec::new(),
+        }
+    }
+
+    /// Whether the stored value of the key is deleted or replaced by the batch
+    fn is_overwritten(&self, cf: &'static str, key: &[u8]) -> bool {
+        self.values.contains_key(&(cf, key.to_vec()))
+            || self
+                .deleted_ranges
+                .iter()
+                .any(|(range_cf, from, to)| *range_cf == cf && key >= from.as_slice() && key < to.as_slice())
+    }
+
+    /// Read the value of the key as it will be after writing the batch
+    fn get(&self, cf: &'static str, key: &[u8]) -> Result<Option<Vec<u8>>, DataError> {
+        match self.values.get(&(cf, key.to_vec())) {
+            Some(value) => Ok(value.clone()),
+            None if self.is_overwritten(cf, key) => Ok(None),
+            None => self
+                .db
+                .db
+                .get_cf(self.db.cf_handle(cf)?, key)
+                .map_err(|e| DataError::storage(format!("read column family '{}'", cf), e)),
+        }
+    }
+
+    fn put(&mut self, cf: &'static str, key: Vec<u8>, value: Vec<u8>) -> Result<(), DataError> {
+        self.batch.put_cf(self.db.cf_handle(cf)?, &key, &value);
+        self.values.insert((cf, key), Some(value));
+        Ok(())
+    }
+
+    fn delete(&mut self, cf: &'static str, key: Vec<u8>) -> Result<(), DataError> {
+        self.batch.delete_cf(self.db.cf_handle(cf)?, &key);
+        self.values.insert((cf, key), None);
+        Ok(())
+    }
+
+    fn delete_range(&mut self, cf: &'static str, from: Vec<u8>, to: Vec<u8>) -> Result<(), DataError> {
+        self.batch.delete_range_cf(self.db.cf_handle(cf)?, &from, &to);
+        self.values
+            .retain(|(value_cf, key), _| *value_cf != cf || *key < from || *key >= to);
+        self.deleted_ranges.push((cf, from, to));
+        Ok(())
+    }
+
+    /// Stored keys and values with keys in `[lower, upper)`, which are not overwritten
+    /// by the batch
+    ///
+    /// Seeks in total order, since the range may span the id prefixes of several tickers
+    /// or sort prefixes.
+    fn stored_values(
+        &self,
+        cf: &'static str,
+        lower: Vec<u8>,
+        upper: Vec<u8>,
+    ) -> Result<impl Iterator<Item = KeyValue> + '_, DataError> {
+        let mut read_opts = ReadOptions::default();
+        read_opts.set_total_order_seek(true);
+        read_opts.set_iterate_lower_bound(lower);
+        read_opts.set_iterate_upper_bound(upper);
+
+        Ok(self
+            .db
+            .db
+            .iterator_cf_opt(self.db.cf_handle(cf)?, read_opts, IteratorMode::Start)
+            .filter(move |(key, _)| !self.is_overwritten(cf, key)))
+    }
+
+    /// All records of the data type as they will be after writing the batch, by key
+    fn records<T: DeserializeOwned>(&self, data_type: DataType) -> Result<BTreeMap<Vec<u8>, T>, DataError> {
+        let cf = cf_name(data_type);
+        let tag = data_type as u8;
+        let decode = |key: &[u8], value: &[u8]| {
+            value_codec::decode(value).map_err(|e| DataError::serialization(data_type, key_to_string(key), e))
+        };
+
+        let mut records = BTreeMap::new();
+        for (key, value) in self.stored_values(cf, vec![KEY_VERSION, tag], vec![KEY_VERSION, tag + 1])? {
+            records.insert(key.to_vec(), decode(&key, &value)?);
+        }
+        for ((value_cf, key), value) in &self.values {
+            if let (true, Some(value)) = (*value_cf == cf, value) {
+                records.insert(key.clone(), decode(key, value)?);
+            }
+        }
+        Ok(records)
+    }
+
+    /// Store the asset and its index entries, rejecting an ISIN or WKN of another asset
+    fn put_asset(&mut self, asset: &Asset) -> Result<(), DataError> {
+        for (index, id_kind, id) in asset_ids(asset) {
+            if let Some(owner) = self.get(ASSET_INDEX_CF, &asset_index_key(index, id))? {
+                if owner != asset.name.as_bytes() {
+                    return Err(duplicate_id(asset, id_kind, id, &String::from_utf8_lossy(&owner)));
+                }
+            }
+        }
+
+        // removes the index entries of the stored asset, which may differ
+        self.delete_asset(&asset.name)?;
+        for (index, _, id) in asset_ids(asset) {
+            self.put(ASSET_INDEX_CF, asset_index_key(index, id), asset.name.as_bytes().to_vec())?;
+        }
+        self.put(
+            cf_name(DataType::Asset),
+            prefix_key(DataType::Asset, &asset.name),
+            serialize(asset, DataType::Asset, &asset.name)?,
+        )
+    }
+
+    /// Delete the asset with the given name together with its index entries
+    fn delete_asset(&mut self, name: &str) -> Result<(), DataError> {
+        let key = prefix_key(DataType::Asset, name);
+
+        if let Some(value) = self.get(cf_name(DataType::Asset), &key)? {
+            let stored: Asset = value_codec::decode(&value)
+                .map_err(|e| DataError::serialization(DataType::Asset, name, e))?;
+            for (index, _, id) in asset_ids(&stored) {
+                self.delete(ASSET_INDEX_CF, asset_index_key(index, id))?;
+            }
+        }
+
+        self.delete(cf_name(DataType::Asset), key)
+    }
+
+    /// Store the transaction under the key of its booking date together with its index
+    /// entries
+    fn put_transaction(&mut self, sort_prefix: &str, transaction: &Transaction) -> Result<(), DataError> {
+        // removes the stored transaction, whose booking date and asset may differ
+        self.delete_transaction(sort_prefix, transaction.id)?;
+
+        let key = transaction_key(sort_prefix, transaction.cash_flow.date, transaction.id);
+        let record_key = transaction_key_string(sort_prefix, transaction.id);
+        self.put(TRANSACTION_INDEX_CF, transaction_index_key(sort_prefix, transaction.id), key.clone())?;
+        if let Some(asset) = transaction.asset_name() {
+            self.put(ASSET_TRANSACTION_INDEX_CF, asset_transaction_index_key(asset, &key), Vec::new())?;
+        }
+        self.put(
+            cf_name(DataType::Transaction),
+            key,
+            serialize(transaction, DataType::Transaction, &record_key)?,
+        )
+    }
+
+    /// Delete the transaction with the given id together with its index entries
+    fn delete_transaction(&mut self, sort_prefix: &str, id: u128) -> Result<(), DataError> {
+        let index_key = transaction_index_key(sort_prefix, id);
+
+        if let Some(key) = self.get(TRANSACTION_INDEX_CF, &index_key)? {
+            if let Some(value) = self.get(cf_name(DataType::Transaction), &key)? {
+                let stored: Transaction = value_codec::decode(&value).map_err(|e| {
+                    DataError::serialization(DataType::Transaction, transaction_key_string(sort_prefix, id), e)
+                })?;
+                if let Some(asset) = stored.asset_name() {
+                    self.delete(ASSET_TRANSACTION_INDEX_CF, asset_transaction_index_key(asset, &key))?;
+                }
+            }
+            self.delete(cf_name(DataType::Transaction), key)?;
+            self.delete(TRANSACTION_INDEX_CF, index_key)?;
+        }
+        Ok(())
+    }
+
+    /// Add the key updates of a single operation
+    fn add_operation(&mut self, operation: &Operation) -> Result<(), DataError> {
+        match operation {
+            Operation::InsertAsset(asset) | Operation::UpdateAsset(asset) | Operation::UpsertAsset(asset) =>
+                self.put_asset(asset),
+            Operation::DeleteAsset(asset) => self.delete_asset(&asset.name),
+            Operation::InsertTicker(ticker) | Operation::UpdateTicker(ticker) | Operation::UpsertTicker(ticker) => self.put(
+                cf_name(DataType::Ticker),
+                prefix_key(DataType::Ticker, &ticker.name),
+                serialize(ticker, DataType::Ticker, &ticker.name)?,
+            ),
+            Operation::DeleteTicker(ticker) => self.delete(
+                cf_name(DataType::Ticker),
+                prefix_key(DataType::Ticker, &ticker.name),
+            ),
+            Operation::InsertQuote(quote) | Operation::UpdateQuote(quote) | Operation::UpsertQuote(quote) => {
+                let seq = quote.id.unwrap_or(0);
+                self.put(
+                    cf_name(DataType::Quote),
+                    quote_key(&quote.ticker, &quote.time, seq),
+                    serialize(quote, DataType::Quote, &quote_key_string(&quote.ticker, &quote.time, seq))?,
+                )
+            }
+            Operation::DeleteQuote(quote) => self.delete(
+                cf_name(DataType::Quote),
+                quote_key(&quote.ticker, &quote.time, quote.id.unwrap_or(0)),
+            ),
+            Operation::InsertTransaction { sort_prefix, transaction }
+            | Operation::UpdateTransaction { sort_prefix, transaction }
+            | Operation::UpsertTransaction { sort_prefix, transaction } =>
+                self.put_transaction(sort_prefix, transaction),
+            Operation::DeleteTransaction { sort_prefix, transaction } =>
+                self.delete_transaction(sort_prefix, transaction.id),
+        }
+    }
+}
+
+impl<'a> BatchStore for PendingWrites<'a> {
+    fn asset_exists(&mut self, name: &str) -> Result<bool, DataError> {
+        Ok(self.get(cf_name(DataType::Asset), &prefix_key(DataType::Asset, name))?.is_some())
+    }
+
+    fn ticker_exists(&mut self, name: &str) -> Result<bool, DataError> {
+        Ok(self.get(cf_name(DataType::Ticker), &prefix_key(DataType::Ticker, name))?.is_some())
+    }
+
+    fn has_quotes(&mut self, ticker: &str) -> Result<bool, DataError> {
+        let cf = cf_name(DataType::Quote);
+        let lower = prefix_key(DataType::Quote, ticker);
+        let upper = prefix_end(&lower);
+
+        let written = self
+            .values
+            .iter()
+            .any(|((value_cf, key), value)| *value_cf == cf && value.is_some() && *key >= lower && *key < upper);
+        Ok(written || self.stored_values(cf, lower, upper)?.next().is_some())
+    }
+
+    fn asset_tickers(&mut self, asset: &str) -> Result<Vec<Ticker>, DataError> {
+        let tickers: BTreeMap<Vec<u8>, Ticker> = self.records(DataType::Ticker)?;
+        Ok(tickers.into_values().filter(|ticker| ticker.asset == asset).collect())
+    }
+
+    fn asset_transaction(&mut self, asset: &str) -> Result<Option<String>, DataError> {
+        let cf = ASSET_TRANSACTION_INDEX_CF;
+        let prefix = asset_transaction_prefix(asset);
+        let upper = prefix_end(&prefix);
+
+        let written = self
+            .values
+            .iter()
+            .find(|((value_cf, key), value)| *value_cf == cf && value.is_some() && key.starts_with(&prefix))
+            .map(|((_, key), _)| key.clone());
+        let index_key = match written {
+            Some(key) => Some(key),
+            None => self.stored_values(cf, prefix.clone(), upper)?.next().map(|(key, _)| key.to_vec()),
+        };
+        // index keys end with the key of the transaction
+        Ok(index_key.map(|key| key_to_string(&key[prefix.len()..])))
+    }
+
+    fn record_exists(&mut self, operation: &Operation) -> Result<bool, DataError> {
+        let (cf, key) = match operation {
+            Operation::InsertAsset(asset)
+            | Operation::UpdateAsset(asset)
+            | Operation::UpsertAsset(asset)
+            | Operation::DeleteAsset(asset) => (cf_name(DataType::Asset), prefix_key(DataType::Asset, &asset.name)),
+            Operation::InsertTicker(ticker)
+            | Operation::UpdateTicker(ticker)
+            | Operation::UpsertTicker(ticker)
+            | Operation::DeleteTicker(ticker) =>
+                (cf_name(DataType::Ticker), prefix_key(DataType::Ticker, &ticker.name)),
+            Operation::InsertQuote(quote)
+            | Operation::UpdateQuote(quote)
+            | Operation::UpsertQuote(quote)
+            | Operation::DeleteQuote(quote) =>
+                (cf_name(DataType::Quote), quote_key(&quote.ticker, &quote.time, quote.id.unwrap_or(0))),
+            Operation::InsertTransaction { sort_prefix, transaction }
+            | Operation::UpdateTransaction { sort_prefix, transaction }
+            | Operation::UpsertTransaction { sort_prefix, transaction }
+            | Operation::DeleteTransaction { sort_prefix, transaction } =>
+                (TRANSACTION_INDEX_CF, transaction_index_key(sort_prefix, transaction.id)),
+        };
+
+        Ok(self.get(cf, &key)?.is_some())
+    }
+
+    fn max_quote_seq(&mut self, ticker: &str, time: &DateTime<Utc>) -> Result<Option<i64>, DataError> {
+        let cf = cf_name(DataType::Quote);
+        let lower = quote_key(ticker, time, i64::MIN);
+        let upper = next_key(&quote_key(ticker, time, i64::MAX));
+
+        // keys of the same ticker and time order by sequence id
+        let written = self
+            .values
+            .iter()
+            .filter(|((value_cf, key), value)| *value_cf == cf && value.is_some() && *key >= lower && *key < upper)
+            .map(|((_, key), _)| key.clone())
+            .max();
+        let stored = self.stored_values(cf, lower, upper)?.map(|(key, _)| key.to_vec()).last();
+
+        match written.max(stored).as_deref().and_then(decode_key) {
+            Some(Key::Quote { seq, .. }) => Ok(Some(seq)),
+            Some(_) | None => Ok(None),
+        }
+    }
+
+    fn apply(&mut self, operation: &Operation) -> Result<(), DataError> {
+        self.add_operation(operation)
+    }
+
+    fn delete_ticker_quotes(&mut self, ticker: &str) -> Result<(), DataError> {
+        let quote_prefix = prefix_key(DataType::Quote, ticker);
+        let upper = prefix_end(&quote_prefix);
+        self.delete_range(cf_name(DataType::Quote), quote_prefix, upper)?;
+        self.delete(cf_name(DataType::Ticker), prefix_key(DataType::Ticker, ticker))
+    }
+}
+
+/// All writes, including those of single records, are collected in a `WriteBatch`,
+/// which RocksDB applies atomically across all column families
+///
+/// References are checked against the stored records merged with the pending writes,
+/// before anything is written.
+impl BatchHandler for RocksDB {
+    fn write_batch(&mut self, batch: &Batch) -> Result<(), DataError> {
+        self.write(batch).map(|_| ())
+    }
+}
+
+impl RocksDB {
+    /// Write the batch and return the sequence ids of the quotes it inserts
+    pub(super) fn write(&mut self, batch: &Batch) -> Result<Vec<i64>, DataError> {
+        self.check_writable()?;
+        let mut changes = self.feed.collector();
+        let mut pending = PendingWrites::new(self);
+        let quote_ids = apply_batch(&mut pending, batch, self.options.integrity_policy, changes.as_mut())?;
+
+        self.db
+            .write_opt(pending.batch, &self.options.write_options())
+            .map_err(|e| DataError::storage(format!("write batch of {} operations", batch.len()), e))?;
+        self.feed.publish(changes.unwrap_or_default());
+        Ok(quote_ids)
+    }
+
+    /// Write the quotes in a single `WriteBatch`
+    pub(super) fn write_quotes(&mut self, quotes: &[Quote], on_duplicate: OnDuplicate) -> Result<BulkReport, DataError> {
+        self.check_writable()?;
+        let mut changes = self.feed.collector();
+        let mut pending = PendingWrites::new(self);
+        let report = apply_quotes(&mut pending, quotes, on_duplicate, changes.as_mut())?;
+
+        self.db
+            .write_opt(pending.batch, &self.options.write_options())
+            .map_err(|e| DataError::storage(format!("write {} quotes", report.written()), e))?;
+        self.feed.publish(changes.unwrap_or_default());
+        Ok(report)
+    }
+}
+
+/// Only writes through this instance are observed, e.g. a secondary instance does not
+/// report the writes of its primary
+impl SubscriptionHandler for RocksDB {
+    fn subscribe(&mut self, filter: ChangeFilter) -> Receiver<Change> {
+        self.feed.subscribe(filter)
+    }
+}
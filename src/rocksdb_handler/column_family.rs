@@ -0,0 +1,104 @@
+//! Column family layout of the RocksDB backend
+//!
+//! Each data type is stored in a column family of its own, such that the options can be
+//! tuned to its access pattern. Quotes and transactions are mostly read by range scans
+//! over a single ticker or sort prefix, hence their column families use the id prefix of
+//! the keys for bloom filters. Assets and tickers are few and read by point lookups,
+//! as is the asset index, which maps ISINs and WKNs to asset names. The transaction
+//! index maps transaction ids to the keys of the transactions, which are ordered by
+//! booking date, and the asset transaction index holds the keys of the transactions of
+//! each asset. Both are tuned like the transactions.
+use rocksdb::{BlockBasedOptions, Cache, ColumnFamilyDescriptor, Options, SliceTransform};
+
+use super::key_codec::{has_id_prefix, id_prefix};
+use crate::data_handler::DataType;
+
+/// Data types with a column family of their own
+pub const DATA_TYPES: [DataType; 4] = [
+    DataType::Asset,
+    DataType::Quote,
+    DataType::Ticker,
+    DataType::Transaction,
+];
+
+/// Column family of the secondary indexes of assets
+pub const ASSET_INDEX_CF: &str = "asset_index";
+
+/// Column family of the index of transactions by id
+pub const TRANSACTION_INDEX_CF: &str = "transaction_index";
+
+/// Column family of the index of transactions by the asset they refer to
+pub const ASSET_TRANSACTION_INDEX_CF: &str = "asset_transaction_index";
+
+/// Bits per key of the bloom filters
+const BLOOM_BITS_PER_KEY: f64 = 10.0;
+
+/// Share of the memtable size used for the prefix bloom filter of the memtable
+const MEMTABLE_PREFIX_BLOOM_RATIO: f64 = 0.1;
+
+pub fn cf_name(data_type: DataType) -> &'static str {
+    match data_type {
+        DataType::Asset => "assets",
+        DataType::Quote => "quotes",
+        DataType::Ticker => "tickers",
+        DataType::Transaction => "transactions",
+    }
+}
+
+/// Options of the column family of the data type, derived from the database options
+/// and sharing the given block cache
+pub fn cf_options(data_type: DataType, db_opts: &Options, cache: Option<&Cache>) -> Options {
+    let mut opts = db_opts.clone();
+    let mut table_opts = BlockBasedOptions::default();
+    table_opts.set_bloom_filter(BLOOM_BITS_PER_KEY, false);
+    if let Some(cache) = cache {
+        table_opts.set_block_cache(cache);
+    }
+
+    match data_type {
+        DataType::Quote | DataType::Transaction => {
+            opts.set_prefix_extractor(SliceTransform::create(
+                "ticky.id_prefix",
+                id_prefix,
+                Some(has_id_prefix),
+            ));
+            opts.set_memtable_prefix_bloom_ratio(MEMTABLE_PREFIX_BLOOM_RATIO);
+        }
+        DataType::Asset | DataType::Ticker => {
+            opts.set_optimize_filters_for_hits(true);
+        }
+    }
+
+    opts.set_block_based_table_factory(&table_opts);
+    opts
+}
+
+/// Names of the column families of all data types and the indexes
+pub fn cf_names() -> Vec<&'static str> {
+    let mut names: Vec<_> = DATA_TYPES.iter().map(|data_type| cf_name(*data_type)).collect();
+    names.push(ASSET_INDEX_CF);
+    names.push(TRANSACTION_INDEX_CF);
+    names.push(ASSET_TRANSACTION_INDEX_CF);
+    names
+}
+
+/// Data type whose options the column family of the given name is tuned with
+fn tuned_like(name: &str) -> Option<DataType> {
+    match name {
+        ASSET_INDEX_CF => Some(DataType::Asset),
+        TRANSACTION_INDEX_CF | ASSET_TRANSACTION_INDEX_CF => Some(DataType::Transaction),
+        _ => DATA_TYPES.iter().copied().find(|data_type| cf_name(*data_type) == name),
+    }
+}
+
+/// Descriptors of the column families of the given names, which have to be names of
+/// `cf_names`
+pub fn cf_descriptors(names: &[&str], db_opts: &Options, cache: Option<&Cache>) -> Vec<ColumnFamilyDescriptor> {
+    names
+        .iter()
+        .map(|name| {
+            let data_type = tuned_like(name).expect("unknown column family");
+            ColumnFamilyDescriptor::new(*name, cf_options(data_type, db_opts, cache))
+        })
+        .collect()
+}
@@ -0,0 +1,107 @@
+//! Implementation of rocksdb integrity handler
+use std::collections::{BTreeMap, HashSet};
+
+use super::key_codec::{decode_key, key_to_string, Key, KEY_VERSION};
+use super::value_codec;
+use super::RocksDB;
+
+use crate::asset::Asset;
+use crate::data_handler::integrity::{OrphanedQuotes, OrphanedTicker, OrphanedTransaction};
+use crate::data_handler::{DataError, DataType, IntegrityHandler, IntegrityPolicy, IntegrityReport};
+use crate::quote::Ticker;
+use crate::transaction::Transaction;
+
+use rocksdb::{DBIterator, IteratorMode, ReadOptions};
+
+
+impl RocksDB {
+    /// Iterate over all keys and values of the data type in key order
+    fn scan(&self, data_type: DataType) -> Result<DBIterator<'_>, DataError> {
+        let tag = data_type as u8;
+        let mut read_opts = ReadOptions::default();
+        read_opts.set_total_order_seek(true);
+        read_opts.set_iterate_lower_bound(vec![KEY_VERSION, tag]);
+        read_opts.set_iterate_upper_bound(vec![KEY_VERSION, tag + 1]);
+
+        Ok(self.db.iterator_cf_opt(self.cf(data_type)?, read_opts, IteratorMode::Start))
+    }
+}
+
+fn invalid_key(data_type: DataType, key: &[u8]) -> DataError {
+    DataError::serialization(data_type, key_to_string(key), format!("invalid {} key", data_type))
+}
+
+impl IntegrityHandler for RocksDB {
+    fn integrity_policy(&self) -> IntegrityPolicy {
+        self.options.integrity_policy
+    }
+
+    fn set_integrity_policy(&mut self, policy: IntegrityPolicy) {
+        self.options.integrity_policy = policy;
+    }
+
+    fn check_integrity(&mut self) -> Result<IntegrityReport, DataError> {
+        let mut report = IntegrityReport::default();
+        let assets: HashSet<String> = self
+            .reader()
+            .all_records::<Asset>(DataType::Asset)?
+            .into_iter()
+            .map(|asset| asset.name)
+            .collect();
+        let tickers: Vec<Ticker> = self.reader().all_records(DataType::Ticker)?;
+
+        for ticker in &tickers {
+            if !assets.contains(&ticker.asset) {
+                report.tickers.push(OrphanedTicker {
+                    ticker: ticker.name.clone(),
+                    asset: ticker.asset.clone(),
+                });
+            }
+        }
+
+        // quotes are counted by their keys, without reading the values
+        let ticker_names: HashSet<&str> = tickers.iter().map(|ticker| ticker.name.as_str()).collect();
+        let mut quote_counts: BTreeMap<String, usize> = BTreeMap::new();
+        for (key, _) in self.scan(DataType::Quote)? {
+            match decode_key(&key) {
+                Some(Key::Quote { ticker, .. }) => {
+                    if !ticker_names.contains(ticker.as_str()) {
+                        *quote_counts.entry(ticker).or_default() += 1;
+                    }
+                }
+                _ => return Err(invalid_key(DataType::Quote, &key)),
+            }
+        }
+        report.quotes = quote_counts
+            .into_iter()
+            .map(|(ticker, count)| OrphanedQuotes { ticker, count })
+            .collect();
+
+        for (key, value) in self.scan(DataType::Transaction)? {
+            let (sort_prefix, id) = match decode_key(&key) {
+                Some(Key::Transaction { sort_prefix, id, .. }) => (sort_prefix, id),
+                _ => return Err(invalid_key(DataType::Transaction, &key)),
+            };
+            let transaction: Transaction = value_codec::decode(&value)
+                .map_err(|e| DataError::serialization(DataType::Transaction, key_to_string(&key), e))?;
+
+            match transaction.asset_name() {
+                Some(asset) if !assets.contains(asset) => {
+                    report.transactions.push(OrphanedTransaction {
+                        sort_prefix,
+                        id,
+                        asset: asset.to_string(),
+                    })
+                }
+                _ => {}
+            }
+        }
+
+        // keys order names by length first
+        report.tickers.sort_by(|a, b| a.ticker.cmp(&b.ticker));
+        report
+            .transactions
+            .sort_by(|a, b| (&a.sort_prefix, a.id).cmp(&(&b.sort_prefix, b.id)));
+        Ok(report)
+    }
+}
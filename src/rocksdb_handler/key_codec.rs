@@ -0,0 +1,394 @@
+//! Order-preserving binary encoding of RocksDB keys
+//!
+//! Every key starts with the codec version, the data type tag and the length-prefixed
+//! primary id, i.e. the asset name, ticker name or transaction sort prefix:
+//!
+//! ```text
+//! | version: u8 | data type: u8 | id length: u32 | id |
+//! ```
+//!
+//! Quote keys append the quote time, stored as sign-flipped seconds since the epoch
+//! followed by the nanoseconds, and the sign-flipped sequence id. Transaction keys
+//! append the booking date, stored as sign-flipped days since the common era, and the
+//! transaction id. All integers are stored big-endian, such that the byte-wise order of
+//! the keys of a ticker or sort prefix matches their time order, including times before
+//! 1970.
+//!
+//! The version, data type and id form the prefix of quote and transaction keys, which
+//! is used by the prefix bloom filters of their column families.
+//!
+//! Entries of the asset index map an ISIN or WKN to the asset name. Their keys have the
+//! same layout as asset keys, with the tag of the index instead of the data type tag.
+//! Entries of the transaction index map the sort prefix and id of a transaction to its
+//! key, their keys append the transaction id to the tagged sort prefix. Entries of the
+//! asset transaction index have empty values, their keys append the key of a transaction
+//! to the tagged name of the asset it refers to.
+use chrono::{DateTime, Datelike, NaiveDate, TimeZone, Utc};
+use std::convert::TryInto;
+use std::fmt;
+
+use crate::data_handler::{quote_key_string, transaction_key_string, DataType};
+
+/// Version of the key layout, stored as first byte of each key
+pub const KEY_VERSION: u8 = 1;
+
+/// Length of the encoded time of quote keys
+const TIME_LENGTH: usize = 12;
+
+/// Length of the encoded booking date of transaction keys
+const DATE_LENGTH: usize = 4;
+
+/// Tag of the keys of the transaction index, distinct from the data type tags
+pub const TRANSACTION_INDEX_TAG: u8 = 0x20;
+
+/// Tag of the keys of the asset transaction index, distinct from the data type tags
+pub const ASSET_TRANSACTION_INDEX_TAG: u8 = 0x21;
+
+/// Maximum length of the part of a key following the id
+const MAX_SUFFIX_LENGTH: usize = TIME_LENGTH + 8;
+
+/// Decoded key of any data type
+#[derive(Debug, Clone, PartialEq)]
+pub enum Key {
+    Asset { name: String },
+    Quote { ticker: String, time: DateTime<Utc>, seq: i64 },
+    Ticker { name: String },
+    Transaction { sort_prefix: String, date: NaiveDate, id: u128 },
+}
+
+impl fmt::Display for Key {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Key::Asset { name } | Key::Ticker { name } => write!(f, "{}", name),
+            Key::Quote { ticker, time, seq } => write!(f, "{}", quote_key_string(ticker, time, *seq)),
+            Key::Transaction { sort_prefix, id, .. } =>
+                write!(f, "{}", transaction_key_string(sort_prefix, *id)),
+        }
+    }
+}
+
+/// Readable form of an encoded key as used in errors, falling back to the raw bytes
+/// for keys that can't be decoded
+pub fn key_to_string(key: &[u8]) -> String {
+    match decode_key(key) {
+        Some(key) => key.to_string(),
+        None => format!("{:?}", key),
+    }
+}
+
+fn flip_sign(value: i64) -> [u8; 8] {
+    ((value as u64) ^ (1 << 63)).to_be_bytes()
+}
+
+fn unflip_sign(bytes: [u8; 8]) -> i64 {
+    (u64::from_be_bytes(bytes) ^ (1 << 63)) as i64
+}
+
+pub fn data_type_from_tag(tag: u8) -> Option<DataType> {
+    match tag {
+        tag if tag == DataType::Asset as u8 => Some(DataType::Asset),
+        tag if tag == DataType::Quote as u8 => Some(DataType::Quote),
+        tag if tag == DataType::Ticker as u8 => Some(DataType::Ticker),
+        tag if tag == DataType::Transaction as u8 => Some(DataType::Transaction),
+        _ => None,
+    }
+}
+
+/// Secondary index of assets, with tags distinct from the data type tags
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum AssetIndex {
+    Isin = 0x10,
+    Wkn = 0x11,
+}
+
+/// Key of assets and tickers and common prefix of all quote or transaction keys
+/// of a ticker or sort prefix
+pub fn prefix_key(data_type: DataType, id: &str) -> Vec<u8> {
+    tagged_key(data_type as u8, id)
+}
+
+/// Key of the entry of the asset index for the given ISIN or WKN
+pub fn asset_index_key(index: AssetIndex, id: &str) -> Vec<u8> {
+    tagged_key(index as u8, id)
+}
+
+fn tagged_key(tag: u8, id: &str) -> Vec<u8> {
+    let mut key = Vec::with_capacity(6 + id.len() + 16);
+
+    key.push(KEY_VERSION);
+    key.push(tag);
+    key.extend_from_slice(&(id.len() as u32).to_be_bytes());
+    key.extend_from_slice(id.as_bytes());
+
+    key
+}
+
+/// Encode time such that byte-wise and chronological order coincide
+pub fn encode_time(time: &DateTime<Utc>) -> [u8; TIME_LENGTH] {
+    let mut bytes = [0; TIME_LENGTH];
+
+    bytes[..8].copy_from_slice(&flip_sign(time.timestamp()));
+    bytes[8..].copy_from_slice(&time.timestamp_subsec_nanos().to_be_bytes());
+
+    bytes
+}
+
+fn decode_time(bytes: &[u8]) -> Option<DateTime<Utc>> {
+    let seconds = unflip_sign(bytes.get(..8)?.try_into().ok()?);
+    let nanos = u32::from_be_bytes(bytes.get(8..TIME_LENGTH)?.try_into().ok()?);
+
+    Utc.timestamp_opt(seconds, nanos).single()
+}
+
+pub fn quote_key(ticker: &str, time: &DateTime<Utc>, seq: i64) -> Vec<u8> {
+    let mut key = prefix_key(DataType::Quote, ticker);
+
+    key.extend_from_slice(&encode_time(time));
+    key.extend_from_slice(&flip_sign(seq));
+
+    key
+}
+
+/// Encode booking date such that byte-wise and chronological order coincide
+fn encode_date(date: NaiveDate) -> [u8; DATE_LENGTH] {
+    ((date.num_days_from_ce() as u32) ^ (1 << 31)).to_be_bytes()
+}
+
+fn decode_date(bytes: &[u8]) -> Option<NaiveDate> {
+    let days = u32::from_be_bytes(bytes.try_into().ok()?) ^ (1 << 31);
+    NaiveDate::from_num_days_from_ce_opt(days as i32)
+}
+
+pub fn transaction_key(sort_prefix: &str, date: NaiveDate, id: u128) -> Vec<u8> {
+    let mut key = prefix_key(DataType::Transaction, sort_prefix);
+
+    key.extend_from_slice(&encode_date(date));
+    key.extend_from_slice(&id.to_be_bytes());
+
+    key
+}
+
+/// First key of the transactions of the sort prefix booked on the given date
+pub fn transaction_date_key(sort_prefix: &str, date: NaiveDate) -> Vec<u8> {
+    let mut key = prefix_key(DataType::Transaction, sort_prefix);
+    key.extend_from_slice(&encode_date(date));
+    key
+}
+
+/// Key of the entry of the transaction index for the given sort prefix and id
+pub fn transaction_index_key(sort_prefix: &str, id: u128) -> Vec<u8> {
+    let mut key = tagged_key(TRANSACTION_INDEX_TAG, sort_prefix);
+    key.extend_from_slice(&id.to_be_bytes());
+    key
+}
+
+/// Common prefix of the entries of the asset transaction index for the given asset
+pub fn asset_transaction_prefix(asset: &str) -> Vec<u8> {
+    tagged_key(ASSET_TRANSACTION_INDEX_TAG, asset)
+}
+
+/// Key of the entry of the asset transaction index for the transaction with the given
+/// key, which refers to the given asset
+pub fn asset_transaction_index_key(asset: &str, transaction_key: &[u8]) -> Vec<u8> {
+    let mut key = asset_transaction_prefix(asset);
+    key.extend_from_slice(transaction_key);
+    key
+}
+
+/// Exclusive upper bound of all keys starting with the given prefix key
+///
+/// Unlike the lexicographic successor of the prefix, the bound shares the prefix with
+/// the keys it bounds, such that seeks to it still hit the prefix bloom filter.
+pub fn prefix_end(prefix: &[u8]) -> Vec<u8> {
+    let mut key = prefix.to_vec();
+    key.extend_from_slice(&[u8::MAX; MAX_SUFFIX_LENGTH + 1]);
+    key
+}
+
+/// Smallest key greater than the given key, used as exclusive bound to include the key
+pub fn next_key(key: &[u8]) -> Vec<u8> {
+    let mut key = key.to_vec();
+    key.push(0);
+    key
+}
+
+fn id_prefix_length(key: &[u8]) -> Option<usize> {
+    let length = u32::from_be_bytes(key.get(2..6)?.try_into().ok()?) as usize;
+    6usize.checked_add(length).filter(|end| *end <= key.len())
+}
+
+/// Prefix of a key up to and including the id, used as prefix extractor
+pub fn id_prefix(key: &[u8]) -> &[u8] {
+    match id_prefix_length(key) {
+        Some(length) => &key[..length],
+        None => key,
+    }
+}
+
+/// Check whether the key is long enough to contain the id prefix
+pub fn has_id_prefix(key: &[u8]) -> bool {
+    id_prefix_length(key).is_some()
+}
+
+/// Decode key, returns `None` if the key is not a valid key of the current version
+pub fn decode_key(key: &[u8]) -> Option<Key> {
+    if *key.first()? != KEY_VERSION {
+        return None;
+    }
+
+    let data_type = data_type_from_tag(*key.get(1)?)?;
+    let length = u32::from_be_bytes(key.get(2..6)?.try_into().ok()?) as usize;
+    let id = String::from_utf8(key.get(6..6 + length)?.to_vec()).ok()?;
+    let rest = &key[6 + length..];
+
+    match data_type {
+        DataType::Asset if rest.is_empty() => Some(Key::Asset { name: id }),
+        DataType::Ticker if rest.is_empty() => Some(Key::Ticker { name: id }),
+        DataType::Quote if rest.len() == TIME_LENGTH + 8 => Some(Key::Quote {
+            ticker: id,
+            time: decode_time(&rest[..TIME_LENGTH])?,
+            seq: unflip_sign(rest[TIME_LENGTH..].try_into().ok()?),
+        }),
+        DataType::Transaction if rest.len() == DATE_LENGTH + 16 => Some(Key::Transaction {
+            sort_prefix: id,
+            date: decode_date(&rest[..DATE_LENGTH])?,
+            id: u128::from_be_bytes(rest[DATE_LENGTH..].try_into().ok()?),
+        }),
+        _ => None,
+    }
+}
+
+/// Decode the primary id of a key, i.e. the asset name, ticker name or sort prefix,
+/// regardless of the layout of the rest of the key
+pub fn decode_id(key: &[u8]) -> Option<String> {
+    String::from_utf8(key.get(6..id_prefix_length(key)?)?.to_vec()).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use quickcheck::{Arbitrary, Gen};
+    use quickcheck_macros::quickcheck;
+
+    /// Arbitrary time within about 10,000 years around the epoch
+    #[derive(Debug, Clone)]
+    struct Time(DateTime<Utc>);
+
+    impl Arbitrary for Time {
+        fn arbitrary<G: Gen>(g: &mut G) -> Time {
+            const RANGE: u64 = 2 * 315_569_520_000;
+            let seconds = (g.next_u64() % RANGE) as i64 - (RANGE / 2) as i64;
+            let nanos = g.next_u32() % 1_000_000_000;
+
+            Time(Utc.timestamp(seconds, nanos))
+        }
+    }
+
+    #[quickcheck]
+    fn prop_quote_key_order_matches_time_order(a: Time, b: Time, seq_a: i64, seq_b: i64) -> bool {
+        let key_a = quote_key("AAPL", &a.0, seq_a);
+        let key_b = quote_key("AAPL", &b.0, seq_b);
+
+        key_a.cmp(&key_b) == (a.0, seq_a).cmp(&(b.0, seq_b))
+    }
+
+    #[quickcheck]
+    fn prop_quote_keys_of_tickers_do_not_interleave(
+        ticker_a: String,
+        ticker_b: String,
+        a: Time,
+        b: Time,
+    ) -> bool {
+        let key_a = quote_key(&ticker_a, &a.0, 0);
+        let key_b = quote_key(&ticker_b, &b.0, 0);
+
+        ticker_a == ticker_b
+            || key_a.cmp(&key_b)
+                == prefix_key(DataType::Quote, &ticker_a).cmp(&prefix_key(DataType::Quote, &ticker_b))
+    }
+
+    #[quickcheck]
+    fn prop_transaction_key_order_matches_date_order(a: Time, b: Time, id_a: u64, id_b: u64) -> bool {
+        let (date_a, date_b) = (a.0.naive_utc().date(), b.0.naive_utc().date());
+        let (id_a, id_b) = ((id_a as u128) << 64, (id_b as u128) << 64);
+        let key_a = transaction_key("book", date_a, id_a);
+        let key_b = transaction_key("book", date_b, id_b);
+
+        key_a.cmp(&key_b) == (date_a, id_a).cmp(&(date_b, id_b))
+            && (transaction_date_key("book", date_a) <= key_a)
+    }
+
+    #[quickcheck]
+    fn prop_prefix_end_bounds_prefix(ticker_a: String, ticker_b: String, time: Time, seq: i64) -> bool {
+        let prefix = prefix_key(DataType::Quote, &ticker_a);
+        let key = quote_key(&ticker_b, &time.0, seq);
+
+        (ticker_a == ticker_b) == (key >= prefix && key < prefix_end(&prefix))
+    }
+
+    #[quickcheck]
+    fn prop_id_prefix(ticker: String, time: Time, seq: i64) -> bool {
+        let prefix = prefix_key(DataType::Quote, &ticker);
+        let key = quote_key(&ticker, &time.0, seq);
+
+        id_prefix(&key) == prefix.as_slice()
+            && id_prefix(&prefix) == prefix.as_slice()
+            && id_prefix(&prefix_end(&prefix)) == prefix.as_slice()
+            && id_prefix(&next_key(&key)) == prefix.as_slice()
+    }
+
+    #[quickcheck]
+    fn prop_quote_key_roundtrip(ticker: String, time: Time, seq: i64) -> bool {
+        decode_key(&quote_key(&ticker, &time.0, seq))
+            == Some(Key::Quote {
+                ticker,
+                time: time.0,
+                seq,
+            })
+    }
+
+    #[quickcheck]
+    fn prop_transaction_key_roundtrip(sort_prefix: String, time: Time, id: u128) -> bool {
+        let date = time.0.naive_utc().date();
+        decode_key(&transaction_key(&sort_prefix, date, id)) == Some(Key::Transaction { sort_prefix, date, id })
+    }
+
+    #[quickcheck]
+    fn prop_decode_id(sort_prefix: String, id: u128) -> bool {
+        decode_id(&transaction_index_key(&sort_prefix, id)) == Some(sort_prefix.clone())
+            && decode_id(&prefix_key(DataType::Transaction, &sort_prefix)) == Some(sort_prefix)
+    }
+
+    #[test]
+    fn test_decode_key() {
+        assert_eq!(
+            decode_key(&prefix_key(DataType::Asset, "Apple")),
+            Some(Key::Asset {
+                name: "Apple".to_string()
+            })
+        );
+        assert_eq!(
+            decode_key(&prefix_key(DataType::Ticker, "AAPL")),
+            Some(Key::Ticker {
+                name: "AAPL".to_string()
+            })
+        );
+        assert_eq!(decode_key(b"0:Apple:"), None);
+        assert_eq!(decode_key(&prefix_key(DataType::Quote, "AAPL")), None);
+    }
+
+    #[test]
+    fn test_has_id_prefix() {
+        assert!(has_id_prefix(&prefix_key(DataType::Quote, "AAPL")));
+        assert!(has_id_prefix(&prefix_key(DataType::Quote, "")));
+        assert!(!has_id_prefix(&[KEY_VERSION, DataType::Quote as u8, 0, 0, 0, 4, b'A']));
+        assert!(!has_id_prefix(&[KEY_VERSION]));
+    }
+
+    #[test]
+    fn test_time_before_epoch() {
+        let before = Utc.ymd(1969, 12, 31).and_hms(23, 59, 59);
+        let after = Utc.ymd(1970, 1, 1).and_hms(0, 0, 1);
+        assert!(encode_time(&before) < encode_time(&after));
+    }
+}
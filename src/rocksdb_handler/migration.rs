@@ -0,0 +1,660 @@
+//! Migration of databases written by earlier versions to the current schema
+//!
+//! The schema version is stored in the default column family, databases without one
+//! were written before schema versioning and have version 0. The migrations are:
+//!
+//! 1. Earlier versions stored all records in the default column family and formatted
+//!    keys as `{data type}:{id}:{suffix}` strings. Since these start with an ASCII digit,
+//!    they are easy to tell apart from keys of the binary layout, which start with the
+//!    key version. All records are moved to the column family of their data type.
+//! 2. Values were stored as plain bincode, which is wrapped in the versioned format of
+//!    `value_codec`.
+//! 3. The asset index is built from the stored assets.
+//! 4. Transactions were keyed by id, i.e. by creation time. They are moved to keys
+//!    ordered by booking date and indexed by id.
+//! 5. Transactions referring to an asset are indexed by the asset.
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::sync::atomic;
+
+use super::asset_handler::asset_ids;
+use super::column_family::{ASSET_INDEX_CF, ASSET_TRANSACTION_INDEX_CF, DATA_TYPES, TRANSACTION_INDEX_CF};
+use super::key_codec::{
+    asset_index_key, asset_transaction_index_key, data_type_from_tag, decode_id, key_to_string, next_key,
+    prefix_key, quote_key, transaction_index_key, transaction_key, KEY_VERSION,
+};
+use super::options::AccessMode;
+use super::{value_codec, RocksDB};
+
+use crate::asset::Asset;
+use crate::data_handler::asset_handler::duplicate_id;
+use crate::data_handler::{DataError, DataType};
+use crate::quote::{Quote, Ticker};
+use crate::transaction::Transaction;
+
+use rocksdb::{Direction, IteratorMode, ReadOptions, WriteBatch};
+use serde::de::DeserializeOwned;
+
+/// Schema version written by this version
+pub const SCHEMA_VERSION: u32 = 5;
+
+const SCHEMA_VERSION_KEY: &[u8] = b"ticky.schema_version";
+
+/// Index of the column family and last key rewritten by an unfinished migration
+const MIGRATION_PROGRESS_KEY: &[u8] = b"ticky.migration_progress";
+
+/// Upgrade of the schema from the previous version to `version`
+struct Migration {
+    version: u32,
+    run: fn(&RocksDB) -> Result<usize, DataError>,
+}
+
+const MIGRATIONS: [Migration; 5] = [
+    Migration {
+        version: 1,
+        run: RocksDB::migrate_legacy_keys,
+    },
+    Migration {
+        version: 2,
+        run: RocksDB::wrap_values,
+    },
+    Migration {
+        version: 3,
+        run: RocksDB::index_assets,
+    },
+    Migration {
+        version: 4,
+        run: RocksDB::index_transactions,
+    },
+    Migration {
+        version: 5,
+        run: RocksDB::index_asset_transactions,
+    },
+];
+
+/// Number of records rewritten per write batch
+const MIGRATION_BATCH_SIZE: usize = 10_000;
+
+fn is_legacy_key(key: &[u8]) -> bool {
+    key.len() > 1 && key[0].is_ascii_digit() && key[1] == b':'
+}
+
+fn deserialize<T: DeserializeOwned>(value: &[u8], data_type: DataType, key: &str) -> Result<T, DataError> {
+    bincode::deserialize(value).map_err(|e| DataError::serialization(data_type, key, e))
+}
+
+fn invalid_legacy_key(key: &str) -> DataError {
+    DataError::parse(key, "invalid legacy key, expected '{data type}:{id}:{suffix}'")
+}
+
+/// Build the binary key of a record stored under a legacy key
+fn convert_legacy_key(key: &[u8], value: &[u8]) -> Result<(DataType, Vec<u8>), DataError> {
+    let key = std::str::from_utf8(key).map_err(|e| DataError::parse(String::from_utf8_lossy(key), e))?;
+    let (tag, id) = match key.find(':') {
+        Some(pos) => (&key[..pos], &key[pos + 1..]),
+        None => return Err(invalid_legacy_key(key)),
+    };
+    let data_type = tag
+        .parse::<u8>()
+        .ok()
+        .and_then(data_type_from_tag)
+        .ok_or_else(|| invalid_legacy_key(key))?;
+
+    let key = match data_type {
+        DataType::Asset => {
+            let asset: Asset = deserialize(value, data_type, key)?;
+            prefix_key(DataType::Asset, &asset.name)
+        }
+        DataType::Ticker => {
+            let ticker: Ticker = deserialize(value, data_type, key)?;
+            prefix_key(DataType::Ticker, &ticker.name)
+        }
+        DataType::Quote => {
+            let quote: Quote = deserialize(value, data_type, key)?;
+            quote_key(&quote.ticker, &quote.time, quote.id.unwrap_or(0))
+        }
+        DataType::Transaction => {
+            // the sort prefix may contain colons, but the trailing id does not
+            let sort_prefix = match id.rfind(':') {
+                Some(pos) => &id[..pos],
+                None => return Err(invalid_legacy_key(key)),
+            };
+            let transaction: Transaction = deserialize(value, data_type, key)?;
+            transaction_key(sort_prefix, transaction.cash_flow.date, transaction.id)
+        }
+    };
+
+    Ok((data_type, key))
+}
+
+/// Data type and key of a record in the default column family, or `None` if the key
+/// is unknown to this version
+fn migrated_key(key: &[u8], value: &[u8]) -> Result<Option<(DataType, Vec<u8>)>, DataError> {
+    if is_legacy_key(key) {
+        return convert_legacy_key(key, value).map(Some);
+    }
+
+    match key {
+        [KEY_VERSION, tag, ..] => {
+            Ok(data_type_from_tag(*tag).map(|data_type| (data_type, key.to_vec())))
+        }
+        _ => Ok(None),
+    }
+}
+
+impl RocksDB {
+    /// Schema version of the database, 0 for databases written before schema versioning
+    pub fn schema_version(&self) -> Result<u32, DataError> {
+        let version = self
+            .db
+            .get(SCHEMA_VERSION_KEY)
+            .map_err(|e| DataError::storage("read schema version", e))?;
+
+        match version {
+            Some(version) => version
+                .as_slice()
+                .try_into()
+                .map(u32::from_be_bytes)
+                .map_err(|e| DataError::storage("read schema version", e)),
+            None => Ok(0),
+        }
+    }
+
+    /// Store the schema version, which also ends the migration to it
+    fn set_schema_version(&self, version: u32) -> Result<(), DataError> {
+        let mut batch = WriteBatch::default();
+        batch.put(SCHEMA_VERSION_KEY, version.to_be_bytes());
+        batch.delete(MIGRATION_PROGRESS_KEY);
+        self.write_migration(batch)
+    }
+
+    fn write_migration(&self, batch: WriteBatch) -> Result<(), DataError> {
+        self.db
+            .write_opt(batch, &self.options.write_options())
+            .map_err(|e| DataError::storage("write migrated records", e))
+    }
+
+    /// Write a batch of rewritten records together with the position of the last one
+    fn write_progress(&self, mut batch: WriteBatch, index: usize, last_key: &[u8]) -> Result<(), DataError> {
+        batch.put(MIGRATION_PROGRESS_KEY, [&[index as u8], last_key].concat());
+        self.write_migration(batch)
+    }
+
+    /// Upgrade the database to the current schema version and return the number of
+    /// migrated records
+    ///
+    /// Migrations can be interrupted at any time and continue when run again. Databases
+    /// of a newer schema version are rejected.
+    pub fn migrate(&self) -> Result<usize, DataError> {
+        let version = self.schema_version()?;
+        if version > SCHEMA_VERSION {
+            return Err(DataError::IncompatibleSchema {
+                found: version,
+                supported: SCHEMA_VERSION,
+            });
+        }
+
+        let mut migrated = 0;
+        for migration in MIGRATIONS.iter().filter(|migration| migration.version > version) {
+            migrated += (migration.run)(self)?;
+            self.set_schema_version(migration.version)?;
+        }
+        self.outdated.store(false, atomic::Ordering::Release);
+
+        Ok(migrated)
+    }
+
+    /// Check the schema version of a database that has just been opened, migrating it
+    /// if it is outdated, opened read-write and `migrate_on_open` is set
+    ///
+    /// Outdated databases opened read-write without `migrate_on_open` are left as they
+    /// are, to be upgraded by an explicit call to `migrate`. Until then all writes fail,
+    /// since the migration would misread records written in the current layout.
+    pub(super) fn check_schema(&self) -> Result<(), DataError> {
+        let version = self.schema_version()?;
+        let writable = self.options.access_mode == AccessMode::ReadWrite;
+
+        match version.cmp(&SCHEMA_VERSION) {
+            Ordering::Equal => Ok(()),
+            Ordering::Less if writable && self.options.migrate_on_open => self.migrate().map(|_| ()),
+            Ordering::Less if writable => {
+                self.outdated.store(true, atomic::Ordering::Release);
+                Ok(())
+            }
+            _ => Err(DataError::IncompatibleSchema {
+                found: version,
+                supported: SCHEMA_VERSION,
+            }),
+        }
+    }
+
+    /// Reject writes to a database that has not been migrated to the current schema
+    pub(super) fn check_writable(&self) -> Result<(), DataError> {
+        if !self.outdated.load(atomic::Ordering::Acquire) {
+            return Ok(());
+        }
+        Err(DataError::IncompatibleSchema {
+            found: self.schema_version()?,
+            supported: SCHEMA_VERSION,
+        })
+    }
+
+    /// Move all records stored in the default column family by earlier versions to the
+    /// column family of their data type, converting legacy keys to the binary key layout,
+    /// and return the number of migrated records
+    ///
+    /// Each old key is deleted in the same write batch that inserts the new key,
+    /// hence an interrupted migration can simply be run again.
+    fn migrate_legacy_keys(&self) -> Result<usize, DataError> {
+        let mut batch = WriteBatch::default();
+        let mut migrated = 0;
+
+        for (key, value) in self.db.iterator(IteratorMode::Start) {
+            let (data_type, new_key) = match migrated_key(&key, &value)? {
+                Some(migrated_key) => migrated_key,
+                None => continue,
+            };
+
+            batch.put_cf(self.cf(data_type)?, new_key, &value);
+            batch.delete(&key);
+            migrated += 1;
+
+            if batch.len() >= 2 * MIGRATION_BATCH_SIZE {
+                self.write_migration(std::mem::take(&mut batch))?;
+            }
+        }
+
+        self.write_migration(batch)?;
+        Ok(migrated)
+    }
+
+    /// Wrap all values stored as plain bincode in the versioned value format and return
+    /// the number of rewritten records
+    ///
+    /// Each write batch also stores the last rewritten key, such that an interrupted
+    /// migration continues after it instead of wrapping values twice.
+    fn wrap_values(&self) -> Result<usize, DataError> {
+        let progress = self
+            .db
+            .get(MIGRATION_PROGRESS_KEY)
+            .map_err(|e| DataError::storage("read migration progress", e))?;
+        let (first, resume_key) = match progress.as_deref() {
+            Some([index, last_key @ ..]) => (*index as usize, Some(next_key(last_key))),
+            _ => (0, None),
+        };
+        let mut migrated = 0;
+
+        for (index, data_type) in DATA_TYPES.iter().enumerate().skip(first) {
+            let cf = self.cf(*data_type)?;
+            let mut read_opts = ReadOptions::default();
+            read_opts.set_total_order_seek(true);
+            let mode = match &resume_key {
+                Some(key) if index == first => IteratorMode::From(key, Direction::Forward),
+                _ => IteratorMode::Start,
+            };
+
+            let mut batch = WriteBatch::default();
+            let mut last_key = Box::default();
+            for (key, value) in self.db.iterator_cf_opt(cf, read_opts, mode) {
+                batch.put_cf(cf, &key, value_codec::wrap(&value));
+                migrated += 1;
+                last_key = key;
+
+                if batch.len() >= MIGRATION_BATCH_SIZE {
+                    self.write_progress(std::mem::take(&mut batch), index, &last_key)?;
+                }
+            }
+
+            if !batch.is_empty() {
+                self.write_progress(batch, index, &last_key)?;
+            }
+        }
+
+        Ok(migrated)
+    }
+
+    /// Build the asset index of all stored assets and return the number of index entries
+    ///
+    /// Earlier versions did not enforce unique ISINs and WKNs, hence duplicates may fail
+    /// the migration. They can be resolved after opening the database without
+    /// `migrate_on_open`.
+    fn index_assets(&self) -> Result<usize, DataError> {
+        let index_cf = self.cf_handle(ASSET_INDEX_CF)?;
+        let mut owners: HashMap<Vec<u8>, String> = HashMap::new();
+        let mut batch = WriteBatch::default();
+
+        for (key, value) in self.db.iterator_cf(self.cf(DataType::Asset)?, IteratorMode::Start) {
+            let asset: Asset = value_codec::decode(&value)
+                .map_err(|e| DataError::serialization(DataType::Asset, key_to_string(&key), e))?;
+
+            for (index, id_kind, id) in asset_ids(&asset) {
+                let index_key = asset_index_key(index, id);
+                if let Some(owner) = owners.get(&index_key) {
+                    return Err(duplicate_id(&asset, id_kind, id, owner));
+                }
+                batch.put_cf(index_cf, &index_key, asset.name.as_bytes());
+                owners.insert(index_key, asset.name.clone());
+            }
+        }
+
+        self.write_migration(batch)?;
+        Ok(owners.len())
+    }
+
+    /// Move all transactions to keys ordered by booking date, index them by id and return
+    /// the number of indexed transactions
+    ///
+    /// Each old key is deleted in the same write batch that inserts the new key and its
+    /// index entry, while transactions that already have their new key are only indexed,
+    /// hence an interrupted migration can simply be run again.
+    fn index_transactions(&self) -> Result<usize, DataError> {
+        let cf = self.cf(DataType::Transaction)?;
+        let index_cf = self.cf_handle(TRANSACTION_INDEX_CF)?;
+        let mut read_opts = ReadOptions::default();
+        read_opts.set_total_order_seek(true);
+        let mut batch = WriteBatch::default();
+        let mut migrated = 0;
+
+        // iterators read a consistent view, hence they don't visit the keys written here
+        for (key, value) in self.db.iterator_cf_opt(cf, read_opts, IteratorMode::Start) {
+            let record_key = key_to_string(&key);
+            let sort_prefix = decode_id(&key)
+                .ok_or_else(|| DataError::serialization(DataType::Transaction, &record_key, "invalid transaction key"))?;
+            let transaction: Transaction = value_codec::decode(&value)
+                .map_err(|e| DataError::serialization(DataType::Transaction, &record_key, e))?;
+
+            let new_key = transaction_key(&sort_prefix, transaction.cash_flow.date, transaction.id);
+            if *key != *new_key {
+                batch.put_cf(cf, &new_key, &value);
+                batch.delete_cf(cf, &key);
+            }
+            batch.put_cf(index_cf, transaction_index_key(&sort_prefix, transaction.id), &new_key);
+            migrated += 1;
+
+            if batch.len() >= 3 * MIGRATION_BATCH_SIZE {
+                self.write_migration(std::mem::take(&mut batch))?;
+            }
+        }
+
+        self.write_migration(batch)?;
+        Ok(migrated)
+    }
+
+    /// Index all transactions referring to an asset by the asset and return the number
+    /// of index entries
+    ///
+    /// Index entries are only ever put, hence an interrupted migration can simply be run
+    /// again.
+    fn index_asset_transactions(&self) -> Result<usize, DataError> {
+        let index_cf = self.cf_handle(ASSET_TRANSACTION_INDEX_CF)?;
+        let mut read_opts = ReadOptions::default();
+        read_opts.set_total_order_seek(true);
+        let mut batch = WriteBatch::default();
+        let mut indexed = 0;
+
+        for (key, value) in self.db.iterator_cf_opt(self.cf(DataType::Transaction)?, read_opts, IteratorMode::Start) {
+            let transaction: Transaction = value_codec::decode(&value)
+                .map_err(|e| DataError::serialization(DataType::Transaction, key_to_string(&key), e))?;
+
+            if let Some(asset) = transaction.asset_name() {
+                batch.put_cf(index_cf, asset_transaction_index_key(asset, &key), b"");
+                indexed += 1;
+
+                if batch.len() >= MIGRATION_BATCH_SIZE {
+                    self.write_migration(std::mem::take(&mut batch))?;
+                }
+            }
+        }
+
+        self.write_migration(batch)?;
+        Ok(indexed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data_handler::{AssetHandler, OnDuplicate, QuoteHandler, TransactionHandler};
+    use crate::fiat::{CashFlow, Currency};
+    use crate::helpers::make_time;
+    use crate::rocksdb_handler::column_family::cf_name;
+    use crate::transaction::TransactionType;
+    use chrono::NaiveDate;
+    use rocksdb::{Options, DB};
+
+    fn asset(name: &str) -> Asset {
+        Asset::new(name, None, None, None)
+    }
+
+    /// Database with column families and plain bincode values, as written before
+    /// schema versioning
+    fn write_version_1(path: &std::path::Path, assets: &[Asset]) {
+        let mut opts = Options::default();
+        opts.create_if_missing(true);
+        opts.create_missing_column_families(true);
+        let cf_names = DATA_TYPES.iter().map(|data_type| cf_name(*data_type));
+        let db = DB::open_cf(&opts, path, cf_names).unwrap();
+
+        let cf = db.cf_handle(cf_name(DataType::Asset)).unwrap();
+        for asset in assets {
+            let value = bincode::serialize(asset).unwrap();
+            db.put_cf(cf, prefix_key(DataType::Asset, &asset.name), value).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_migrate_legacy_keys() {
+        let dir = tempfile::tempdir().unwrap();
+
+        let quote = Quote {
+            id: Some(1),
+            ticker: "AAPL".to_string(),
+            price: 100.0,
+            time: make_time(1969, 7, 20, 20, 17, 0).unwrap(),
+            volume: None,
+        };
+        let mut transaction = Transaction::new(
+            TransactionType::Cash,
+            CashFlow::new(100.0, Currency::EUR, NaiveDate::from_ymd(2020, 9, 1)),
+            None,
+        );
+        transaction.id = 42;
+
+        {
+            let db = DB::open_default(dir.path()).unwrap();
+            db.put("0:Apple:", bincode::serialize(&asset("Apple")).unwrap())
+                .unwrap();
+            db.put(
+                format!("1:AAPL:{}1", quote.time.timestamp_nanos()),
+                bincode::serialize(&quote).unwrap(),
+            )
+            .unwrap();
+            db.put("3:my:book:42", bincode::serialize(&transaction).unwrap())
+                .unwrap();
+            // binary keys written to the default column family before the column family layout
+            db.put(
+                prefix_key(DataType::Asset, "Microsoft"),
+                bincode::serialize(&asset("Microsoft")).unwrap(),
+            )
+            .unwrap();
+        }
+
+        let db = RocksDB::new(dir.path()).unwrap();
+        assert_eq!(db.schema_version().unwrap(), SCHEMA_VERSION);
+        assert_eq!(db.migrate().unwrap(), 0);
+
+        assert_eq!(db.get_asset_by_name("Apple").unwrap().name, "Apple");
+        assert_eq!(db.get_asset_by_name("Microsoft").unwrap().name, "Microsoft");
+        assert_eq!(db.db.iterator(IteratorMode::Start).count(), 1);
+        assert_eq!(db.get_transaction_by_id("my:book", 42).unwrap().id, 42);
+        let ticker = Ticker {
+            name: "AAPL".to_string(),
+            asset: "Apple".to_string(),
+            currency: Currency::USD,
+            priority: 1,
+            factor: 1.0,
+        };
+        let quotes: Vec<Quote> = db
+            .quote_cursor_forward(&ticker, quote.time)
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(quotes.len(), 1);
+        assert_eq!(quotes[0].id, Some(1));
+    }
+
+    #[test]
+    fn test_explicit_migration() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut apple = asset("Apple");
+        apple.isin = Some("US0378331005".to_string());
+        write_version_1(dir.path(), &[apple, asset("Microsoft")]);
+
+        let outdated = RocksDB::builder(dir.path()).read_only().open();
+        assert!(matches!(
+            outdated,
+            Err(DataError::IncompatibleSchema { found: 0, supported: SCHEMA_VERSION })
+        ));
+
+        let db = RocksDB::builder(dir.path()).migrate_on_open(false).open().unwrap();
+        assert_eq!(db.schema_version().unwrap(), 0);
+        assert!(matches!(db.get_asset_by_name("Apple"), Err(DataError::Serialization { .. })));
+
+        // two wrapped values and one index entry
+        assert_eq!(db.migrate().unwrap(), 3);
+        assert_eq!(db.schema_version().unwrap(), SCHEMA_VERSION);
+        assert_eq!(db.get_asset_by_isin("US0378331005").unwrap().name, "Apple");
+        assert_eq!(db.migrate().unwrap(), 0);
+    }
+
+    #[test]
+    fn test_writes_wait_for_migration() {
+        let dir = tempfile::tempdir().unwrap();
+        write_version_1(dir.path(), &[asset("Apple")]);
+
+        let mut db = RocksDB::builder(dir.path()).migrate_on_open(false).open().unwrap();
+        let outdated = |result: Result<_, DataError>| {
+            matches!(result, Err(DataError::IncompatibleSchema { found: 0, supported: SCHEMA_VERSION }))
+        };
+        assert!(outdated(db.insert_asset(&asset("Microsoft"))));
+        assert!(outdated(db.insert_quotes(&[], OnDuplicate::Replace).map(|_| ())));
+        let time = make_time(2020, 9, 1, 0, 0, 0).unwrap();
+        assert!(outdated(db.delete_quotes("AAPL", time, time + chrono::Duration::days(1))));
+
+        assert_eq!(db.migrate().unwrap(), 1);
+        db.insert_asset(&asset("Microsoft")).unwrap();
+        assert_eq!(db.get_asset_by_name("Apple").unwrap().name, "Apple");
+        assert_eq!(db.get_asset_by_name("Microsoft").unwrap().name, "Microsoft");
+        assert_eq!(db.migrate().unwrap(), 0);
+    }
+
+    #[test]
+    fn test_resume_interrupted_migration() {
+        let dir = tempfile::tempdir().unwrap();
+        write_version_1(dir.path(), &[asset("Apple"), asset("Microsoft")]);
+
+        {
+            // state after writing the first batch of the migration to version 2
+            let db = RocksDB::builder(dir.path()).migrate_on_open(false).open().unwrap();
+            db.set_schema_version(1).unwrap();
+            let key = prefix_key(DataType::Asset, "Apple");
+            let mut batch = WriteBatch::default();
+            let value = value_codec::encode(&asset("Apple")).unwrap();
+            batch.put_cf(db.cf(DataType::Asset).unwrap(), &key, value);
+            db.write_progress(batch, 0, &key).unwrap();
+        }
+
+        let db = RocksDB::new(dir.path()).unwrap();
+        assert_eq!(db.get_asset_by_name("Apple").unwrap().name, "Apple");
+        assert_eq!(db.get_asset_by_name("Microsoft").unwrap().name, "Microsoft");
+        assert!(db.db.get(MIGRATION_PROGRESS_KEY).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_index_transactions() {
+        let dir = tempfile::tempdir().unwrap();
+        {
+            let db = RocksDB::builder(dir.path()).migrate_on_open(false).open().unwrap();
+            db.set_schema_version(3).unwrap();
+            let cf = db.cf(DataType::Transaction).unwrap();
+
+            // created in id order, but booked in reverse order
+            for (id, day) in [(1, 3), (2, 2), (3, 1)].iter() {
+                let mut transaction = Transaction::new(
+                    TransactionType::Cash,
+                    CashFlow::new(100.0, Currency::EUR, NaiveDate::from_ymd(2020, 9, *day)),
+                    None,
+                );
+                transaction.id = *id;
+                let mut old_key = prefix_key(DataType::Transaction, "book");
+                old_key.extend_from_slice(&transaction.id.to_be_bytes());
+                db.db.put_cf(cf, old_key, value_codec::encode(&transaction).unwrap()).unwrap();
+            }
+        }
+
+        let db = RocksDB::new(dir.path()).unwrap();
+        assert_eq!(db.schema_version().unwrap(), SCHEMA_VERSION);
+        assert_eq!(db.get_transaction_by_id("book", 1).unwrap().id, 1);
+        let ids: Vec<u128> = db
+            .transaction_cursor_forward("book", make_time(2020, 1, 1, 0, 0, 0).unwrap())
+            .map(|t| t.unwrap().id)
+            .collect();
+        assert_eq!(ids, vec![3, 2, 1]);
+
+        // running the migration again only rewrites the index
+        assert_eq!(db.index_transactions().unwrap(), 3);
+        assert_eq!(db.transaction_cursor_forward("book", make_time(2020, 1, 1, 0, 0, 0).unwrap()).count(), 3);
+    }
+
+    #[test]
+    fn test_index_asset_transactions() {
+        let dir = tempfile::tempdir().unwrap();
+        let apple = asset("Apple");
+        {
+            let mut db = RocksDB::new(dir.path()).unwrap();
+            db.insert_asset(&apple).unwrap();
+            for (id, transaction_type) in [
+                (1, TransactionType::Dividend { asset_name: "Apple".to_string() }),
+                (2, TransactionType::Cash),
+            ] {
+                let mut transaction = Transaction::new(
+                    transaction_type,
+                    CashFlow::new(10.0, Currency::USD, NaiveDate::from_ymd(2020, 9, 1)),
+                    None,
+                );
+                transaction.id = id;
+                db.insert_transaction("book", &transaction).unwrap();
+            }
+
+            // state before the asset transaction index
+            let index_cf = db.cf_handle(ASSET_TRANSACTION_INDEX_CF).unwrap();
+            let keys: Vec<_> = db.db.iterator_cf(index_cf, IteratorMode::Start).map(|(key, _)| key).collect();
+            assert_eq!(keys.len(), 1);
+            for key in keys {
+                db.db.delete_cf(index_cf, key).unwrap();
+            }
+            db.set_schema_version(4).unwrap();
+        }
+
+        let mut db = RocksDB::builder(dir.path()).migrate_on_open(false).open().unwrap();
+        assert_eq!(db.migrate().unwrap(), 1);
+        assert!(matches!(
+            db.delete_asset(&apple),
+            Err(DataError::Validation { data_type: DataType::Asset, reason, .. }) if reason.contains("book/1")
+        ));
+    }
+
+    #[test]
+    fn test_reject_newer_schema() {
+        let dir = tempfile::tempdir().unwrap();
+        RocksDB::new(dir.path())
+            .unwrap()
+            .set_schema_version(SCHEMA_VERSION + 1)
+            .unwrap();
+
+        assert!(matches!(
+            RocksDB::new(dir.path()),
+            Err(DataError::IncompatibleSchema { found, .. }) if found == SCHEMA_VERSION + 1
+        ));
+    }
+}
@@ -1,52 +1,495 @@
 ///! Implemenation of rocksdb data handler
-use rocksdb::DB;
-use std::path::Path;
-use crate::data_handler::DataType;
+use rocksdb::{ColumnFamily, DB};
+use std::sync::atomic::AtomicBool;
+
+use crate::data_handler::changes::ChangeFeed;
+use crate::data_handler::{DataError, DataType};
+use column_family::cf_name;
 
 mod asset_handler;
+mod backup;
+mod batch_handler;
+mod column_family;
+mod ingest;
+mod integrity;
+pub mod key_codec;
+mod migration;
+mod options;
 mod quote_handler;
+mod reader;
+mod snapshot;
 mod transaction_handler;
+pub mod value_codec;
+
+pub use backup::BackupInfo;
+pub use migration::SCHEMA_VERSION;
+pub use options::{AccessMode, RocksDBBuilder, RocksDBOptions};
+pub use snapshot::RocksDBSnapshot;
 
-/// Struct to handle connections to sqlite3 databases
+/// Struct to handle connections to rocksdb databases
+///
+/// Each data type is stored in a column family of its own, which are created when the
+/// database is opened. Handlers are `Send` and `Sync`, hence one handler may be shared
+/// between threads, e.g. in an `Arc`, which all read through it in parallel.
 pub struct RocksDB {
     /// conn is made public to allow extending this struct outside of the library
     pub db: DB,
+    options: RocksDBOptions,
+    feed: ChangeFeed,
+    /// Set while the database has an earlier schema version and awaits `migrate`
+    outdated: AtomicBool,
 }
 
 impl RocksDB {
-    pub fn new<P: AsRef<Path>>(&self, path: P) -> Result<RocksDB, rocksdb::Error> {
-        let db = rocksdb::DB::open_default(path);
+    /// Column family of the data type, which exists in all databases opened by this crate
+    fn cf(&self, data_type: DataType) -> Result<&ColumnFamily, DataError> {
+        self.cf_handle(cf_name(data_type))
+    }
+
+    fn cf_handle(&self, name: &str) -> Result<&ColumnFamily, DataError> {
+        self.db.cf_handle(name).ok_or_else(|| {
+            DataError::storage(format!("open column family '{}'", name), "column family does not exist")
+        })
+    }
+}
 
-        if db.is_err() {
-            return Err(db.unwrap_err());
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asset::Asset;
+    use crate::data_handler::integrity::{OrphanedQuotes, OrphanedTicker, OrphanedTransaction};
+    use crate::data_handler::{
+        conformance, AssetHandler, BulkReport, Cursor, DataType, IntegrityHandler, OnDuplicate, QuoteHandler,
+        Snapshot, TransactionHandler,
+    };
+    use crate::fiat::{CashFlow, Currency};
+    use crate::helpers::make_time;
+    use crate::memory_handler::MemoryDB;
+    use crate::quote::{Quote, Ticker};
+    use crate::transaction::{Transaction, TransactionType};
+    use chrono::NaiveDate;
+    use rocksdb::IteratorMode;
+    use std::sync::Arc;
+    use std::thread;
+
+    fn ticker(name: &str) -> Ticker {
+        Ticker {
+            name: name.to_string(),
+            asset: "Apple".to_string(),
+            currency: Currency::USD,
+            priority: 1,
+            factor: 1.0,
         }
+    }
+
+    fn quote(ticker: &str, hour: u32) -> Quote {
+        Quote {
+            id: None,
+            ticker: ticker.to_string(),
+            price: hour as f64,
+            time: make_time(2020, 9, 1, hour, 0, 0).unwrap(),
+            volume: None,
+        }
+    }
 
-        Ok(
-            RocksDB {
-                db: db.unwrap()
+    fn prices(cursor: Cursor<'_, Quote>) -> Vec<f64> {
+        cursor.map(|q| q.unwrap().price).collect()
+    }
+
+    fn open_temp_db() -> (tempfile::TempDir, RocksDB) {
+        let dir = tempfile::tempdir().unwrap();
+        let db = RocksDB::new(dir.path()).unwrap();
+        (dir, db)
+    }
+
+    #[test]
+    fn test_conformance() {
+        // the directories have to outlive the handlers
+        let mut dirs = Vec::new();
+        conformance::check_all(
+            || {
+                let (dir, db) = open_temp_db();
+                dirs.push(dir);
+                db
             },
-        )
-    }
-
-    fn build_key(
-        &self,
-        data_type: &DataType,
-        secondary_id: &str,
-        tertiary_id: &str,
-    ) -> Vec<u8> {
-        format!(
-            "{}:{}:{}",
-            *data_type as u8,
-            secondary_id,
-            tertiary_id,
-        ).as_bytes().to_vec()
-    }
-
-    fn build_subkey(
-        &self,
-        primary_key: &[u8],
-        secondary_key: &[u8],
-    ) -> Vec<u8> {
-        (&[primary_key, secondary_key].concat()).to_vec()
+            MemoryDB::new,
+        );
+    }
+
+    #[test]
+    fn test_column_families() {
+        let dir = tempfile::tempdir().unwrap();
+        {
+            let mut db = RocksDB::new(dir.path()).unwrap();
+            db.insert_asset(&Asset::new("Apple", None, None, None)).unwrap();
+            db.insert_ticker(&ticker("AAPL")).unwrap();
+            db.insert_quote(&quote("AAPL", 10)).unwrap();
+
+            // the default column family only holds the schema version
+            assert_eq!(db.db.iterator(IteratorMode::Start).count(), 1);
+            let quotes = db.cf(DataType::Quote).unwrap();
+            assert_eq!(db.db.iterator_cf(quotes, IteratorMode::Start).count(), 1);
+        }
+
+        let db = RocksDB::new(dir.path()).unwrap();
+        assert!(db.get_ticker_by_name("AAPL").is_ok());
+        assert!(db.get_latest_quote("AAPL").unwrap().is_some());
+    }
+
+    #[test]
+    fn test_shared_reads() {
+        let (_dir, mut db) = open_temp_db();
+        db.insert_asset(&Asset::new("Apple", None, None, None)).unwrap();
+        db.insert_ticker(&ticker("AAPL")).unwrap();
+        for hour in 10..14 {
+            db.insert_quote(&quote("AAPL", hour)).unwrap();
+        }
+
+        let db = Arc::new(db);
+        let (from, to) = (quote("AAPL", 11).time, quote("AAPL", 13).time);
+        let readers: Vec<_> = (0..4)
+            .map(|_| {
+                let db = Arc::clone(&db);
+                thread::spawn(move || prices(db.quote_range_forward(&ticker("AAPL"), from, to)))
+            })
+            .collect();
+        for reader in readers {
+            assert_eq!(reader.join().unwrap(), vec![11.0, 12.0]);
+        }
+    }
+
+    #[test]
+    fn test_open_modes() {
+        let dir = tempfile::tempdir().unwrap();
+        let secondary_dir = tempfile::tempdir().unwrap();
+        let missing = dir.path().join("missing");
+
+        assert!(RocksDB::builder(&missing).read_only().open().is_err());
+        assert!(RocksDB::builder(&missing).create_if_missing(false).open().is_err());
+
+        let mut primary = RocksDB::builder(dir.path())
+            .compression(rocksdb::DBCompressionType::Lz4)
+            .cache_size(1 << 20)
+            .sync_writes(true)
+            .open()
+            .unwrap();
+        primary.insert_asset(&Asset::new("Apple", None, None, None)).unwrap();
+
+        let mut reader = RocksDB::builder(dir.path()).read_only().open().unwrap();
+        assert_eq!(reader.options().access_mode, AccessMode::ReadOnly);
+        assert!(reader.get_asset_by_name("Apple").is_ok());
+        assert!(reader.insert_asset(&Asset::new("Microsoft", None, None, None)).is_err());
+
+        let follower = RocksDB::builder(dir.path())
+            .secondary(secondary_dir.path())
+            .open()
+            .unwrap();
+        primary.insert_asset(&Asset::new("Microsoft", None, None, None)).unwrap();
+        follower.try_catch_up_with_primary().unwrap();
+        assert!(follower.get_asset_by_name("Microsoft").is_ok());
+    }
+
+    #[test]
+    fn test_secondary_with_options() {
+        let (dir, mut primary) = open_temp_db();
+        let secondary_dir = tempfile::tempdir().unwrap();
+        primary.insert_asset(&Asset::new("Apple", None, None, None)).unwrap();
+        primary.insert_ticker(&ticker("AAPL")).unwrap();
+        primary.insert_quote(&quote("AAPL", 10)).unwrap();
+
+        let follower = RocksDB::builder(dir.path())
+            .secondary(secondary_dir.path())
+            .compression(rocksdb::DBCompressionType::Lz4)
+            .cache_size(1 << 20)
+            .open()
+            .unwrap();
+        for hour in 11..14 {
+            primary.insert_quote(&quote("AAPL", hour)).unwrap();
+        }
+        follower.try_catch_up_with_primary().unwrap();
+
+        let aapl = ticker("AAPL");
+        let from = make_time(2020, 9, 1, 11, 0, 0).unwrap();
+        assert_eq!(prices(follower.quote_cursor_forward(&aapl, from)), vec![11.0, 12.0, 13.0]);
+        assert_eq!(follower.get_latest_quote("AAPL").unwrap().unwrap().price, 13.0);
+
+        let reader = RocksDB::builder(dir.path()).read_only().cache_size(1 << 20).open().unwrap();
+        assert_eq!(prices(reader.quote_cursor_reverse(&aapl, from)), vec![11.0, 10.0]);
+    }
+
+    #[test]
+    fn test_backup_and_restore() {
+        let (dir, mut db) = open_temp_db();
+        let backup_dir = dir.path().join("backup");
+        db.insert_asset(&Asset::new("Apple", None, None, None)).unwrap();
+        db.insert_ticker(&ticker("AAPL")).unwrap();
+
+        let checkpoint = dir.path().join("checkpoint");
+        db.create_checkpoint(&checkpoint).unwrap();
+        assert!(db.create_checkpoint(&checkpoint).is_err());
+        assert!(RocksDB::new(&checkpoint).unwrap().get_ticker_by_name("AAPL").is_ok());
+
+        let reader = RocksDB::builder(dir.path()).read_only().open().unwrap();
+        assert!(reader.create_backup(&backup_dir).is_err());
+
+        let first = db.create_backup(&backup_dir).unwrap();
+        db.insert_quote(&quote("AAPL", 10)).unwrap();
+        let second = db.create_backup(&backup_dir).unwrap();
+        assert!(second.id > first.id);
+        RocksDB::purge_backups(&backup_dir, 1).unwrap();
+        assert_eq!(RocksDB::list_backups(&backup_dir).unwrap(), vec![second]);
+
+        let restored_path = dir.path().join("restored");
+        let mut restored = RocksDB::restore_backup(&backup_dir, &restored_path, &RocksDBOptions::default()).unwrap();
+        assert!(restored.get_latest_quote("AAPL").unwrap().is_some());
+        assert!(restored.check_integrity().unwrap().is_clean());
+
+        // never restore over existing files
+        let occupied = dir.path().join("occupied");
+        std::fs::create_dir(&occupied).unwrap();
+        std::fs::write(occupied.join("CURRENT"), b"").unwrap();
+        assert!(RocksDB::restore_backup(&backup_dir, &occupied, &RocksDBOptions::default()).is_err());
+    }
+
+    #[test]
+    fn test_ingest_quotes() {
+        let dir = tempfile::tempdir().unwrap();
+        let ingest_dir = tempfile::tempdir().unwrap();
+        let mut db = RocksDB::builder(dir.path()).ingest_dir(ingest_dir.path()).open().unwrap();
+        let aapl = ticker("AAPL");
+        let start = make_time(2020, 9, 1, 0, 0, 0).unwrap();
+
+        db.insert_asset(&Asset::new("Apple", None, None, None)).unwrap();
+        db.insert_ticker(&aapl).unwrap();
+        db.insert_quote(&quote("AAPL", 10)).unwrap();
+
+        let mut quotes: Vec<Quote> = (10..14).rev().map(|hour| quote("AAPL", hour)).collect();
+        let report = db.ingest_quotes(&quotes, OnDuplicate::Skip).unwrap();
+        assert_eq!(report, BulkReport { inserted: 3, replaced: 0, skipped: 1 });
+        assert_eq!(prices(db.quote_cursor_forward(&aapl, start)), vec![10.0, 11.0, 12.0, 13.0]);
+
+        // the last of several quotes with the same key wins
+        quotes[0].price = 100.0;
+        quotes.push(Quote { price: 110.0, ..quote("AAPL", 11) });
+        let report = db.ingest_quotes(&quotes, OnDuplicate::Replace).unwrap();
+        assert_eq!(report, BulkReport { inserted: 0, replaced: 5, skipped: 0 });
+        assert_eq!(prices(db.quote_cursor_forward(&aapl, start)), vec![10.0, 110.0, 12.0, 100.0]);
+
+        assert!(matches!(
+            db.ingest_quotes(&[quote("MSFT", 10)], OnDuplicate::Skip),
+            Err(DataError::Validation { .. })
+        ));
+        assert_eq!(std::fs::read_dir(ingest_dir.path()).unwrap().count(), 0, "ingest files left behind");
+    }
+
+    #[test]
+    fn test_snapshot_ignores_later_writes() {
+        let (_dir, mut db) = open_temp_db();
+        let aapl = ticker("AAPL");
+        let start = make_time(2020, 9, 1, 0, 0, 0).unwrap();
+
+        db.insert_asset(&Asset::new("Apple", None, None, None)).unwrap();
+        db.insert_ticker(&aapl).unwrap();
+        db.insert_quote(&quote("AAPL", 10)).unwrap();
+
+        // writes bypass the handler, which the snapshot borrows
+        let snapshot = RocksDBSnapshot::new(&db);
+        let quotes = db.cf(DataType::Quote).unwrap();
+        let later = quote("AAPL", 11);
+        db.db
+            .put_cf(quotes, key_codec::quote_key("AAPL", &later.time, 0), value_codec::encode(&later).unwrap())
+            .unwrap();
+        db.db.delete_cf(db.cf(DataType::Ticker).unwrap(), key_codec::prefix_key(DataType::Ticker, "AAPL")).unwrap();
+
+        assert_eq!(prices(snapshot.quote_cursor_forward(&aapl, start)), vec![10.0]);
+        assert_eq!(snapshot.get_latest_quote("AAPL").unwrap().map(|q| q.price), Some(10.0));
+        assert!(snapshot.get_ticker_by_name("AAPL").is_ok());
+        assert_eq!(prices(db.reader().quote_cursor_forward(&aapl, start)), vec![10.0, 11.0]);
+        assert!(db.reader().get_ticker_by_name("AAPL").is_err());
+    }
+
+    #[test]
+    fn test_quote_cursors_stop_at_ticker() {
+        let (_dir, mut db) = open_temp_db();
+
+        db.insert_asset(&Asset::new("Apple", None, None, None)).unwrap();
+        for name in ["AAP", "AAPL", "AAPM"].iter() {
+            db.insert_ticker(&ticker(name)).unwrap();
+        }
+        for hour in 10..13 {
+            db.insert_quote(&quote("AAPL", hour)).unwrap();
+            db.insert_quote(&quote("AAPM", hour + 10)).unwrap();
+        }
+        db.insert_quote(&quote("AAP", 9)).unwrap();
+
+        let aapl = ticker("AAPL");
+        let start = make_time(2020, 9, 1, 0, 0, 0).unwrap();
+        let end = make_time(2020, 9, 2, 0, 0, 0).unwrap();
+        let noon = make_time(2020, 9, 1, 12, 0, 0).unwrap();
+        assert_eq!(prices(db.quote_cursor_forward(&aapl, start)), vec![10.0, 11.0, 12.0]);
+        assert_eq!(prices(db.quote_cursor_reverse(&aapl, end)), vec![12.0, 11.0, 10.0]);
+        assert_eq!(prices(db.quote_cursor_reverse(&aapl, noon)), vec![12.0, 11.0, 10.0]);
+        assert_eq!(prices(db.quote_range_forward(&aapl, start, noon)), vec![10.0, 11.0]);
+        assert_eq!(prices(db.quote_range_reverse(&aapl, start, noon)), vec![11.0, 10.0]);
+        assert!(prices(db.quote_cursor_forward(&ticker("IBM"), start)).is_empty());
+    }
+
+    #[test]
+    fn test_transaction_cursors_stop_at_sort_prefix() {
+        let (_dir, mut db) = open_temp_db();
+
+        let mut transaction = Transaction::new(
+            TransactionType::Cash,
+            CashFlow::new(100.0, Currency::EUR, NaiveDate::from_ymd(2020, 9, 1)),
+            None,
+        );
+        for id in 1..4 {
+            transaction.id = id;
+            db.insert_transaction("book", &transaction).unwrap();
+            transaction.id = id + 10;
+            db.insert_transaction("books", &transaction).unwrap();
+        }
+
+        let start = make_time(1970, 1, 1, 0, 0, 0).unwrap();
+        let ids: Vec<u128> = db
+            .transaction_cursor_forward("book", start)
+            .map(|t| t.unwrap().id)
+            .collect();
+        assert_eq!(ids, vec![1, 2, 3]);
+        let ids: Vec<u128> = db
+            .transaction_cursor_reverse("book", make_time(2020, 9, 1, 0, 0, 0).unwrap())
+            .map(|t| t.unwrap().id)
+            .collect();
+        assert_eq!(ids, vec![3, 2, 1]);
+        let ids: Vec<u128> = db
+            .transaction_range_reverse("book", start, make_time(2020, 9, 2, 0, 0, 0).unwrap())
+            .map(|t| t.unwrap().id)
+            .collect();
+        assert_eq!(ids, vec![3, 2, 1]);
+    }
+
+    #[test]
+    fn test_cursor_reports_corrupt_values() {
+        let (_dir, mut db) = open_temp_db();
+
+        db.insert_asset(&Asset::new("Apple", None, None, None)).unwrap();
+        db.insert_ticker(&ticker("AAPL")).unwrap();
+        db.insert_quote(&quote("AAPL", 10)).unwrap();
+        let time = make_time(2020, 9, 1, 11, 0, 0).unwrap();
+        let quotes = db.cf(DataType::Quote).unwrap();
+        db.db
+            .put_cf(quotes, key_codec::quote_key("AAPL", &time, 0), b"garbage")
+            .unwrap();
+
+        let quotes: Vec<_> = db
+            .quote_cursor_forward(&ticker("AAPL"), make_time(2020, 9, 1, 0, 0, 0).unwrap())
+            .collect();
+        assert_eq!(quotes.len(), 2);
+        assert!(quotes[0].is_ok());
+        assert!(matches!(
+            &quotes[1],
+            Err(DataError::Serialization { data_type: DataType::Quote, key, .. }) if key.starts_with("AAPL@")
+        ));
+        assert!(matches!(
+            db.get_latest_quote("AAPL"),
+            Err(DataError::Serialization { data_type: DataType::Quote, .. })
+        ));
+        assert!(db.get_oldest_quote("AAPL").unwrap().is_some());
+    }
+
+    #[test]
+    fn test_asset_delete_skips_transactions_of_other_assets() {
+        let (_dir, mut db) = open_temp_db();
+        db.insert_asset(&Asset::new("Apple", None, None, None)).unwrap();
+        db.insert_asset(&Asset::new("Microsoft", None, None, None)).unwrap();
+        let transactions = db.cf(DataType::Transaction).unwrap();
+        let date = NaiveDate::from_ymd(2020, 9, 1);
+        db.db
+            .put_cf(transactions, key_codec::transaction_key("book", date, 1), b"garbage")
+            .unwrap();
+        let mut dividend = Transaction::new(
+            TransactionType::Dividend { asset_name: "Apple".to_string() },
+            CashFlow::new(10.0, Currency::USD, date),
+            None,
+        );
+        dividend.id = 2;
+        db.insert_transaction("book", &dividend).unwrap();
+
+        assert!(matches!(
+            db.delete_asset(&Asset::new("Apple", None, None, None)),
+            Err(DataError::Validation { data_type: DataType::Asset, reason, .. }) if reason.contains("book/2")
+        ));
+        db.delete_asset(&Asset::new("Microsoft", None, None, None)).unwrap();
+    }
+
+    #[test]
+    fn test_asset_transaction_index_follows_updates() {
+        let (_dir, mut db) = open_temp_db();
+        let (apple, microsoft) = (Asset::new("Apple", None, None, None), Asset::new("Microsoft", None, None, None));
+        db.insert_asset(&apple).unwrap();
+        db.insert_asset(&microsoft).unwrap();
+        let mut dividend = Transaction::new(
+            TransactionType::Dividend { asset_name: "Apple".to_string() },
+            CashFlow::new(10.0, Currency::USD, NaiveDate::from_ymd(2020, 9, 1)),
+            None,
+        );
+        dividend.id = 1;
+        db.insert_transaction("book", &dividend).unwrap();
+
+        dividend.transaction_type = TransactionType::Dividend { asset_name: "Microsoft".to_string() };
+        dividend.cash_flow.date = NaiveDate::from_ymd(2020, 9, 2);
+        db.update_transaction("book", &dividend).unwrap();
+        db.delete_asset(&apple).unwrap();
+        assert!(matches!(db.delete_asset(&microsoft), Err(DataError::Validation { .. })));
+
+        db.delete_transaction("book", &dividend).unwrap();
+        db.delete_asset(&microsoft).unwrap();
+        let index = db.cf_handle(column_family::ASSET_TRANSACTION_INDEX_CF).unwrap();
+        assert_eq!(db.db.iterator_cf(index, IteratorMode::Start).count(), 0);
+    }
+
+    #[test]
+    fn test_integrity_report() {
+        let (_dir, mut db) = open_temp_db();
+
+        // orphans can only be stored by bypassing the checks of the handler
+        let put = |data_type: DataType, key: Vec<u8>, value: Vec<u8>| {
+            db.db.put_cf(db.cf(data_type).unwrap(), key, value).unwrap();
+        };
+        let mut dividend = Transaction::new(
+            TransactionType::Dividend { asset_name: "Apple".to_string() },
+            CashFlow::new(10.0, Currency::USD, NaiveDate::from_ymd(2020, 9, 1)),
+            None,
+        );
+        dividend.id = 1;
+        put(
+            DataType::Ticker,
+            key_codec::prefix_key(DataType::Ticker, "AAPL"),
+            value_codec::encode(&ticker("AAPL")).unwrap(),
+        );
+        for (ticker, hour) in [("AAPL", 10), ("MSFT", 10), ("MSFT", 11)].iter() {
+            let quote = quote(ticker, *hour);
+            put(
+                DataType::Quote,
+                key_codec::quote_key(ticker, &quote.time, 0),
+                value_codec::encode(&quote).unwrap(),
+            );
+        }
+        put(
+            DataType::Transaction,
+            key_codec::transaction_key("book", dividend.cash_flow.date, 1),
+            value_codec::encode(&dividend).unwrap(),
+        );
+
+        let report = db.check_integrity().unwrap();
+        assert_eq!(
+            report.tickers,
+            vec![OrphanedTicker { ticker: "AAPL".to_string(), asset: "Apple".to_string() }]
+        );
+        assert_eq!(report.quotes, vec![OrphanedQuotes { ticker: "MSFT".to_string(), count: 2 }]);
+        assert_eq!(
+            report.transactions,
+            vec![OrphanedTransaction { sort_prefix: "book".to_string(), id: 1, asset: "Apple".to_string() }]
+        );
     }
 }
@@ -0,0 +1,262 @@
+//! Options and builder to open RocksDB databases
+use std::path::{Path, PathBuf};
+use std::sync::atomic::AtomicBool;
+
+use rocksdb::{Cache, DBCompressionType, Options, WriteOptions, DB};
+
+use super::column_family::{cf_descriptors, cf_names};
+use super::RocksDB;
+use crate::data_handler::changes::ChangeFeed;
+use crate::data_handler::{DataError, IntegrityPolicy};
+
+/// Mode in which the database is accessed
+#[derive(Debug, Clone, PartialEq)]
+pub enum AccessMode {
+    ReadWrite,
+    /// Read a snapshot of the database as of opening it, all writes fail
+    ReadOnly,
+    /// Follow a database written by another process, which is caught up with by
+    /// `RocksDB::try_catch_up_with_primary`; the secondary keeps its own logs in
+    /// `secondary_path`
+    Secondary { secondary_path: PathBuf },
+}
+
+/// Options to open a RocksDB database
+#[derive(Debug, Clone)]
+pub struct RocksDBOptions {
+    pub access_mode: AccessMode,
+    /// Create the database if it does not exist yet, only applies to `AccessMode::ReadWrite`
+    pub create_if_missing: bool,
+    pub compression: DBCompressionType,
+    /// Size in bytes of the block cache shared by all column families, uses the RocksDB
+    /// default if not set
+    pub cache_size: Option<usize>,
+    /// Directory of the write-ahead log, defaults to the database directory
+    pub wal_dir: Option<PathBuf>,
+    /// Directory of the temporary SST files written by `RocksDB::ingest_quotes`, defaults
+    /// to the database directory
+    ///
+    /// The files are moved into the database, which falls back to copying them if the
+    /// directory is on another file system.
+    pub ingest_dir: Option<PathBuf>,
+    /// Size in bytes the write-ahead log may grow to before the column families are flushed
+    pub max_total_wal_size: Option<u64>,
+    /// Skip the write-ahead log, i.e. recent writes get lost on a crash
+    pub disable_wal: bool,
+    /// Sync the write-ahead log to disk before each write returns
+    pub sync_writes: bool,
+    /// Upgrade databases of an earlier schema version when opening them read-write,
+    /// otherwise they have to be upgraded by `RocksDB::migrate` and all writes fail with
+    /// `DataError::IncompatibleSchema` until then
+    pub migrate_on_open: bool,
+    /// Handling of deletes of assets and tickers that are still referred to
+    pub integrity_policy: IntegrityPolicy,
+}
+
+impl Default for RocksDBOptions {
+    fn default() -> RocksDBOptions {
+        RocksDBOptions {
+            access_mode: AccessMode::ReadWrite,
+            create_if_missing: true,
+            compression: DBCompressionType::Snappy,
+            cache_size: None,
+            wal_dir: None,
+            ingest_dir: None,
+            max_total_wal_size: None,
+            disable_wal: false,
+            sync_writes: false,
+            migrate_on_open: true,
+            integrity_policy: IntegrityPolicy::Restrict,
+        }
+    }
+}
+
+impl RocksDBOptions {
+    pub(super) fn db_options(&self) -> Options {
+        let mut opts = Options::default();
+        opts.create_if_missing(self.create_if_missing);
+        opts.create_missing_column_families(true);
+        opts.set_compression_type(self.compression);
+
+        if let Some(wal_dir) = &self.wal_dir {
+            opts.set_wal_dir(wal_dir);
+        }
+        if let Some(max_total_wal_size) = self.max_total_wal_size {
+            opts.set_max_total_wal_size(max_total_wal_size);
+        }
+        if let AccessMode::Secondary { .. } = self.access_mode {
+            // required by secondary instances to see all files of the primary
+            opts.set_max_open_files(-1);
+        }
+
+        opts
+    }
+
+    pub(super) fn write_options(&self) -> WriteOptions {
+        let mut write_opts = WriteOptions::default();
+        write_opts.disable_wal(self.disable_wal);
+        write_opts.set_sync(self.sync_writes);
+        write_opts
+    }
+}
+
+/// Column families of the database at the path, read-only and secondary instances
+/// can't create missing column families of databases written by earlier versions,
+/// which are rejected by the schema check instead
+fn existing_cf_names(opts: &Options, path: &Path) -> Result<Vec<&'static str>, rocksdb::Error> {
+    let existing = DB::list_cf(opts, path)?;
+    Ok(cf_names()
+        .into_iter()
+        .filter(|name| existing.iter().any(|existing| existing == name))
+        .collect())
+}
+
+impl RocksDB {
+    /// Open the database at the given path with default options, creating it if required
+    pub fn new<P: AsRef<Path>>(path: P) -> Result<RocksDB, DataError> {
+        RocksDB::open(path, &RocksDBOptions::default())
+    }
+
+    /// Open the database at the given path together with the column families of all
+    /// data types, which are created if missing unless the database is opened read-only
+    /// or as secondary instance
+    ///
+    /// All modes open the column families with the same options, such that the block
+    /// cache, prefix extractors and bloom filters apply to readers and followers as well.
+    ///
+    /// Databases of an earlier schema version are migrated, see `migrate_on_open`.
+    pub fn open<P: AsRef<Path>>(path: P, options: &RocksDBOptions) -> Result<RocksDB, DataError> {
+        let path = path.as_ref();
+        let open_error = |e| DataError::storage(format!("open database at '{}'", path.display()), e);
+        let opts = options.db_options();
+
+        let cache = match options.cache_size {
+            Some(cache_size) => Some(Cache::new_lru_cache(cache_size).map_err(open_error)?),
+            None => None,
+        };
+        let db = match &options.access_mode {
+            AccessMode::ReadWrite =>
+                DB::open_cf_descriptors(&opts, path, cf_descriptors(&cf_names(), &opts, cache.as_ref())),
+            AccessMode::ReadOnly => existing_cf_names(&opts, path).and_then(|cf_names| {
+                let descriptors = cf_descriptors(&cf_names, &opts, cache.as_ref());
+                DB::open_cf_descriptors_read_only(&opts, path, descriptors, false)
+            }),
+            AccessMode::Secondary { secondary_path } => existing_cf_names(&opts, path).and_then(|cf_names| {
+                let descriptors = cf_descriptors(&cf_names, &opts, cache.as_ref());
+                DB::open_cf_descriptors_as_secondary(&opts, path, secondary_path.as_path(), descriptors)
+            }),
+        }
+        .map_err(open_error)?;
+
+        let db = RocksDB {
+            db,
+            options: options.clone(),
+            feed: ChangeFeed::default(),
+            outdated: AtomicBool::new(false),
+        };
+        db.check_schema()?;
+        Ok(db)
+    }
+
+    pub fn builder<P: AsRef<Path>>(path: P) -> RocksDBBuilder {
+        RocksDBBuilder {
+            path: path.as_ref().to_path_buf(),
+            options: RocksDBOptions::default(),
+        }
+    }
+
+    pub fn options(&self) -> &RocksDBOptions {
+        &self.options
+    }
+
+    /// Apply the changes the primary instance has written since opening or the last
+    /// call, only supported by secondary instances
+    pub fn try_catch_up_with_primary(&self) -> Result<(), DataError> {
+        self.db
+            .try_catch_up_with_primary()
+            .map_err(|e| DataError::storage("catch up with primary", e))
+    }
+}
+
+/// Builder to open a RocksDB database, e.g. a reporting process following the database
+/// written by an importer:
+///
+/// ```ignore
+/// let db = RocksDB::builder("/data/ticky")
+///     .secondary("/tmp/ticky-report")
+///     .cache_size(256 << 20)
+///     .open()?;
+/// ```
+#[derive(Debug, Clone)]
+pub struct RocksDBBuilder {
+    path: PathBuf,
+    options: RocksDBOptions,
+}
+
+impl RocksDBBuilder {
+    pub fn read_only(mut self) -> RocksDBBuilder {
+        self.options.access_mode = AccessMode::ReadOnly;
+        self
+    }
+
+    pub fn secondary<P: AsRef<Path>>(mut self, secondary_path: P) -> RocksDBBuilder {
+        self.options.access_mode = AccessMode::Secondary {
+            secondary_path: secondary_path.as_ref().to_path_buf(),
+        };
+        self
+    }
+
+    pub fn create_if_missing(mut self, create_if_missing: bool) -> RocksDBBuilder {
+        self.options.create_if_missing = create_if_missing;
+        self
+    }
+
+    pub fn compression(mut self, compression: DBCompressionType) -> RocksDBBuilder {
+        self.options.compression = compression;
+        self
+    }
+
+    pub fn cache_size(mut self, cache_size: usize) -> RocksDBBuilder {
+        self.options.cache_size = Some(cache_size);
+        self
+    }
+
+    pub fn wal_dir<P: AsRef<Path>>(mut self, wal_dir: P) -> RocksDBBuilder {
+        self.options.wal_dir = Some(wal_dir.as_ref().to_path_buf());
+        self
+    }
+
+    pub fn ingest_dir<P: AsRef<Path>>(mut self, ingest_dir: P) -> RocksDBBuilder {
+        self.options.ingest_dir = Some(ingest_dir.as_ref().to_path_buf());
+        self
+    }
+
+    pub fn max_total_wal_size(mut self, max_total_wal_size: u64) -> RocksDBBuilder {
+        self.options.max_total_wal_size = Some(max_total_wal_size);
+        self
+    }
+
+    pub fn disable_wal(mut self, disable_wal: bool) -> RocksDBBuilder {
+        self.options.disable_wal = disable_wal;
+        self
+    }
+
+    pub fn sync_writes(mut self, sync_writes: bool) -> RocksDBBuilder {
+        self.options.sync_writes = sync_writes;
+        self
+    }
+
+    pub fn migrate_on_open(mut self, migrate_on_open: bool) -> RocksDBBuilder {
+        self.options.migrate_on_open = migrate_on_open;
+        self
+    }
+
+    pub fn integrity_policy(mut self, integrity_policy: IntegrityPolicy) -> RocksDBBuilder {
+        self.options.integrity_policy = integrity_policy;
+        self
+    }
+
+    pub fn open(self) -> Result<RocksDB, DataError> {
+        RocksDB::open(self.path, &self.options)
+    }
+}
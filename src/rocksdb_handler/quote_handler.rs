@@ -1,191 +1,196 @@
-///! Implementation for quote handler with Sqlite3 database as backend
+///! Implementation for quote handler with RocksDB database as backend
+use super::key_codec::{next_key, prefix_end, prefix_key, quote_key};
+use super::reader::Reader;
 use super::RocksDB;
 
-use crate::data_handler::{DataError, QuoteHandler, DataType};
+use crate::data_handler::{
+    quote_key_string, BatchHandler, BulkReport, Change, Cursor, DataError, QuoteHandler, DataType, OnDuplicate, Operation,
+    Page, TickerFilter,
+};
 
 use crate::quote::{Quote, Ticker};
 use chrono::{DateTime, Utc};
 
-use rocksdb::{IteratorMode, Direction};
+use rocksdb::{Direction, WriteBatch};
 
 
-/// Sqlite implementation of quote handler
-impl QuoteHandler for RocksDB {
-    fn get_ticker_by_name(&mut self, name: &str) -> Result<Ticker, DataError> {
-        let key = self.build_key(
-            &DataType::Ticker,
-            &name,
-            "",
-        );
+impl<'a> Reader<'a> {
+    pub(super) fn get_ticker_by_name(self, name: &str) -> Result<Ticker, DataError> {
+        let key = prefix_key(DataType::Ticker, name);
 
-        match self.db.get(key) {
-            Ok(Some(data)) =>
-                match bincode::deserialize(&data) {
-                    Ok(asset) => Ok(asset),
-                    _ => Err(DataError::DataAccessFailure),
-                },
-            _ => Err(DataError::NotFound),
-        }
+        self.get_record(DataType::Ticker, key, name)
     }
 
-    fn get_latest_quote(&mut self, ticker_name: &str) -> Option<Quote> {
-        let quote_prefix = self.build_key(
-            &DataType::Quote,
-            &ticker_name,
-            "",
-        );
+    pub(super) fn list_tickers(self, filter: &TickerFilter, page: &Page) -> Result<Vec<Ticker>, DataError> {
+        let mut tickers: Vec<Ticker> = self.all_records(DataType::Ticker)?;
+        tickers.retain(|ticker| filter.matches(ticker));
+        tickers.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(page.select(tickers, |ticker| &ticker.name))
+    }
 
-        self.db
-            .iterator(
-                IteratorMode::From(&quote_prefix, Direction::Forward)
-            )
+    pub(super) fn get_quote_by_id(self, ticker_name: &str, time: DateTime<Utc>, id: i64) -> Result<Quote, DataError> {
+        let key = quote_key(ticker_name, &time, id);
+
+        self.get_record(DataType::Quote, key, &quote_key_string(ticker_name, &time, id))
+    }
+
+    pub(super) fn get_latest_quote(self, ticker_name: &str) -> Result<Option<Quote>, DataError> {
+        let quote_prefix = prefix_key(DataType::Quote, ticker_name);
+        let upper = prefix_end(&quote_prefix);
+
+        self.bounded_cursor(DataType::Quote, quote_prefix, upper, Direction::Reverse)
             .next()
-            .map(|item|
-                match bincode::deserialize::<Quote>(&item.1) {
-                    Ok(quote) => Some(quote),
-                    _ => None,
-                }
-            )
-            .unwrap_or(None)
-    }
-
-    fn get_oldest_quote(&mut self, ticker_name: &str) -> Option<Quote> {
-        let quote_prefix = self.build_key(
-            &DataType::Quote,
-            &ticker_name,
-            "\x7f",
-        );
+            .transpose()
+    }
 
-        self.db
-            .iterator(
-                IteratorMode::From(&quote_prefix, Direction::Reverse)
-            )
+    pub(super) fn get_oldest_quote(self, ticker_name: &str) -> Result<Option<Quote>, DataError> {
+        let quote_prefix = prefix_key(DataType::Quote, ticker_name);
+        let upper = prefix_end(&quote_prefix);
+
+        self.bounded_cursor(DataType::Quote, quote_prefix, upper, Direction::Forward)
             .next()
-            .map(|item|
-                match bincode::deserialize::<Quote>(&item.1) {
-                    Ok(quote) => Some(quote),
-                    _ => None,
-                }
-            )
-            .unwrap_or(None)
+            .transpose()
+    }
+
+    pub(super) fn quote_cursor_forward(self, ticker: &Ticker, time: DateTime<Utc>) -> Cursor<'a, Quote> {
+        let quote_prefix = prefix_key(DataType::Quote, &ticker.name);
+
+        self.bounded_cursor(
+            DataType::Quote,
+            quote_key(&ticker.name, &time, i64::MIN),
+            prefix_end(&quote_prefix),
+            Direction::Forward,
+        )
+    }
+
+    pub(super) fn quote_cursor_reverse(self, ticker: &Ticker, time: DateTime<Utc>) -> Cursor<'a, Quote> {
+        let quote_prefix = prefix_key(DataType::Quote, &ticker.name);
+
+        self.bounded_cursor(
+            DataType::Quote,
+            quote_prefix,
+            next_key(&quote_key(&ticker.name, &time, i64::MAX)),
+            Direction::Reverse,
+        )
+    }
+
+    pub(super) fn quote_range_forward(self, ticker: &Ticker, from: DateTime<Utc>, to: DateTime<Utc>) -> Cursor<'a, Quote> {
+        self.bounded_cursor(
+            DataType::Quote,
+            quote_key(&ticker.name, &from, i64::MIN),
+            quote_key(&ticker.name, &to, i64::MIN),
+            Direction::Forward,
+        )
+    }
+
+    pub(super) fn quote_range_reverse(self, ticker: &Ticker, from: DateTime<Utc>, to: DateTime<Utc>) -> Cursor<'a, Quote> {
+        self.bounded_cursor(
+            DataType::Quote,
+            quote_key(&ticker.name, &from, i64::MIN),
+            quote_key(&ticker.name, &to, i64::MIN),
+            Direction::Reverse,
+        )
+    }
+}
+
+/// RocksDB implementation of quote handler
+impl QuoteHandler for RocksDB {
+    fn get_ticker_by_name(&self, name: &str) -> Result<Ticker, DataError> {
+        self.reader().get_ticker_by_name(name)
+    }
+
+    fn list_tickers(&self, filter: &TickerFilter, page: &Page) -> Result<Vec<Ticker>, DataError> {
+        self.reader().list_tickers(filter, page)
+    }
+
+    fn get_quote_by_id(&self, ticker_name: &str, time: DateTime<Utc>, id: i64) -> Result<Quote, DataError> {
+        self.reader().get_quote_by_id(ticker_name, time, id)
+    }
+
+    fn get_latest_quote(&self, ticker_name: &str) -> Result<Option<Quote>, DataError> {
+        self.reader().get_latest_quote(ticker_name)
+    }
+
+    fn get_oldest_quote(&self, ticker_name: &str) -> Result<Option<Quote>, DataError> {
+        self.reader().get_oldest_quote(ticker_name)
     }
 
     fn insert_ticker(&mut self, ticker: &Ticker) -> Result<(), DataError> {
-        self.update_ticker(ticker)
+        self.write_batch(&Operation::InsertTicker(ticker.clone()).into())
     }
 
     fn update_ticker(&mut self, ticker: &Ticker) -> Result<(), DataError> {
-        let key = self.build_key(
-            &DataType::Ticker,
-            &ticker.name,
-            "",
-        );
+        self.write_batch(&Operation::UpdateTicker(ticker.clone()).into())
+    }
 
-        self.db
-            .put(
-                key,
-                bincode::serialize(&ticker).unwrap(),
-            )
-            .map_err(|_| DataError::InsertFailed)
+    fn upsert_ticker(&mut self, ticker: &Ticker) -> Result<(), DataError> {
+        self.write_batch(&Operation::UpsertTicker(ticker.clone()).into())
     }
 
     fn delete_ticker(&mut self, ticker: &Ticker) -> Result<(), DataError> {
-        let key = self.build_key(
-            &DataType::Ticker,
-            &ticker.name,
-            "",
-        );
-
-        self.db
-            .delete(key)
-            .map_err(|_| DataError::DeleteFailed)
+        self.write_batch(&Operation::DeleteTicker(ticker.clone()).into())
     }
 
-    fn insert_quote(&mut self, quote: &Quote) -> Result<(), DataError> {
-        self.update_quote(quote)
+    fn insert_quote(&mut self, quote: &Quote) -> Result<i64, DataError> {
+        // a batch of a single insert returns a single id
+        self.write(&Operation::InsertQuote(quote.clone()).into())
+            .map(|quote_ids| quote_ids[0])
     }
 
     fn update_quote(&mut self, quote: &Quote) -> Result<(), DataError> {
-        let ticker_key = self.build_key(
-            &DataType::Quote,
-            &quote.ticker,
-            &quote.time.timestamp_nanos().to_string(),
-        );
-
-        let key = self.build_subkey(
-            &ticker_key,
-            quote.id.unwrap_or(0).to_string().as_bytes(),
-        );
+        self.write_batch(&Operation::UpdateQuote(quote.clone()).into())
+    }
 
-        self.db
-            .put(
-                key,
-                bincode::serialize(&quote).unwrap(),
-            )
-            .map_err(|_| DataError::InsertFailed)
+    fn upsert_quote(&mut self, quote: &Quote) -> Result<(), DataError> {
+        self.write_batch(&Operation::UpsertQuote(quote.clone()).into())
     }
 
     fn delete_quote(&mut self, quote: &Quote) -> Result<(), DataError> {
-        let ticker_key = self.build_key(
-            &DataType::Quote,
-            &quote.ticker,
-            &quote.time.timestamp_nanos().to_string(),
-        );
-
-        let key = self.build_subkey(
-            &ticker_key,
-            quote.id.unwrap_or(0).to_string().as_bytes(),
-        );
+        self.write_batch(&Operation::DeleteQuote(quote.clone()).into())
+    }
 
+    fn delete_quotes(&mut self, ticker_name: &str, from: DateTime<Utc>, to: DateTime<Utc>) -> Result<(), DataError> {
+        self.check_writable()?;
+        if from >= to {
+            return Ok(());
+        }
 
+        // a single range tombstone instead of a tombstone per quote
+        let mut batch = WriteBatch::default();
+        batch.delete_range_cf(
+            self.cf(DataType::Quote)?,
+            quote_key(ticker_name, &from, i64::MIN),
+            quote_key(ticker_name, &to, i64::MIN),
+        );
         self.db
-            .delete(key)
-            .map_err(|_| DataError::DeleteFailed)
+            .write_opt(batch, &self.options.write_options())
+            .map_err(|e| DataError::storage(format!("delete quotes of ticker '{}'", ticker_name), e))?;
+
+        self.feed.publish(vec![Change::QuotesDeleted {
+            ticker: ticker_name.to_string(),
+            from: Some(from),
+            to: Some(to),
+        }]);
+        Ok(())
     }
 
-    fn quote_cursor_forward(&mut self, ticker: &Ticker, time: DateTime<Utc>) -> Box<dyn Iterator<Item=Quote> + '_> {
-        let quote_prefix = self.build_key(
-            &DataType::Quote,
-            &ticker.name,
-            &time.timestamp_nanos().to_string(),
-        );
+    fn insert_quotes(&mut self, quotes: &[Quote], on_duplicate: OnDuplicate) -> Result<BulkReport, DataError> {
+        self.write_quotes(quotes, on_duplicate)
+    }
 
-        let iter =
-            self.db
-                .iterator(
-                    IteratorMode::From(&quote_prefix, Direction::Forward)
-                )
-                .filter_map(|item|
-                    bincode::deserialize::<Quote>(&item.1)
-                        .ok()
-                );
-
-        Box::new(
-            iter
-        )
+    fn quote_cursor_forward(&self, ticker: &Ticker, time: DateTime<Utc>) -> Cursor<'_, Quote> {
+        self.reader().quote_cursor_forward(ticker, time)
     }
 
-    fn quote_cursor_reverse(&mut self, ticker: &Ticker, time: DateTime<Utc>) -> Box<dyn Iterator<Item=Quote> + '_> {
-        let quote_prefix = self.build_key(
-            &DataType::Quote,
-            &ticker.name,
-            &time.timestamp_nanos().to_string(),
-        );
+    fn quote_cursor_reverse(&self, ticker: &Ticker, time: DateTime<Utc>) -> Cursor<'_, Quote> {
+        self.reader().quote_cursor_reverse(ticker, time)
+    }
 
-        let iter =
-            self.db
-                .iterator(
-                    IteratorMode::From(&quote_prefix, Direction::Reverse)
-                )
-                .filter_map(|item|
-                    match bincode::deserialize::<Quote>(&item.1) {
-                        Ok(res) => Some(res.to_owned()),
-                        Err(_) => None
-                    }
-                );
-
-        Box::new(
-            iter
-        )
+    fn quote_range_forward(&self, ticker: &Ticker, from: DateTime<Utc>, to: DateTime<Utc>) -> Cursor<'_, Quote> {
+        self.reader().quote_range_forward(ticker, from, to)
+    }
+
+    fn quote_range_reverse(&self, ticker: &Ticker, from: DateTime<Utc>, to: DateTime<Utc>) -> Cursor<'_, Quote> {
+        self.reader().quote_range_reverse(ticker, from, to)
     }
 }
@@ -0,0 +1,123 @@
+//! Reads of a rocksdb database, shared by the handler and its snapshots
+use std::iter;
+
+use rocksdb::{ColumnFamily, DBIterator, Direction, IteratorMode, ReadOptions, Snapshot};
+use serde::de::DeserializeOwned;
+
+use super::key_codec::{key_to_string, KEY_VERSION};
+use super::value_codec;
+use super::RocksDB;
+
+use crate::data_handler::{Cursor, DataError, DataType};
+
+/// Reads of the latest state of a `RocksDB`, or of a snapshot of it
+///
+/// The read methods of the handler traits are implemented here once for both, cursors
+/// borrow the database and snapshot instead of the reader.
+#[derive(Clone, Copy)]
+pub(super) struct Reader<'a> {
+    db: &'a RocksDB,
+    snapshot: Option<&'a Snapshot<'a>>,
+}
+
+impl RocksDB {
+    /// Reader of the latest state of the database
+    pub(super) fn reader(&self) -> Reader<'_> {
+        Reader { db: self, snapshot: None }
+    }
+}
+
+impl<'a> Reader<'a> {
+    /// Reader of the state of the database as of the snapshot
+    pub(super) fn at(db: &'a RocksDB, snapshot: &'a Snapshot<'a>) -> Reader<'a> {
+        Reader { db, snapshot: Some(snapshot) }
+    }
+
+    pub(super) fn cf(self, data_type: DataType) -> Result<&'a ColumnFamily, DataError> {
+        self.db.cf(data_type)
+    }
+
+    pub(super) fn cf_handle(self, name: &str) -> Result<&'a ColumnFamily, DataError> {
+        self.db.cf_handle(name)
+    }
+
+    pub(super) fn get_cf(self, cf: &ColumnFamily, key: &[u8]) -> Result<Option<Vec<u8>>, rocksdb::Error> {
+        match self.snapshot {
+            Some(snapshot) => snapshot.get_cf(cf, key),
+            None => self.db.db.get_cf(cf, key),
+        }
+    }
+
+    pub(super) fn iterator_cf_opt(
+        self,
+        cf: &ColumnFamily,
+        read_opts: ReadOptions,
+        mode: IteratorMode,
+    ) -> DBIterator<'a> {
+        match self.snapshot {
+            Some(snapshot) => snapshot.iterator_cf_opt(cf, read_opts, mode),
+            None => self.db.db.iterator_cf_opt(cf, read_opts, mode),
+        }
+    }
+
+    /// Read and deserialize the record stored under the key in the column family of the
+    /// data type, `record_key` is the readable key reported in errors
+    pub(super) fn get_record<T: DeserializeOwned>(
+        self,
+        data_type: DataType,
+        key: Vec<u8>,
+        record_key: &str,
+    ) -> Result<T, DataError> {
+        let value = self
+            .get_cf(self.cf(data_type)?, &key)
+            .map_err(|e| DataError::storage(format!("read {} '{}'", data_type, record_key), e))?
+            .ok_or_else(|| DataError::not_found(data_type, record_key))?;
+
+        value_codec::decode(&value).map_err(|e| DataError::serialization(data_type, record_key, e))
+    }
+
+    /// Read all records of the data type in key order, which orders names by length first
+    pub(super) fn all_records<T: DeserializeOwned + 'a>(self, data_type: DataType) -> Result<Vec<T>, DataError> {
+        let tag = data_type as u8;
+        self.bounded_cursor(data_type, vec![KEY_VERSION, tag], vec![KEY_VERSION, tag + 1], Direction::Forward)
+            .collect()
+    }
+
+    /// Iterate over the values with keys in `[lower, upper)`, in ascending key order for
+    /// `Direction::Forward` and descending key order otherwise
+    ///
+    /// Values that can't be deserialized are reported as errors instead of being skipped.
+    pub(super) fn bounded_cursor<T: DeserializeOwned + 'a>(
+        self,
+        data_type: DataType,
+        lower: Vec<u8>,
+        upper: Vec<u8>,
+        direction: Direction,
+    ) -> Cursor<'a, T> {
+        if lower >= upper {
+            return Box::new(iter::empty());
+        }
+
+        let cf = match self.cf(data_type) {
+            Ok(cf) => cf,
+            Err(err) => return Box::new(iter::once(Err(err))),
+        };
+
+        let mut read_opts = ReadOptions::default();
+        read_opts.set_iterate_lower_bound(lower);
+        read_opts.set_iterate_upper_bound(upper);
+
+        let mode = match direction {
+            Direction::Forward => IteratorMode::Start,
+            Direction::Reverse => IteratorMode::End,
+        };
+
+        Box::new(
+            self.iterator_cf_opt(cf, read_opts, mode)
+                .map(move |(key, value)|
+                    value_codec::decode(&value)
+                        .map_err(|e| DataError::serialization(data_type, key_to_string(&key), e))
+                )
+        )
+    }
+}
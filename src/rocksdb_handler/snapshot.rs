@@ -0,0 +1,36 @@
+//! Implementation of rocksdb snapshots
+use rocksdb::Snapshot as DBSnapshot;
+
+use super::reader::Reader;
+use super::RocksDB;
+
+use crate::data_handler::snapshot::delegate_snapshot;
+use crate::data_handler::{DataError, Snapshot, SnapshotHandler};
+
+/// Snapshot of a `RocksDB`, which pins the state of the database as of its creation
+///
+/// Writes of the database are not blocked by snapshots, but compactions keep the values
+/// they replace until all snapshots reading them are dropped, hence long-lived snapshots
+/// make the database grow.
+pub struct RocksDBSnapshot<'a> {
+    db: &'a RocksDB,
+    snapshot: DBSnapshot<'a>,
+}
+
+impl<'a> RocksDBSnapshot<'a> {
+    pub(super) fn new(db: &'a RocksDB) -> RocksDBSnapshot<'a> {
+        RocksDBSnapshot { db, snapshot: db.db.snapshot() }
+    }
+
+    fn reader(&self) -> Reader<'_> {
+        Reader::at(self.db, &self.snapshot)
+    }
+}
+
+delegate_snapshot!(RocksDBSnapshot<'_>, reader());
+
+impl SnapshotHandler for RocksDB {
+    fn snapshot(&mut self) -> Result<Box<dyn Snapshot + '_>, DataError> {
+        Ok(Box::new(RocksDBSnapshot::new(self)))
+    }
+}
@@ -1,138 +1,181 @@
-///! Implementation of sqlite3 data handler
-use crate::data_handler::{DataError, TransactionHandler, DataType};
+///! Implementation of rocksdb transaction handler
+use crate::data_handler::transaction_handler::booking_date_bound;
+use crate::data_handler::{
+    transaction_key_string, BatchHandler, Cursor, DataError, DataType, Operation, Page, TransactionHandler,
+};
 use crate::transaction::Transaction;
 
+use super::column_family::TRANSACTION_INDEX_CF;
+use super::key_codec::{
+    decode_key, key_to_string, prefix_end, prefix_key, transaction_date_key, transaction_index_key, Key,
+    KEY_VERSION,
+};
+use super::reader::Reader;
 use super::RocksDB;
-use chrono::{Utc, DateTime};
-use rocksdb::{IteratorMode, Direction};
+use chrono::naive::MIN_DATE;
+use chrono::{Utc, DateTime, NaiveDate};
+use rocksdb::{Direction, IteratorMode, ReadOptions};
+
+impl<'a> Reader<'a> {
+    /// Iterate over the transactions with booking dates in `[from, to)`, where `None` is
+    /// beyond any date
+    fn transactions_between(
+        self,
+        sort_prefix: &str,
+        from: Option<NaiveDate>,
+        to: Option<NaiveDate>,
+        direction: Direction,
+    ) -> Cursor<'a, Transaction> {
+        let transaction_prefix = prefix_key(DataType::Transaction, sort_prefix);
+        let bound = |date: Option<NaiveDate>| match date {
+            Some(date) => transaction_date_key(sort_prefix, date),
+            None => prefix_end(&transaction_prefix),
+        };
+
+        self.bounded_cursor(DataType::Transaction, bound(from), bound(to), direction)
+    }
 
-impl TransactionHandler for RocksDB {
-    fn get_transaction_by_id(&mut self, sort_prefix: &str, id: u128) -> Result<Transaction, DataError> {
-        let key = self.build_key(
-            &DataType::Transaction,
-            &sort_prefix,
-            &id.to_string(),
-        );
-
-        match self.db.get(key) {
-            Ok(Some(data)) =>
-                match bincode::deserialize(&data) {
-                    Ok(asset) => Ok(asset),
-                    _ => Err(DataError::DataAccessFailure),
-                },
-            _ => Err(DataError::NotFound),
+    pub(super) fn get_transaction_by_id(self, sort_prefix: &str, id: u128) -> Result<Transaction, DataError> {
+        let record_key = transaction_key_string(sort_prefix, id);
+        let key = self
+            .get_cf(self.cf_handle(TRANSACTION_INDEX_CF)?, &transaction_index_key(sort_prefix, id))
+            .map_err(|e| DataError::storage(format!("read index of transaction '{}'", record_key), e))?
+            .ok_or_else(|| DataError::not_found(DataType::Transaction, &record_key))?;
+
+        self.get_record(DataType::Transaction, key, &record_key)
+    }
+
+    pub(super) fn list_sort_prefixes(self, page: &Page) -> Result<Vec<String>, DataError> {
+        let cf = self.cf(DataType::Transaction)?;
+        let mut sort_prefixes = Vec::new();
+        let mut lower = vec![KEY_VERSION, DataType::Transaction as u8];
+
+        // seek from one sort prefix to the next instead of visiting every transaction
+        loop {
+            let mut read_opts = ReadOptions::default();
+            read_opts.set_total_order_seek(true);
+            let mode = IteratorMode::From(&lower, Direction::Forward);
+            let key = match self.iterator_cf_opt(cf, read_opts, mode).next() {
+                Some((key, _)) => key,
+                None => break,
+            };
+
+            match decode_key(&key) {
+                Some(Key::Transaction { sort_prefix, .. }) => {
+                    lower = prefix_end(&prefix_key(DataType::Transaction, &sort_prefix));
+                    sort_prefixes.push(sort_prefix);
+                }
+                _ => {
+                    return Err(DataError::serialization(
+                        DataType::Transaction,
+                        key_to_string(&key),
+                        "invalid transaction key",
+                    ))
+                }
+            }
         }
+
+        // keys order sort prefixes by length first
+        sort_prefixes.sort();
+        Ok(page.select(sort_prefixes, |sort_prefix| sort_prefix))
     }
 
-    fn get_latest_transaction(&mut self, sort_prefix: &str) -> Option<Transaction> {
-        let quote_prefix = self.build_key(
-            &DataType::Transaction,
-            &sort_prefix,
-            "",
-        );
+    pub(super) fn get_latest_transaction(self, sort_prefix: &str) -> Result<Option<Transaction>, DataError> {
+        let transaction_prefix = prefix_key(DataType::Transaction, sort_prefix);
+        let upper = prefix_end(&transaction_prefix);
 
-        self.db.iterator(
-            IteratorMode::From(&quote_prefix, Direction::Forward)
-        )
+        self.bounded_cursor(DataType::Transaction, transaction_prefix, upper, Direction::Reverse)
             .next()
-            .map(|item|
-                match bincode::deserialize::<Transaction>(&item.1) {
-                    Ok(quote) => Some(quote),
-                    _ => None,
-                }
-            )
-            .unwrap_or(None)
+            .transpose()
     }
 
-    fn get_oldest_transaction(&mut self, sort_prefix: &str) -> Option<Transaction> {
-        let quote_prefix = self.build_key(
-            &DataType::Transaction,
-            &sort_prefix,
-            &"\x7f",
-        );
+    pub(super) fn get_oldest_transaction(self, sort_prefix: &str) -> Result<Option<Transaction>, DataError> {
+        let transaction_prefix = prefix_key(DataType::Transaction, sort_prefix);
+        let upper = prefix_end(&transaction_prefix);
 
-        self.db.iterator(
-            IteratorMode::From(&quote_prefix, Direction::Forward)
-        )
+        self.bounded_cursor(DataType::Transaction, transaction_prefix, upper, Direction::Forward)
             .next()
-            .map(|item|
-                match bincode::deserialize::<Transaction>(&item.1) {
-                    Ok(quote) => Some(quote),
-                    _ => None,
-                }
-            )
-            .unwrap_or(None)
+            .transpose()
+    }
+
+    pub(super) fn transaction_cursor_forward(self, sort_prefix: &str, time: DateTime<Utc>) -> Cursor<'a, Transaction> {
+        self.transactions_between(sort_prefix, booking_date_bound(time, true), None, Direction::Forward)
+    }
+
+    pub(super) fn transaction_cursor_reverse(self, sort_prefix: &str, time: DateTime<Utc>) -> Cursor<'a, Transaction> {
+        let to = booking_date_bound(time, false);
+        self.transactions_between(sort_prefix, Some(MIN_DATE), to, Direction::Reverse)
+    }
+
+    pub(super) fn transaction_range_forward(self, sort_prefix: &str, from: DateTime<Utc>, to: DateTime<Utc>) -> Cursor<'a, Transaction> {
+        let (from, to) = (booking_date_bound(from, true), booking_date_bound(to, true));
+        self.transactions_between(sort_prefix, from, to, Direction::Forward)
+    }
+
+    pub(super) fn transaction_range_reverse(self, sort_prefix: &str, from: DateTime<Utc>, to: DateTime<Utc>) -> Cursor<'a, Transaction> {
+        let (from, to) = (booking_date_bound(from, true), booking_date_bound(to, true));
+        self.transactions_between(sort_prefix, from, to, Direction::Reverse)
+    }
+}
+
+impl TransactionHandler for RocksDB {
+    fn get_transaction_by_id(&self, sort_prefix: &str, id: u128) -> Result<Transaction, DataError> {
+        self.reader().get_transaction_by_id(sort_prefix, id)
+    }
+
+    fn list_sort_prefixes(&self, page: &Page) -> Result<Vec<String>, DataError> {
+        self.reader().list_sort_prefixes(page)
+    }
+
+    fn get_latest_transaction(&self, sort_prefix: &str) -> Result<Option<Transaction>, DataError> {
+        self.reader().get_latest_transaction(sort_prefix)
+    }
+
+    fn get_oldest_transaction(&self, sort_prefix: &str) -> Result<Option<Transaction>, DataError> {
+        self.reader().get_oldest_transaction(sort_prefix)
     }
 
     fn insert_transaction(&mut self, sort_prefix: &str, transaction: &Transaction) -> Result<(), DataError> {
-        self.update_transaction(sort_prefix, transaction)
+        self.write_batch(&Operation::InsertTransaction {
+            sort_prefix: sort_prefix.to_string(),
+            transaction: transaction.clone(),
+        }.into())
     }
 
     fn update_transaction(&mut self, sort_prefix: &str, transaction: &Transaction) -> Result<(), DataError> {
-        let key = self.build_key(
-            &DataType::Transaction,
-            &sort_prefix,
-            &transaction.id.to_string(),
-        );
+        self.write_batch(&Operation::UpdateTransaction {
+            sort_prefix: sort_prefix.to_string(),
+            transaction: transaction.clone(),
+        }.into())
+    }
 
-        self.db
-            .put(
-                key,
-                bincode::serialize(&transaction).unwrap(),
-            )
-            .map_err(|_| DataError::InsertFailed)
+    fn upsert_transaction(&mut self, sort_prefix: &str, transaction: &Transaction) -> Result<(), DataError> {
+        self.write_batch(&Operation::UpsertTransaction {
+            sort_prefix: sort_prefix.to_string(),
+            transaction: transaction.clone(),
+        }.into())
     }
 
     fn delete_transaction(&mut self, sort_prefix: &str, transaction: &Transaction) -> Result<(), DataError> {
-        let key = self.build_key(
-            &DataType::Transaction,
-            &sort_prefix,
-            &transaction.id.to_string(),
-        );
-
-        self.db
-            .delete(key)
-            .map_err(|_| DataError::DeleteFailed)
-    }
-
-    fn transaction_cursor_forward(&mut self, sort_prefix: &str, time: DateTime<Utc>) -> Box<dyn Iterator<Item=Transaction> + '_> {
-        let quote_prefix = self.build_key(
-            &DataType::Transaction,
-            &sort_prefix,
-            &time.timestamp_nanos().to_string(),
-        );
-
-        Box::new(
-            self.db
-                .iterator(
-                    IteratorMode::From(&quote_prefix, Direction::Forward)
-                )
-                .filter_map(|item|
-                    bincode::deserialize::<Transaction>(&item.1)
-                        .ok()
-                )
-        )
-    }
-
-    fn transaction_cursor_reverse(&mut self, sort_prefix: &str, time: DateTime<Utc>) -> Box<dyn Iterator<Item=Transaction> + '_> {
-        let quote_prefix = self.build_key(
-            &DataType::Transaction,
-            &sort_prefix,
-            &time.timestamp_nanos().to_string(),
-        );
-
-        let iter =
-            self.db
-                .iterator(
-                    IteratorMode::From(&quote_prefix, Direction::Reverse)
-                )
-                .filter_map(|item|
-                    bincode::deserialize::<Transaction>(&item.1)
-                        .ok()
-                );
-
-        Box::new(
-            iter
-        )
+        self.write_batch(&Operation::DeleteTransaction {
+            sort_prefix: sort_prefix.to_string(),
+            transaction: transaction.clone(),
+        }.into())
+    }
+
+    fn transaction_cursor_forward(&self, sort_prefix: &str, time: DateTime<Utc>) -> Cursor<'_, Transaction> {
+        self.reader().transaction_cursor_forward(sort_prefix, time)
+    }
+
+    fn transaction_cursor_reverse(&self, sort_prefix: &str, time: DateTime<Utc>) -> Cursor<'_, Transaction> {
+        self.reader().transaction_cursor_reverse(sort_prefix, time)
+    }
+
+    fn transaction_range_forward(&self, sort_prefix: &str, from: DateTime<Utc>, to: DateTime<Utc>) -> Cursor<'_, Transaction> {
+        self.reader().transaction_range_forward(sort_prefix, from, to)
+    }
+
+    fn transaction_range_reverse(&self, sort_prefix: &str, from: DateTime<Utc>, to: DateTime<Utc>) -> Cursor<'_, Transaction> {
+        self.reader().transaction_range_reverse(sort_prefix, from, to)
     }
 }
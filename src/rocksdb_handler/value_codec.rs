@@ -0,0 +1,53 @@
+//! Versioned encoding of values of the RocksDB backend
+//!
+//! Values are stored as a format version byte followed by the bincode encoding of the
+//! record. Once a record struct changes, `FORMAT_VERSION` is incremented and `decode`
+//! keeps reading values of earlier versions by converting them to the current struct,
+//! while `migrate` of the schema rewrites them in the current format.
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::data_handler::BoxError;
+
+/// Version of the value format written by this version
+pub const FORMAT_VERSION: u8 = 1;
+
+/// Wrap the bincode encoding of a record in the current format
+pub fn wrap(payload: &[u8]) -> Vec<u8> {
+    let mut value = Vec::with_capacity(payload.len() + 1);
+    value.push(FORMAT_VERSION);
+    value.extend_from_slice(payload);
+    value
+}
+
+pub fn encode<T: Serialize>(record: &T) -> Result<Vec<u8>, BoxError> {
+    Ok(wrap(&bincode::serialize(record)?))
+}
+
+pub fn decode<T: DeserializeOwned>(value: &[u8]) -> Result<T, BoxError> {
+    match value.split_first() {
+        Some((&FORMAT_VERSION, payload)) => Ok(bincode::deserialize(payload)?),
+        Some((version, _)) => Err(format!("unsupported value format version {}", version).into()),
+        None => Err("empty value".into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asset::Asset;
+
+    #[test]
+    fn test_value_format() {
+        let asset = Asset::new("Apple", None, Some("US0378331005".to_string()), None);
+        let value = encode(&asset).unwrap();
+        assert_eq!(value[0], FORMAT_VERSION);
+        assert_eq!(&value[1..], bincode::serialize(&asset).unwrap().as_slice());
+        assert_eq!(decode::<Asset>(&value).unwrap().isin, asset.isin);
+
+        let mut unknown = value;
+        unknown[0] = FORMAT_VERSION + 1;
+        assert!(decode::<Asset>(&unknown).is_err());
+        assert!(decode::<Asset>(&[]).is_err());
+    }
+}
@@ -0,0 +1,133 @@
+//! Implementation of sqlite3 asset handler
+use super::{read_error, write_error, SQLiteDB};
+
+use crate::asset::Asset;
+use crate::data_handler::asset_handler::duplicate_id;
+use crate::data_handler::{AssetHandler, BatchHandler, DataError, DataType, Operation, Page};
+
+use rusqlite::{params, OptionalExtension, Row};
+
+
+fn asset_from_row(row: &Row) -> rusqlite::Result<Asset> {
+    Ok(Asset {
+        name: row.get(0)?,
+        wkn: row.get(1)?,
+        isin: row.get(2)?,
+        note: row.get(3)?,
+    })
+}
+
+impl SQLiteDB {
+    /// Get the asset with the given value in the column `name`, `isin` or `wkn`
+    fn query_asset(&self, column: &str, value: &str) -> Result<Asset, DataError> {
+        self.conn
+            .query_row(
+                &format!("SELECT name, wkn, isin, note FROM assets WHERE {} = ?1", column),
+                params![value],
+                asset_from_row,
+            )
+            .map_err(|e| read_error(e, DataType::Asset, value))
+    }
+
+    /// Fail if the ISIN or WKN `id` of the asset is used by another asset
+    ///
+    /// The unique indexes reject duplicates as well, but can't tell which asset uses the id.
+    fn check_unique(
+        &self,
+        asset: &Asset,
+        column: &str,
+        id_kind: &str,
+        id: Option<&str>,
+    ) -> Result<(), DataError> {
+        let id = match id {
+            Some(id) => id,
+            None => return Ok(()),
+        };
+        let owner: Option<String> = self
+            .conn
+            .query_row(
+                &format!("SELECT name FROM assets WHERE {} = ?1 AND name != ?2", column),
+                params![id, asset.name],
+                |row| row.get(0),
+            )
+            .optional()
+            .map_err(|e| read_error(e, DataType::Asset, id))?;
+
+        match owner {
+            Some(owner) => Err(duplicate_id(asset, id_kind, id, &owner)),
+            None => Ok(()),
+        }
+    }
+
+    pub(super) fn put_asset(&self, asset: &Asset) -> Result<(), DataError> {
+        self.check_unique(asset, "isin", "ISIN", asset.isin.as_deref())?;
+        self.check_unique(asset, "wkn", "WKN", asset.wkn.as_deref())?;
+
+        // an upsert instead of INSERT OR REPLACE, which would delete assets with the same
+        // ISIN or WKN
+        self.conn
+            .execute(
+                "INSERT INTO assets (name, wkn, isin, note) VALUES (?1, ?2, ?3, ?4) \
+                 ON CONFLICT (name) DO UPDATE SET wkn = ?2, isin = ?3, note = ?4",
+                params![asset.name, asset.wkn, asset.isin, asset.note],
+            )
+            .map(|_| ())
+            .map_err(|e| write_error(e, DataType::Asset, &asset.name))
+    }
+
+    pub(super) fn remove_asset(&self, name: &str) -> Result<(), DataError> {
+        self.conn
+            .execute(
+                "DELETE FROM assets WHERE name = ?1",
+                params![name],
+            )
+            .map(|_| ())
+            .map_err(|e| write_error(e, DataType::Asset, name))
+    }
+}
+
+impl AssetHandler for SQLiteDB {
+    fn get_asset_by_name(&self, name: &str) -> Result<Asset, DataError> {
+        self.query_asset("name", name)
+    }
+
+    fn get_asset_by_isin(&self, isin: &str) -> Result<Asset, DataError> {
+        self.query_asset("isin", isin)
+    }
+
+    fn get_asset_by_wkn(&self, wkn: &str) -> Result<Asset, DataError> {
+        self.query_asset("wkn", wkn)
+    }
+
+    fn list_assets(&self, page: &Page) -> Result<Vec<Asset>, DataError> {
+        let mut stmt = self
+            .conn
+            .prepare(
+                "SELECT name, wkn, isin, note FROM assets WHERE (?1 IS NULL OR name > ?1) \
+                 ORDER BY name LIMIT ?2",
+            )
+            .map_err(|e| DataError::storage("list assets", e))?;
+
+        let assets = stmt
+            .query_map(params![page.after, page.limit as i64], asset_from_row)
+            .and_then(|rows| rows.collect())
+            .map_err(|e| DataError::storage("list assets", e));
+        assets
+    }
+
+    fn insert_asset(&mut self, asset: &Asset) -> Result<(), DataError> {
+        self.write_batch(&Operation::InsertAsset(asset.clone()).into())
+    }
+
+    fn update_asset(&mut self, asset: &Asset) -> Result<(), DataError> {
+        self.write_batch(&Operation::UpdateAsset(asset.clone()).into())
+    }
+
+    fn upsert_asset(&mut self, asset: &Asset) -> Result<(), DataError> {
+        self.write_batch(&Operation::UpsertAsset(asset.clone()).into())
+    }
+
+    fn delete_asset(&mut self, asset: &Asset) -> Result<(), DataError> {
+        self.write_batch(&Operation::DeleteAsset(asset.clone()).into())
+    }
+}
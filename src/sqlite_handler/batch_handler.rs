@@ -0,0 +1,207 @@
+//! Implementation of sqlite3 batch handler
+use super::quote_handler::ticker_from_row;
+use super::transaction_handler::{id_to_sql, sql_to_id};
+use super::{read_error, time_to_sql, write_error, SQLiteDB};
+
+use crate::data_handler::bulk::apply_quotes;
+use crate::data_handler::integrity::{apply_batch, BatchStore};
+use crate::data_handler::{
+    transaction_key_string, Batch, BatchHandler, BulkReport, Change, ChangeFilter, DataError, DataType, OnDuplicate,
+    Operation, SubscriptionHandler,
+};
+use crate::quote::{Quote, Ticker};
+
+use chrono::{DateTime, Utc};
+use rusqlite::{params, OptionalExtension, ToSql};
+use std::sync::mpsc::Receiver;
+
+
+impl SQLiteDB {
+    /// Whether the query for the record with the given readable key returns any row
+    fn exists(&self, sql: &str, data_type: DataType, key: &str) -> Result<bool, DataError> {
+        self.exists_with(sql, params![key], data_type, key)
+    }
+
+    fn exists_with(&self, sql: &str, params: &[&dyn ToSql], data_type: DataType, key: &str) -> Result<bool, DataError> {
+        // cached, since bulk inserts check each quote
+        self.conn
+            .prepare_cached(&format!("SELECT EXISTS ({})", sql))
+            .and_then(|mut stmt| stmt.query_row(params, |row| row.get(0)))
+            .map_err(|e| read_error(e, data_type, key))
+    }
+}
+
+impl BatchStore for SQLiteDB {
+    fn asset_exists(&mut self, name: &str) -> Result<bool, DataError> {
+        self.exists("SELECT 1 FROM assets WHERE name = ?1", DataType::Asset, name)
+    }
+
+    fn ticker_exists(&mut self, name: &str) -> Result<bool, DataError> {
+        self.exists("SELECT 1 FROM tickers WHERE name = ?1", DataType::Ticker, name)
+    }
+
+    fn has_quotes(&mut self, ticker: &str) -> Result<bool, DataError> {
+        self.exists("SELECT 1 FROM quotes WHERE ticker = ?1", DataType::Ticker, ticker)
+    }
+
+    fn asset_tickers(&mut self, asset: &str) -> Result<Vec<Ticker>, DataError> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT name, asset, currency, priority, factor FROM tickers WHERE asset = ?1 ORDER BY name")
+            .map_err(|e| read_error(e, DataType::Asset, asset))?;
+
+        let tickers = stmt
+            .query_map(params![asset], ticker_from_row)
+            .and_then(|rows| rows.collect())
+            .map_err(|e| read_error(e, DataType::Asset, asset));
+        tickers
+    }
+
+    fn asset_transaction(&mut self, asset: &str) -> Result<Option<String>, DataError> {
+        // only asset, dividend and interest transactions have an asset name
+        self.conn
+            .query_row(
+                "SELECT sort_prefix, id FROM transactions WHERE asset_name = ?1 LIMIT 1",
+                params![asset],
+                |row| {
+                    let sort_prefix: String = row.get(0)?;
+                    let id: String = row.get(1)?;
+                    Ok(transaction_key_string(&sort_prefix, sql_to_id(&id, 1)?))
+                },
+            )
+            .optional()
+            .map_err(|e| read_error(e, DataType::Asset, asset))
+    }
+
+    fn record_exists(&mut self, operation: &Operation) -> Result<bool, DataError> {
+        let (data_type, key) = operation.record_key();
+        match operation {
+            Operation::InsertAsset(asset)
+            | Operation::UpdateAsset(asset)
+            | Operation::UpsertAsset(asset)
+            | Operation::DeleteAsset(asset) => self.asset_exists(&asset.name),
+            Operation::InsertTicker(ticker)
+            | Operation::UpdateTicker(ticker)
+            | Operation::UpsertTicker(ticker)
+            | Operation::DeleteTicker(ticker) => self.ticker_exists(&ticker.name),
+            Operation::InsertQuote(quote)
+            | Operation::UpdateQuote(quote)
+            | Operation::UpsertQuote(quote)
+            | Operation::DeleteQuote(quote) => self.exists_with(
+                "SELECT 1 FROM quotes WHERE ticker = ?1 AND time = ?2 AND seq = ?3",
+                params![quote.ticker, time_to_sql(&quote.time), quote.id.unwrap_or(0)],
+                data_type,
+                &key,
+            ),
+            Operation::InsertTransaction { sort_prefix, transaction }
+            | Operation::UpdateTransaction { sort_prefix, transaction }
+            | Operation::UpsertTransaction { sort_prefix, transaction }
+            | Operation::DeleteTransaction { sort_prefix, transaction } => self.exists_with(
+                "SELECT 1 FROM transactions WHERE sort_prefix = ?1 AND id = ?2",
+                params![sort_prefix, id_to_sql(transaction.id)],
+                data_type,
+                &key,
+            ),
+        }
+    }
+
+    fn max_quote_seq(&mut self, ticker: &str, time: &DateTime<Utc>) -> Result<Option<i64>, DataError> {
+        self.conn
+            .query_row(
+                "SELECT MAX(seq) FROM quotes WHERE ticker = ?1 AND time = ?2",
+                params![ticker, time_to_sql(time)],
+                |row| row.get(0),
+            )
+            .map_err(|e| read_error(e, DataType::Ticker, ticker))
+    }
+
+    fn apply(&mut self, operation: &Operation) -> Result<(), DataError> {
+        match operation {
+            Operation::InsertAsset(asset) | Operation::UpdateAsset(asset) | Operation::UpsertAsset(asset) =>
+                self.put_asset(asset),
+            Operation::DeleteAsset(asset) => self.remove_asset(&asset.name),
+            Operation::InsertTicker(ticker) | Operation::UpdateTicker(ticker) | Operation::UpsertTicker(ticker) =>
+                self.put_ticker(ticker),
+            Operation::DeleteTicker(ticker) => self.remove_ticker(&ticker.name),
+            Operation::InsertQuote(quote) | Operation::UpdateQuote(quote) | Operation::UpsertQuote(quote) =>
+                self.put_quote(quote),
+            Operation::DeleteQuote(quote) => self.remove_quote(quote),
+            Operation::InsertTransaction { sort_prefix, transaction }
+            | Operation::UpdateTransaction { sort_prefix, transaction }
+            | Operation::UpsertTransaction { sort_prefix, transaction } =>
+                self.put_transaction(sort_prefix, transaction),
+            Operation::DeleteTransaction { sort_prefix, transaction } =>
+                self.remove_transaction(sort_prefix, transaction),
+        }
+    }
+
+    fn delete_ticker_quotes(&mut self, ticker: &str) -> Result<(), DataError> {
+        self.conn
+            .execute("DELETE FROM quotes WHERE ticker = ?1", params![ticker])
+            .map_err(|e| write_error(e, DataType::Ticker, ticker))?;
+        self.remove_ticker(ticker)
+    }
+}
+
+impl SQLiteDB {
+    /// Run the writes in a transaction, which is committed if they succeed and rolled
+    /// back otherwise
+    fn in_transaction<T, F>(&mut self, writes: F) -> Result<T, DataError>
+    where
+        F: FnOnce(&mut SQLiteDB) -> Result<T, DataError>,
+    {
+        // takes the write lock right away, such that no other connection writes between
+        // checking and writing records
+        self.conn
+            .execute_batch("BEGIN IMMEDIATE")
+            .map_err(|e| DataError::storage("begin transaction", e))?;
+
+        let result = writes(self).and_then(|value| {
+            self.conn
+                .execute_batch("COMMIT")
+                .map(|_| value)
+                .map_err(|e| DataError::storage("commit transaction", e))
+        });
+
+        if result.is_err() {
+            // the transaction is still open if any statement or the commit failed
+            let _ = self.conn.execute_batch("ROLLBACK");
+        }
+
+        result
+    }
+
+    /// Write the batch and return the sequence ids of the quotes it inserts
+    pub(super) fn write(&mut self, batch: &Batch) -> Result<Vec<i64>, DataError> {
+        // references are checked within the transaction, which sees its own writes
+        let policy = self.integrity_policy;
+        let mut changes = self.feed.collector();
+        let quote_ids = self.in_transaction(|db| apply_batch(db, batch, policy, changes.as_mut()))?;
+
+        self.feed.publish(changes.unwrap_or_default());
+        Ok(quote_ids)
+    }
+
+    /// Write the quotes in a single transaction
+    pub(super) fn write_quotes(&mut self, quotes: &[Quote], on_duplicate: OnDuplicate) -> Result<BulkReport, DataError> {
+        let mut changes = self.feed.collector();
+        let report = self.in_transaction(|db| apply_quotes(db, quotes, on_duplicate, changes.as_mut()))?;
+
+        self.feed.publish(changes.unwrap_or_default());
+        Ok(report)
+    }
+}
+
+impl BatchHandler for SQLiteDB {
+    fn write_batch(&mut self, batch: &Batch) -> Result<(), DataError> {
+        self.write(batch).map(|_| ())
+    }
+}
+
+/// Changes are published once the transaction has been committed. Writes through other
+/// connections, including `conn` used directly, are not observed.
+impl SubscriptionHandler for SQLiteDB {
+    fn subscribe(&mut self, filter: ChangeFilter) -> Receiver<Change> {
+        self.feed.subscribe(filter)
+    }
+}
@@ -0,0 +1,69 @@
+//! Implementation of sqlite3 integrity handler
+use super::transaction_handler::sql_to_id;
+use super::SQLiteDB;
+
+use crate::data_handler::integrity::{OrphanedQuotes, OrphanedTicker, OrphanedTransaction};
+use crate::data_handler::{DataError, IntegrityHandler, IntegrityPolicy, IntegrityReport};
+
+use rusqlite::{Row, NO_PARAMS};
+
+
+impl SQLiteDB {
+    /// Rows of a query joining records with those they refer to
+    fn orphans<T, F>(&self, sql: &str, from_row: F) -> Result<Vec<T>, DataError>
+    where
+        F: FnMut(&Row) -> rusqlite::Result<T>,
+    {
+        let mut stmt = self
+            .conn
+            .prepare(sql)
+            .map_err(|e| DataError::storage("check integrity", e))?;
+
+        let orphans = stmt
+            .query_map(NO_PARAMS, from_row)
+            .and_then(|rows| rows.collect())
+            .map_err(|e| DataError::storage("check integrity", e));
+        orphans
+    }
+}
+
+impl IntegrityHandler for SQLiteDB {
+    fn integrity_policy(&self) -> IntegrityPolicy {
+        self.integrity_policy
+    }
+
+    fn set_integrity_policy(&mut self, policy: IntegrityPolicy) {
+        self.integrity_policy = policy;
+    }
+
+    fn check_integrity(&mut self) -> Result<IntegrityReport, DataError> {
+        let tickers = self.orphans(
+            "SELECT t.name, t.asset FROM tickers t LEFT JOIN assets a ON a.name = t.asset \
+             WHERE a.name IS NULL ORDER BY t.name",
+            |row| Ok(OrphanedTicker { ticker: row.get(0)?, asset: row.get(1)? }),
+        )?;
+        let quotes = self.orphans(
+            "SELECT q.ticker, COUNT(*) FROM quotes q LEFT JOIN tickers t ON t.name = q.ticker \
+             WHERE t.name IS NULL GROUP BY q.ticker ORDER BY q.ticker",
+            |row| {
+                let count: i64 = row.get(1)?;
+                Ok(OrphanedQuotes { ticker: row.get(0)?, count: count as usize })
+            },
+        )?;
+        let transactions = self.orphans(
+            "SELECT x.sort_prefix, x.id, x.asset_name FROM transactions x \
+             LEFT JOIN assets a ON a.name = x.asset_name \
+             WHERE x.asset_name IS NOT NULL AND a.name IS NULL ORDER BY x.sort_prefix, x.id",
+            |row| {
+                let id: String = row.get(1)?;
+                Ok(OrphanedTransaction {
+                    sort_prefix: row.get(0)?,
+                    id: sql_to_id(&id, 1)?,
+                    asset: row.get(2)?,
+                })
+            },
+        )?;
+
+        Ok(IntegrityReport { tickers, quotes, transactions })
+    }
+}
@@ -0,0 +1,470 @@
+//! Implementation of sqlite3 data handler
+use std::collections::VecDeque;
+use std::path::Path;
+
+use chrono::{DateTime, Utc};
+use rusqlite::types::Type;
+use rusqlite::{Connection, NO_PARAMS};
+
+use crate::data_handler::changes::ChangeFeed;
+use crate::data_handler::{DataError, DataType, IntegrityPolicy};
+
+mod asset_handler;
+mod batch_handler;
+mod integrity;
+mod quote_handler;
+mod snapshot;
+mod transaction_handler;
+
+pub use snapshot::SQLiteSnapshot;
+
+/// Number of rows fetched at once by cursors
+const PAGE_SIZE: i64 = 1000;
+
+/// Schema version written by this version, stored as `user_version` of the database
+pub const SCHEMA_VERSION: u32 = 3;
+
+/// Relational schema of version 1
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS assets (
+    name TEXT NOT NULL PRIMARY KEY,
+    wkn TEXT,
+    isin TEXT,
+    note TEXT
+);
+
+CREATE TABLE IF NOT EXISTS tickers (
+    name TEXT NOT NULL PRIMARY KEY,
+    asset TEXT NOT NULL,
+    currency TEXT NOT NULL,
+    priority INTEGER NOT NULL,
+    factor REAL NOT NULL
+);
+CREATE INDEX IF NOT EXISTS tickers_asset ON tickers (asset);
+
+CREATE TABLE IF NOT EXISTS quotes (
+    ticker TEXT NOT NULL,
+    time TEXT NOT NULL,
+    seq INTEGER NOT NULL,
+    id INTEGER,
+    price REAL NOT NULL,
+    volume REAL,
+    PRIMARY KEY (ticker, time, seq)
+);
+CREATE INDEX IF NOT EXISTS quotes_time ON quotes (time);
+
+CREATE TABLE IF NOT EXISTS transactions (
+    sort_prefix TEXT NOT NULL,
+    id TEXT NOT NULL,
+    trans_type TEXT NOT NULL,
+    asset_name TEXT,
+    position REAL,
+    transaction_ref TEXT,
+    amount REAL NOT NULL,
+    currency TEXT NOT NULL,
+    date TEXT NOT NULL,
+    note TEXT,
+    PRIMARY KEY (sort_prefix, id)
+);
+CREATE INDEX IF NOT EXISTS transactions_asset ON transactions (asset_name);
+";
+
+/// Unique indexes of version 2 to look up assets by ISIN and WKN, both allow any number
+/// of assets without ISIN or WKN
+const ASSET_INDEXES: &str = "
+CREATE UNIQUE INDEX IF NOT EXISTS assets_isin ON assets (isin);
+CREATE UNIQUE INDEX IF NOT EXISTS assets_wkn ON assets (wkn);
+";
+
+/// Index of version 3 to read transactions in the order of their booking dates, which
+/// are stored as `%Y-%m-%d` strings
+const TRANSACTION_DATE_INDEX: &str = "
+CREATE INDEX IF NOT EXISTS transactions_date ON transactions (sort_prefix, date, id);
+";
+
+/// Statements upgrading the schema, those at index `i` upgrade version `i` to `i + 1`
+///
+/// Version 0 is an empty database or one written before schema versioning, which
+/// already has the tables of version 1.
+const MIGRATIONS: [&str; SCHEMA_VERSION as usize] = [SCHEMA, ASSET_INDEXES, TRANSACTION_DATE_INDEX];
+
+/// Struct to handle connections to sqlite3 databases
+pub struct SQLiteDB {
+    /// conn is made public to allow extending this struct outside of the library
+    pub conn: Connection,
+    integrity_policy: IntegrityPolicy,
+    feed: ChangeFeed,
+}
+
+impl SQLiteDB {
+    /// Open database at the given path and create or upgrade the schema, if required
+    pub fn new<P: AsRef<Path>>(path: P) -> Result<SQLiteDB, DataError> {
+        let path = path.as_ref();
+        let conn = Connection::open(path)
+            .map_err(|e| DataError::storage(format!("open database at '{}'", path.display()), e))?;
+        SQLiteDB::init(conn)
+    }
+
+    /// Create a database that lives in memory only
+    pub fn in_memory() -> Result<SQLiteDB, DataError> {
+        let conn = Connection::open_in_memory()
+            .map_err(|e| DataError::storage("open in-memory database", e))?;
+        SQLiteDB::init(conn)
+    }
+
+    fn init(conn: Connection) -> Result<SQLiteDB, DataError> {
+        let db = SQLiteDB {
+            conn,
+            integrity_policy: IntegrityPolicy::default(),
+            feed: ChangeFeed::default(),
+        };
+        db.migrate()?;
+
+        Ok(db)
+    }
+
+    /// Schema version of the database, 0 for databases written before schema versioning
+    pub fn schema_version(&self) -> Result<u32, DataError> {
+        self.conn
+            .query_row("PRAGMA user_version", NO_PARAMS, |row| row.get(0))
+            .map_err(|e| DataError::storage("read schema version", e))
+    }
+
+    /// Upgrade the database to the current schema version, applying each migration in
+    /// a transaction of its own
+    ///
+    /// Databases of a newer schema version are rejected.
+    pub fn migrate(&self) -> Result<(), DataError> {
+        let version = self.schema_version()?;
+        if version > SCHEMA_VERSION {
+            return Err(DataError::IncompatibleSchema {
+                found: version,
+                supported: SCHEMA_VERSION,
+            });
+        }
+
+        for (index, statements) in MIGRATIONS.iter().enumerate().skip(version as usize) {
+            let sql = format!("BEGIN; {} PRAGMA user_version = {}; COMMIT;", statements, index + 1);
+            if let Err(e) = self.conn.execute_batch(&sql) {
+                let _ = self.conn.execute_batch("ROLLBACK");
+                return Err(DataError::storage(format!("migrate schema to version {}", index + 1), e));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Times are stored as fixed width RFC 3339 strings in UTC, which sort in time order
+/// and are still readable with any SQL tool
+fn time_to_sql(time: &DateTime<Utc>) -> String {
+    time.format("%Y-%m-%dT%H:%M:%S%.9fZ").to_string()
+}
+
+fn sql_to_time(time: &str, column: usize) -> rusqlite::Result<DateTime<Utc>> {
+    DateTime::parse_from_rfc3339(time)
+        .map(|time| time.with_timezone(&Utc))
+        .map_err(|e| rusqlite::Error::FromSqlConversionFailure(column, Type::Text, Box::new(e)))
+}
+
+/// Map an error of reading the record with the given readable key, or the records of
+/// the given ticker or sort prefix
+fn read_error(e: rusqlite::Error, data_type: DataType, key: &str) -> DataError {
+    match e {
+        rusqlite::Error::QueryReturnedNoRows => DataError::not_found(data_type, key),
+        rusqlite::Error::FromSqlConversionFailure(..) | rusqlite::Error::InvalidColumnType(..) =>
+            DataError::serialization(data_type, key, e),
+        _ => DataError::storage(format!("read {} '{}'", data_type, key), e),
+    }
+}
+
+/// Map an error of writing or deleting the record with the given readable key
+fn write_error(e: rusqlite::Error, data_type: DataType, key: &str) -> DataError {
+    DataError::storage(format!("write {} '{}'", data_type, key), e)
+}
+
+/// Fetches the page of rows following the given item, or the first page
+type FetchPage<'a, T> = Box<dyn FnMut(Option<&T>) -> rusqlite::Result<Vec<T>> + 'a>;
+
+/// Iterator fetching rows page by page, each page starting after the last item seen
+///
+/// A page that fails to load is reported as a single error, which ends the iteration.
+struct PagedCursor<'a, T> {
+    data_type: DataType,
+    /// Ticker or sort prefix of the rows, reported in errors
+    key: String,
+    fetch: FetchPage<'a, T>,
+    page: VecDeque<T>,
+    last: Option<T>,
+    done: bool,
+}
+
+impl<'a, T> PagedCursor<'a, T> {
+    fn new<F>(data_type: DataType, key: String, fetch: F) -> PagedCursor<'a, T>
+    where
+        F: FnMut(Option<&T>) -> rusqlite::Result<Vec<T>> + 'a,
+    {
+        PagedCursor {
+            data_type,
+            key,
+            fetch: Box::new(fetch),
+            page: VecDeque::new(),
+            last: None,
+            done: false,
+        }
+    }
+}
+
+impl<'a, T: Clone> Iterator for PagedCursor<'a, T> {
+    type Item = Result<T, DataError>;
+
+    fn next(&mut self) -> Option<Result<T, DataError>> {
+        if self.page.is_empty() && !self.done {
+            match (self.fetch)(self.last.as_ref()) {
+                Ok(rows) => {
+                    self.done = (rows.len() as i64) < PAGE_SIZE;
+                    self.page = rows.into();
+                }
+                Err(e) => {
+                    self.done = true;
+                    return Some(Err(read_error(e, self.data_type, &self.key)));
+                }
+            }
+        }
+
+        let item = self.page.pop_front()?;
+        self.last = Some(item.clone());
+        Some(Ok(item))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asset::Asset;
+    use crate::data_handler::integrity::{OrphanedQuotes, OrphanedTicker, OrphanedTransaction};
+    use crate::data_handler::{
+        conformance, AssetHandler, Batch, BatchHandler, IntegrityHandler, QuoteHandler, SnapshotHandler,
+        TransactionHandler,
+    };
+    use crate::fiat::{CashFlow, Currency};
+    use crate::helpers::make_time;
+    use crate::memory_handler::MemoryDB;
+    use crate::quote::{Quote, Ticker};
+    use crate::transaction::{Transaction, TransactionType};
+    use chrono::NaiveDate;
+
+    #[test]
+    fn test_conformance() {
+        conformance::check_all(|| SQLiteDB::in_memory().unwrap(), MemoryDB::new);
+    }
+
+    #[test]
+    fn test_time_to_sql() {
+        let time = make_time(2020, 9, 1, 12, 30, 0).unwrap();
+        let sql_time = time_to_sql(&time);
+        assert_eq!(sql_to_time(&sql_time, 0).unwrap(), time);
+        assert!(time_to_sql(&(time + chrono::Duration::nanoseconds(1))) > sql_time);
+    }
+
+    #[test]
+    fn test_asset_and_ticker() {
+        let mut db = SQLiteDB::in_memory().unwrap();
+        let asset = Asset::new("Apple", Some("865985".to_string()), None, None);
+        db.insert_asset(&asset).unwrap();
+        assert_eq!(
+            db.get_asset_by_name("Apple").unwrap().wkn,
+            Some("865985".to_string())
+        );
+
+        let ticker = Ticker {
+            name: "AAPL".to_string(),
+            asset: "Apple".to_string(),
+            currency: Currency::USD,
+            priority: 1,
+            factor: 1.0,
+        };
+        db.insert_ticker(&ticker).unwrap();
+        assert_eq!(db.get_ticker_by_name("AAPL").unwrap().currency, Currency::USD);
+
+        assert!(matches!(db.delete_asset(&asset), Err(DataError::Validation { .. })));
+        db.delete_ticker(&ticker).unwrap();
+        db.delete_asset(&asset).unwrap();
+        assert!(db.get_asset_by_name("Apple").is_err());
+    }
+
+    #[test]
+    fn test_quotes() {
+        let mut db = SQLiteDB::in_memory().unwrap();
+        let ticker = Ticker {
+            name: "AAPL".to_string(),
+            asset: "Apple".to_string(),
+            currency: Currency::USD,
+            priority: 1,
+            factor: 1.0,
+        };
+        db.insert_asset(&Asset::new("Apple", None, None, None)).unwrap();
+        db.insert_ticker(&ticker).unwrap();
+        for hour in 10..15 {
+            db.insert_quote(&Quote {
+                id: None,
+                ticker: "AAPL".to_string(),
+                price: hour as f64,
+                time: make_time(2020, 9, 1, hour, 0, 0).unwrap(),
+                volume: None,
+            })
+            .unwrap();
+        }
+
+        assert_eq!(db.get_latest_quote("AAPL").unwrap().unwrap().price, 14.0);
+        assert_eq!(db.get_oldest_quote("AAPL").unwrap().unwrap().price, 10.0);
+
+        let time = make_time(2020, 9, 1, 12, 0, 0).unwrap();
+        let forward: Vec<f64> = db
+            .quote_cursor_forward(&ticker, time)
+            .map(|q| q.unwrap().price)
+            .collect();
+        assert_eq!(forward, vec![12.0, 13.0, 14.0]);
+        let reverse: Vec<f64> = db
+            .quote_cursor_reverse(&ticker, time)
+            .map(|q| q.unwrap().price)
+            .collect();
+        assert_eq!(reverse, vec![12.0, 11.0, 10.0]);
+
+        let to = make_time(2020, 9, 1, 14, 0, 0).unwrap();
+        let range: Vec<f64> = db
+            .quote_range_forward(&ticker, time, to)
+            .map(|q| q.unwrap().price)
+            .collect();
+        assert_eq!(range, vec![12.0, 13.0]);
+        let range: Vec<f64> = db
+            .quote_range_reverse(&ticker, time, to)
+            .map(|q| q.unwrap().price)
+            .collect();
+        assert_eq!(range, vec![13.0, 12.0]);
+    }
+
+    #[test]
+    fn test_transactions() {
+        let mut db = SQLiteDB::in_memory().unwrap();
+        let mut transaction = Transaction::new(
+            TransactionType::Asset {
+                asset_name: "Apple".to_string(),
+                position: 10.0,
+            },
+            CashFlow::new(-1000.0, Currency::EUR, NaiveDate::from_ymd(2020, 9, 1)),
+            Some("buy".to_string()),
+        );
+        transaction.id = 1;
+        let mut fee = Transaction::new(
+            TransactionType::Fee {
+                transaction_ref: Some(1),
+            },
+            CashFlow::new(-5.0, Currency::EUR, NaiveDate::from_ymd(2020, 9, 1)),
+            None,
+        );
+        fee.id = 2;
+        db.insert_asset(&Asset::new("Apple", None, None, None)).unwrap();
+        db.insert_transaction("book", &transaction).unwrap();
+        db.insert_transaction("book", &fee).unwrap();
+
+        let stored = db.get_transaction_by_id("book", 1).unwrap();
+        match stored.transaction_type {
+            TransactionType::Asset { asset_name, position } => {
+                assert_eq!(asset_name, "Apple");
+                assert_eq!(position, 10.0);
+            }
+            _ => panic!("wrong transaction type"),
+        }
+        assert_eq!(stored.note, Some("buy".to_string()));
+        assert_eq!(db.get_latest_transaction("book").unwrap().unwrap().id, 2);
+        assert_eq!(db.get_oldest_transaction("book").unwrap().unwrap().id, 1);
+    }
+
+    #[test]
+    fn test_batch_rollback() {
+        let mut db = SQLiteDB::in_memory().unwrap();
+        db.conn.execute_batch("DROP TABLE quotes").unwrap();
+
+        let mut batch = Batch::new();
+        batch
+            .insert_asset(&Asset::new("Apple", None, None, None))
+            .insert_quote(&Quote {
+                id: None,
+                ticker: "AAPL".to_string(),
+                price: 100.0,
+                time: make_time(2020, 9, 1, 12, 0, 0).unwrap(),
+                volume: None,
+            });
+        assert!(db.write_batch(&batch).is_err());
+        assert!(db.get_asset_by_name("Apple").is_err());
+
+        // the connection is usable again after the rollback
+        db.insert_asset(&Asset::new("Apple", None, None, None)).unwrap();
+        assert!(db.get_asset_by_name("Apple").is_ok());
+    }
+
+    #[test]
+    fn test_schema_version() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("ticky.db");
+
+        // database written before schema versioning
+        Connection::open(&path).unwrap().execute_batch(SCHEMA).unwrap();
+        let db = SQLiteDB::new(&path).unwrap();
+        assert_eq!(db.schema_version().unwrap(), SCHEMA_VERSION);
+
+        db.conn
+            .execute_batch(&format!("PRAGMA user_version = {}", SCHEMA_VERSION + 1))
+            .unwrap();
+        assert!(matches!(
+            SQLiteDB::new(&path),
+            Err(DataError::IncompatibleSchema { supported: SCHEMA_VERSION, .. })
+        ));
+    }
+
+    #[test]
+    fn test_snapshot_ignores_later_writes() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("ticky.db");
+        let mut reader = SQLiteDB::new(&path).unwrap();
+        reader.conn.query_row("PRAGMA journal_mode = WAL", NO_PARAMS, |_| Ok(())).unwrap();
+        let mut writer = SQLiteDB::new(&path).unwrap();
+        writer.insert_asset(&Asset::new("Apple", None, None, None)).unwrap();
+
+        {
+            let snapshot = reader.snapshot().unwrap();
+            writer.insert_asset(&Asset::new("Microsoft", None, None, None)).unwrap();
+            assert!(snapshot.get_asset_by_name("Apple").is_ok());
+            assert!(snapshot.get_asset_by_name("Microsoft").is_err());
+        }
+        assert!(reader.get_asset_by_name("Microsoft").is_ok());
+    }
+
+    #[test]
+    fn test_integrity_report() {
+        // e.g. written by an earlier version, which did not check references
+        let mut db = SQLiteDB::in_memory().unwrap();
+        db.conn
+            .execute_batch(
+                "INSERT INTO tickers VALUES ('AAPL', 'Apple', 'USD', 1, 1.0);
+                 INSERT INTO quotes VALUES ('AAPL', '2020-09-01T10:00:00.000000000Z', 0, NULL, 1.0, NULL);
+                 INSERT INTO quotes VALUES ('MSFT', '2020-09-01T10:00:00.000000000Z', 0, NULL, 1.0, NULL);
+                 INSERT INTO quotes VALUES ('MSFT', '2020-09-01T11:00:00.000000000Z', 0, NULL, 2.0, NULL);
+                 INSERT INTO transactions VALUES ('book', '000000000000000000000000000000000000001',
+                     'Dividend', 'Apple', NULL, NULL, 10.0, 'USD', '2020-09-01', NULL);",
+            )
+            .unwrap();
+
+        let report = db.check_integrity().unwrap();
+        assert_eq!(
+            report.tickers,
+            vec![OrphanedTicker { ticker: "AAPL".to_string(), asset: "Apple".to_string() }]
+        );
+        assert_eq!(report.quotes, vec![OrphanedQuotes { ticker: "MSFT".to_string(), count: 2 }]);
+        assert_eq!(
+            report.transactions,
+            vec![OrphanedTransaction { sort_prefix: "book".to_string(), id: 1, asset: "Apple".to_string() }]
+        );
+    }
+}
@@ -0,0 +1,308 @@
+//! Implementation for quote handler with Sqlite3 database as backend
+use super::{read_error, sql_to_time, time_to_sql, write_error, PagedCursor, SQLiteDB, PAGE_SIZE};
+
+use crate::data_handler::{
+    quote_key_string, BatchHandler, BulkReport, Change, Cursor, DataError, DataType, OnDuplicate, Operation, Page,
+    QuoteHandler, TickerFilter,
+};
+use crate::fiat::Currency;
+
+use crate::quote::{Quote, Ticker};
+use chrono::{DateTime, Utc};
+
+use rusqlite::types::Type;
+use rusqlite::{params, Connection, OptionalExtension, Row};
+use std::str::FromStr;
+
+
+const QUOTE_COLUMNS: &str = "id, ticker, price, time, volume";
+
+fn quote_from_row(row: &Row) -> rusqlite::Result<Quote> {
+    let time: String = row.get(3)?;
+
+    Ok(Quote {
+        id: row.get(0)?,
+        ticker: row.get(1)?,
+        price: row.get(2)?,
+        time: sql_to_time(&time, 3)?,
+        volume: row.get(4)?,
+    })
+}
+
+pub(super) fn ticker_from_row(row: &Row) -> rusqlite::Result<Ticker> {
+    let currency: String = row.get(2)?;
+
+    Ok(Ticker {
+        name: row.get(0)?,
+        asset: row.get(1)?,
+        currency: Currency::from_str(&currency).map_err(|e|
+            rusqlite::Error::FromSqlConversionFailure(2, Type::Text, Box::new(e))
+        )?,
+        priority: row.get(3)?,
+        factor: row.get(4)?,
+    })
+}
+
+/// Fetch next page of quotes of a ticker, starting at the given `(time, seq)` bound and
+/// stopping at the optional time limit
+fn quote_page(
+    conn: &Connection,
+    ticker: &str,
+    start: (&str, String, i64),
+    stop: (&str, &Option<String>),
+    order: &str,
+) -> rusqlite::Result<Vec<Quote>> {
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {} FROM quotes WHERE ticker = ?1 AND (time, seq) {} (?2, ?3) \
+         AND (?5 IS NULL OR time {} ?5) ORDER BY time {}, seq {} LIMIT ?4",
+        QUOTE_COLUMNS, start.0, stop.0, order, order,
+    ))?;
+
+    let quotes = stmt
+        .query_map(params![ticker, start.1, start.2, PAGE_SIZE, stop.1], quote_from_row)?
+        .collect();
+    quotes
+}
+
+impl SQLiteDB {
+    /// Page through the quotes of a ticker from the start bound until the stop limit,
+    /// in ascending order if `ascending` is set and descending order otherwise
+    fn paged_quotes(
+        &self,
+        ticker: &Ticker,
+        start: (&'static str, DateTime<Utc>, i64),
+        stop: (&'static str, Option<DateTime<Utc>>),
+        ascending: bool,
+    ) -> Cursor<'_, Quote> {
+        let conn = &self.conn;
+        let ticker = ticker.name.clone();
+        let (op, order) = if ascending { (">", "ASC") } else { ("<", "DESC") };
+        let start = (start.0, time_to_sql(&start.1), start.2);
+        let stop = (stop.0, stop.1.as_ref().map(time_to_sql));
+
+        Box::new(
+            PagedCursor::new(DataType::Quote, ticker.clone(), move |last: Option<&Quote>|
+                match last {
+                    None => quote_page(conn, &ticker, start.clone(), (stop.0, &stop.1), order),
+                    Some(quote) => quote_page(
+                        conn,
+                        &ticker,
+                        (op, time_to_sql(&quote.time), quote.id.unwrap_or(0)),
+                        (stop.0, &stop.1),
+                        order,
+                    ),
+                }
+            )
+        )
+    }
+
+    pub(super) fn put_ticker(&self, ticker: &Ticker) -> Result<(), DataError> {
+        self.conn
+            .execute(
+                "INSERT OR REPLACE INTO tickers (name, asset, currency, priority, factor) \
+                 VALUES (?1, ?2, ?3, ?4, ?5)",
+                params![
+                    ticker.name,
+                    ticker.asset,
+                    format!("{:?}", ticker.currency),
+                    ticker.priority,
+                    ticker.factor,
+                ],
+            )
+            .map(|_| ())
+            .map_err(|e| write_error(e, DataType::Ticker, &ticker.name))
+    }
+
+    pub(super) fn remove_ticker(&self, name: &str) -> Result<(), DataError> {
+        self.conn
+            .execute(
+                "DELETE FROM tickers WHERE name = ?1",
+                params![name],
+            )
+            .map(|_| ())
+            .map_err(|e| write_error(e, DataType::Ticker, name))
+    }
+
+    pub(super) fn put_quote(&self, quote: &Quote) -> Result<(), DataError> {
+        // cached, since bulk inserts store many quotes in a row
+        self.conn
+            .prepare_cached(
+                "INSERT OR REPLACE INTO quotes (ticker, time, seq, id, price, volume) \
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            )
+            .and_then(|mut stmt| stmt.execute(params![
+                quote.ticker,
+                time_to_sql(&quote.time),
+                quote.id.unwrap_or(0),
+                quote.id,
+                quote.price,
+                quote.volume,
+            ]))
+            .map(|_| ())
+            .map_err(|e| {
+                let key = quote_key_string(&quote.ticker, &quote.time, quote.id.unwrap_or(0));
+                write_error(e, DataType::Quote, &key)
+            })
+    }
+
+    pub(super) fn remove_quote(&self, quote: &Quote) -> Result<(), DataError> {
+        self.conn
+            .execute(
+                "DELETE FROM quotes WHERE ticker = ?1 AND time = ?2 AND seq = ?3",
+                params![
+                    quote.ticker,
+                    time_to_sql(&quote.time),
+                    quote.id.unwrap_or(0),
+                ],
+            )
+            .map(|_| ())
+            .map_err(|e| {
+                let key = quote_key_string(&quote.ticker, &quote.time, quote.id.unwrap_or(0));
+                write_error(e, DataType::Quote, &key)
+            })
+    }
+}
+
+/// Sqlite implementation of quote handler
+impl QuoteHandler for SQLiteDB {
+    fn get_ticker_by_name(&self, name: &str) -> Result<Ticker, DataError> {
+        self.conn
+            .query_row(
+                "SELECT name, asset, currency, priority, factor FROM tickers WHERE name = ?1",
+                params![name],
+                ticker_from_row,
+            )
+            .map_err(|e| read_error(e, DataType::Ticker, name))
+    }
+
+    fn list_tickers(&self, filter: &TickerFilter, page: &Page) -> Result<Vec<Ticker>, DataError> {
+        let mut stmt = self
+            .conn
+            .prepare(
+                "SELECT name, asset, currency, priority, factor FROM tickers \
+                 WHERE (?1 IS NULL OR name > ?1) AND (?2 IS NULL OR asset = ?2) \
+                 AND (?3 IS NULL OR currency = ?3) ORDER BY name LIMIT ?4",
+            )
+            .map_err(|e| DataError::storage("list tickers", e))?;
+
+        let currency = filter.currency.map(|currency| format!("{:?}", currency));
+        let tickers = stmt
+            .query_map(params![page.after, filter.asset, currency, page.limit as i64], ticker_from_row)
+            .and_then(|rows| rows.collect())
+            .map_err(|e| DataError::storage("list tickers", e));
+        tickers
+    }
+
+    fn get_quote_by_id(&self, ticker_name: &str, time: DateTime<Utc>, id: i64) -> Result<Quote, DataError> {
+        self.conn
+            .query_row(
+                &format!(
+                    "SELECT {} FROM quotes WHERE ticker = ?1 AND time = ?2 AND seq = ?3",
+                    QUOTE_COLUMNS,
+                ),
+                params![ticker_name, time_to_sql(&time), id],
+                quote_from_row,
+            )
+            .map_err(|e| read_error(e, DataType::Quote, &quote_key_string(ticker_name, &time, id)))
+    }
+
+    fn get_latest_quote(&self, ticker_name: &str) -> Result<Option<Quote>, DataError> {
+        self.conn
+            .query_row(
+                &format!(
+                    "SELECT {} FROM quotes WHERE ticker = ?1 ORDER BY time DESC, seq DESC LIMIT 1",
+                    QUOTE_COLUMNS,
+                ),
+                params![ticker_name],
+                quote_from_row,
+            )
+            .optional()
+            .map_err(|e| read_error(e, DataType::Quote, ticker_name))
+    }
+
+    fn get_oldest_quote(&self, ticker_name: &str) -> Result<Option<Quote>, DataError> {
+        self.conn
+            .query_row(
+                &format!(
+                    "SELECT {} FROM quotes WHERE ticker = ?1 ORDER BY time ASC, seq ASC LIMIT 1",
+                    QUOTE_COLUMNS,
+                ),
+                params![ticker_name],
+                quote_from_row,
+            )
+            .optional()
+            .map_err(|e| read_error(e, DataType::Quote, ticker_name))
+    }
+
+    fn insert_ticker(&mut self, ticker: &Ticker) -> Result<(), DataError> {
+        self.write_batch(&Operation::InsertTicker(ticker.clone()).into())
+    }
+
+    fn update_ticker(&mut self, ticker: &Ticker) -> Result<(), DataError> {
+        self.write_batch(&Operation::UpdateTicker(ticker.clone()).into())
+    }
+
+    fn upsert_ticker(&mut self, ticker: &Ticker) -> Result<(), DataError> {
+        self.write_batch(&Operation::UpsertTicker(ticker.clone()).into())
+    }
+
+    fn delete_ticker(&mut self, ticker: &Ticker) -> Result<(), DataError> {
+        self.write_batch(&Operation::DeleteTicker(ticker.clone()).into())
+    }
+
+    fn insert_quote(&mut self, quote: &Quote) -> Result<i64, DataError> {
+        // a batch of a single insert returns a single id
+        self.write(&Operation::InsertQuote(quote.clone()).into())
+            .map(|quote_ids| quote_ids[0])
+    }
+
+    fn update_quote(&mut self, quote: &Quote) -> Result<(), DataError> {
+        self.write_batch(&Operation::UpdateQuote(quote.clone()).into())
+    }
+
+    fn upsert_quote(&mut self, quote: &Quote) -> Result<(), DataError> {
+        self.write_batch(&Operation::UpsertQuote(quote.clone()).into())
+    }
+
+    fn delete_quote(&mut self, quote: &Quote) -> Result<(), DataError> {
+        self.write_batch(&Operation::DeleteQuote(quote.clone()).into())
+    }
+
+    fn delete_quotes(&mut self, ticker_name: &str, from: DateTime<Utc>, to: DateTime<Utc>) -> Result<(), DataError> {
+        self.conn
+            .execute(
+                "DELETE FROM quotes WHERE ticker = ?1 AND time >= ?2 AND time < ?3",
+                params![ticker_name, time_to_sql(&from), time_to_sql(&to)],
+            )
+            .map_err(|e| write_error(e, DataType::Ticker, ticker_name))?;
+
+        if from < to {
+            self.feed.publish(vec![Change::QuotesDeleted {
+                ticker: ticker_name.to_string(),
+                from: Some(from),
+                to: Some(to),
+            }]);
+        }
+        Ok(())
+    }
+
+    fn insert_quotes(&mut self, quotes: &[Quote], on_duplicate: OnDuplicate) -> Result<BulkReport, DataError> {
+        self.write_quotes(quotes, on_duplicate)
+    }
+
+    fn quote_cursor_forward(&self, ticker: &Ticker, time: DateTime<Utc>) -> Cursor<'_, Quote> {
+        self.paged_quotes(ticker, (">=", time, i64::MIN), ("<", None), true)
+    }
+
+    fn quote_cursor_reverse(&self, ticker: &Ticker, time: DateTime<Utc>) -> Cursor<'_, Quote> {
+        self.paged_quotes(ticker, ("<=", time, i64::MAX), (">=", None), false)
+    }
+
+    fn quote_range_forward(&self, ticker: &Ticker, from: DateTime<Utc>, to: DateTime<Utc>) -> Cursor<'_, Quote> {
+        self.paged_quotes(ticker, (">=", from, i64::MIN), ("<", Some(to)), true)
+    }
+
+    fn quote_range_reverse(&self, ticker: &Ticker, from: DateTime<Utc>, to: DateTime<Utc>) -> Cursor<'_, Quote> {
+        self.paged_quotes(ticker, ("<", to, i64::MIN), (">=", Some(from)), false)
+    }
+}
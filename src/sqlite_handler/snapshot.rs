@@ -0,0 +1,45 @@
+//! Implementation of sqlite3 snapshots
+use rusqlite::NO_PARAMS;
+
+use super::SQLiteDB;
+
+use crate::data_handler::snapshot::delegate_snapshot;
+use crate::data_handler::{AssetHandler, DataError, QuoteHandler, Snapshot, SnapshotHandler, TransactionHandler};
+
+/// Read transaction on the connection of a `SQLiteDB`, which ends when the snapshot is
+/// dropped
+///
+/// Other connections writing to a database in the default rollback journal mode wait
+/// for the snapshot to be dropped, or fail once their busy timeout expires. In WAL mode,
+/// they write right away and the snapshot keeps reading the records as of its start.
+pub struct SQLiteSnapshot<'a> {
+    db: &'a mut SQLiteDB,
+}
+
+delegate_snapshot!(SQLiteSnapshot<'_>, db);
+
+impl Drop for SQLiteSnapshot<'_> {
+    fn drop(&mut self) {
+        // nothing has been written, hence ending the transaction can't lose any data
+        let _ = self.db.conn.execute_batch("COMMIT");
+    }
+}
+
+impl SnapshotHandler for SQLiteDB {
+    fn snapshot(&mut self) -> Result<Box<dyn Snapshot + '_>, DataError> {
+        self.conn
+            .execute_batch("BEGIN")
+            .map_err(|e| DataError::storage("begin snapshot", e))?;
+        let snapshot = SQLiteSnapshot { db: self };
+
+        // deferred transactions only start reading at their first statement, which
+        // fixes the state seen by all later statements
+        snapshot
+            .db
+            .conn
+            .query_row("SELECT COUNT(*) FROM sqlite_master", NO_PARAMS, |row| row.get::<_, i64>(0))
+            .map_err(|e| DataError::storage("begin snapshot", e))?;
+
+        Ok(Box::new(snapshot))
+    }
+}
@@ -0,0 +1,308 @@
+//! Implementation of sqlite3 transaction handler
+use crate::data_handler::transaction_handler::booking_date_bound;
+use crate::data_handler::{
+    transaction_key_string, BatchHandler, Cursor, DataError, DataType, Operation, Page, TransactionHandler,
+};
+use crate::fiat::{CashFlow, Currency};
+use crate::transaction::{Transaction, TransactionType};
+
+use super::{read_error, write_error, PagedCursor, SQLiteDB, PAGE_SIZE};
+use chrono::naive::MIN_DATE;
+use chrono::{Utc, DateTime, NaiveDate};
+use rusqlite::types::Type;
+use rusqlite::{params, Connection, OptionalExtension, Row};
+use std::iter;
+use std::str::FromStr;
+
+const TRANSACTION_COLUMNS: &str =
+    "id, trans_type, asset_name, position, transaction_ref, amount, currency, date, note";
+
+/// Ids are stored as zero padded strings, since sqlite has no 128 bit integers
+pub(super) fn id_to_sql(id: u128) -> String {
+    format!("{:039}", id)
+}
+
+pub(super) fn sql_to_id(id: &str, column: usize) -> rusqlite::Result<u128> {
+    u128::from_str(id)
+        .map_err(|e| rusqlite::Error::FromSqlConversionFailure(column, Type::Text, Box::new(e)))
+}
+
+/// Booking dates are stored as `%Y-%m-%d` strings, which sort in date order
+fn date_to_sql(date: NaiveDate) -> String {
+    date.format("%Y-%m-%d").to_string()
+}
+
+/// Split transaction type into its type name, asset name, position and transaction reference
+fn type_to_columns(
+    transaction_type: &TransactionType,
+) -> (&'static str, Option<&str>, Option<f64>, Option<String>) {
+    match transaction_type {
+        TransactionType::Cash => ("Cash", None, None, None),
+        TransactionType::Asset { asset_name, position } =>
+            ("Asset", Some(asset_name), Some(*position), None),
+        TransactionType::Dividend { asset_name } => ("Dividend", Some(asset_name), None, None),
+        TransactionType::Interest { asset_name } => ("Interest", Some(asset_name), None, None),
+        TransactionType::Tax { transaction_ref } =>
+            ("Tax", None, None, transaction_ref.map(id_to_sql)),
+        TransactionType::Fee { transaction_ref } =>
+            ("Fee", None, None, transaction_ref.map(id_to_sql)),
+    }
+}
+
+fn required<T>(value: Option<T>, column: usize) -> rusqlite::Result<T> {
+    value.ok_or_else(|| rusqlite::Error::FromSqlConversionFailure(
+        column,
+        Type::Null,
+        "missing value for transaction type".into(),
+    ))
+}
+
+fn transaction_from_row(row: &Row) -> rusqlite::Result<Transaction> {
+    let id: String = row.get(0)?;
+    let trans_type: String = row.get(1)?;
+    let asset_name: Option<String> = row.get(2)?;
+    let position: Option<f64> = row.get(3)?;
+    let transaction_ref = match row.get::<_, Option<String>>(4)? {
+        Some(trans_ref) => Some(sql_to_id(&trans_ref, 4)?),
+        None => None,
+    };
+
+    let transaction_type = match trans_type.as_str() {
+        "Cash" => TransactionType::Cash,
+        "Asset" => TransactionType::Asset {
+            asset_name: required(asset_name, 2)?,
+            position: required(position, 3)?,
+        },
+        "Dividend" => TransactionType::Dividend { asset_name: required(asset_name, 2)? },
+        "Interest" => TransactionType::Interest { asset_name: required(asset_name, 2)? },
+        "Tax" => TransactionType::Tax { transaction_ref },
+        "Fee" => TransactionType::Fee { transaction_ref },
+        _ => return Err(rusqlite::Error::FromSqlConversionFailure(
+            1,
+            Type::Text,
+            format!("unknown transaction type '{}'", trans_type).into(),
+        )),
+    };
+
+    let currency: String = row.get(6)?;
+    let currency = Currency::from_str(&currency)
+        .map_err(|e| rusqlite::Error::FromSqlConversionFailure(6, Type::Text, Box::new(e)))?;
+    let date: String = row.get(7)?;
+    let date = NaiveDate::parse_from_str(&date, "%Y-%m-%d")
+        .map_err(|e| rusqlite::Error::FromSqlConversionFailure(7, Type::Text, Box::new(e)))?;
+
+    Ok(Transaction {
+        id: sql_to_id(&id, 0)?,
+        transaction_type,
+        cash_flow: CashFlow::new(row.get(5)?, currency, date),
+        note: row.get(8)?,
+    })
+}
+
+/// Fetch next page of transactions with the given sort prefix, starting after the
+/// booking date and id of the last transaction, if any, and stopping at the booking
+/// date limit, if any
+fn transaction_page(
+    conn: &Connection,
+    sort_prefix: &str,
+    start: (&str, Option<(NaiveDate, u128)>),
+    stop: (&str, Option<NaiveDate>),
+    order: &str,
+) -> rusqlite::Result<Vec<Transaction>> {
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {} FROM transactions WHERE sort_prefix = ?1 AND (?2 IS NULL OR (date, id) {} (?2, ?3)) \
+         AND (?5 IS NULL OR date {} ?5) ORDER BY date {}, id {} LIMIT ?4",
+        TRANSACTION_COLUMNS, start.0, stop.0, order, order,
+    ))?;
+
+    let (date, id) = match start.1 {
+        Some((date, id)) => (Some(date_to_sql(date)), Some(id_to_sql(id))),
+        None => (None, None),
+    };
+    let transactions = stmt
+        .query_map(
+            params![sort_prefix, date, id, PAGE_SIZE, stop.1.map(date_to_sql)],
+            transaction_from_row,
+        )?
+        .collect();
+    transactions
+}
+
+impl SQLiteDB {
+    fn query_transaction(&self, sql: &str, sort_prefix: &str) -> Result<Option<Transaction>, DataError> {
+        self.conn
+            .query_row(
+                &format!(
+                    "SELECT {} FROM transactions WHERE sort_prefix = ?1 {}",
+                    TRANSACTION_COLUMNS, sql,
+                ),
+                params![sort_prefix],
+                transaction_from_row,
+            )
+            .optional()
+            .map_err(|e| read_error(e, DataType::Transaction, sort_prefix))
+    }
+
+    /// Page through the transactions with the given sort prefix with booking dates in
+    /// `[from, to)`, where `None` is beyond any date, in ascending order if `ascending` is
+    /// set and descending order otherwise
+    fn paged_transactions(
+        &self,
+        sort_prefix: &str,
+        from: Option<NaiveDate>,
+        to: Option<NaiveDate>,
+        ascending: bool,
+    ) -> Cursor<'_, Transaction> {
+        let from = match from {
+            Some(from) => from,
+            None => return Box::new(iter::empty()),
+        };
+        let conn = &self.conn;
+        let sort_prefix = sort_prefix.to_string();
+        // ids are never below 0, hence `(from, 0)` bounds all transactions of the date
+        let (start, stop, op, order) = if ascending {
+            ((">=", Some((from, 0))), ("<", to), ">", "ASC")
+        } else {
+            (("<", to.map(|to| (to, 0))), (">=", Some(from)), "<", "DESC")
+        };
+
+        Box::new(
+            PagedCursor::new(DataType::Transaction, sort_prefix.clone(), move |last: Option<&Transaction>|
+                match last {
+                    None => transaction_page(conn, &sort_prefix, start, stop, order),
+                    Some(transaction) => {
+                        let last = Some((transaction.cash_flow.date, transaction.id));
+                        transaction_page(conn, &sort_prefix, (op, last), stop, order)
+                    }
+                }
+            )
+        )
+    }
+
+    pub(super) fn put_transaction(&self, sort_prefix: &str, transaction: &Transaction) -> Result<(), DataError> {
+        let (trans_type, asset_name, position, transaction_ref) =
+            type_to_columns(&transaction.transaction_type);
+
+        self.conn
+            .execute(
+                "INSERT OR REPLACE INTO transactions (sort_prefix, id, trans_type, asset_name, \
+                 position, transaction_ref, amount, currency, date, note) \
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+                params![
+                    sort_prefix,
+                    id_to_sql(transaction.id),
+                    trans_type,
+                    asset_name,
+                    position,
+                    transaction_ref,
+                    transaction.cash_flow.amount.amount,
+                    format!("{:?}", transaction.cash_flow.amount.currency),
+                    date_to_sql(transaction.cash_flow.date),
+                    transaction.note,
+                ],
+            )
+            .map(|_| ())
+            .map_err(|e| {
+                let key = transaction_key_string(sort_prefix, transaction.id);
+                write_error(e, DataType::Transaction, &key)
+            })
+    }
+
+    pub(super) fn remove_transaction(&self, sort_prefix: &str, transaction: &Transaction) -> Result<(), DataError> {
+        self.conn
+            .execute(
+                "DELETE FROM transactions WHERE sort_prefix = ?1 AND id = ?2",
+                params![sort_prefix, id_to_sql(transaction.id)],
+            )
+            .map(|_| ())
+            .map_err(|e| {
+                let key = transaction_key_string(sort_prefix, transaction.id);
+                write_error(e, DataType::Transaction, &key)
+            })
+    }
+}
+
+impl TransactionHandler for SQLiteDB {
+    fn get_transaction_by_id(&self, sort_prefix: &str, id: u128) -> Result<Transaction, DataError> {
+        self.conn
+            .query_row(
+                &format!(
+                    "SELECT {} FROM transactions WHERE sort_prefix = ?1 AND id = ?2",
+                    TRANSACTION_COLUMNS,
+                ),
+                params![sort_prefix, id_to_sql(id)],
+                transaction_from_row,
+            )
+            .map_err(|e| read_error(e, DataType::Transaction, &transaction_key_string(sort_prefix, id)))
+    }
+
+    fn list_sort_prefixes(&self, page: &Page) -> Result<Vec<String>, DataError> {
+        let mut stmt = self
+            .conn
+            .prepare(
+                "SELECT DISTINCT sort_prefix FROM transactions WHERE (?1 IS NULL OR sort_prefix > ?1) \
+                 ORDER BY sort_prefix LIMIT ?2",
+            )
+            .map_err(|e| DataError::storage("list sort prefixes", e))?;
+
+        let sort_prefixes = stmt
+            .query_map(params![page.after, page.limit as i64], |row| row.get(0))
+            .and_then(|rows| rows.collect())
+            .map_err(|e| DataError::storage("list sort prefixes", e));
+        sort_prefixes
+    }
+
+    fn get_latest_transaction(&self, sort_prefix: &str) -> Result<Option<Transaction>, DataError> {
+        self.query_transaction("ORDER BY date DESC, id DESC LIMIT 1", sort_prefix)
+    }
+
+    fn get_oldest_transaction(&self, sort_prefix: &str) -> Result<Option<Transaction>, DataError> {
+        self.query_transaction("ORDER BY date ASC, id ASC LIMIT 1", sort_prefix)
+    }
+
+    fn insert_transaction(&mut self, sort_prefix: &str, transaction: &Transaction) -> Result<(), DataError> {
+        self.write_batch(&Operation::InsertTransaction {
+            sort_prefix: sort_prefix.to_string(),
+            transaction: transaction.clone(),
+        }.into())
+    }
+
+    fn update_transaction(&mut self, sort_prefix: &str, transaction: &Transaction) -> Result<(), DataError> {
+        self.write_batch(&Operation::UpdateTransaction {
+            sort_prefix: sort_prefix.to_string(),
+            transaction: transaction.clone(),
+        }.into())
+    }
+
+    fn upsert_transaction(&mut self, sort_prefix: &str, transaction: &Transaction) -> Result<(), DataError> {
+        self.write_batch(&Operation::UpsertTransaction {
+            sort_prefix: sort_prefix.to_string(),
+            transaction: transaction.clone(),
+        }.into())
+    }
+
+    fn delete_transaction(&mut self, sort_prefix: &str, transaction: &Transaction) -> Result<(), DataError> {
+        self.write_batch(&Operation::DeleteTransaction {
+            sort_prefix: sort_prefix.to_string(),
+            transaction: transaction.clone(),
+        }.into())
+    }
+
+    fn transaction_cursor_forward(&self, sort_prefix: &str, time: DateTime<Utc>) -> Cursor<'_, Transaction> {
+        self.paged_transactions(sort_prefix, booking_date_bound(time, true), None, true)
+    }
+
+    fn transaction_cursor_reverse(&self, sort_prefix: &str, time: DateTime<Utc>) -> Cursor<'_, Transaction> {
+        self.paged_transactions(sort_prefix, Some(MIN_DATE), booking_date_bound(time, false), false)
+    }
+
+    fn transaction_range_forward(&self, sort_prefix: &str, from: DateTime<Utc>, to: DateTime<Utc>) -> Cursor<'_, Transaction> {
+        let (from, to) = (booking_date_bound(from, true), booking_date_bound(to, true));
+        self.paged_transactions(sort_prefix, from, to, true)
+    }
+
+    fn transaction_range_reverse(&self, sort_prefix: &str, from: DateTime<Utc>, to: DateTime<Utc>) -> Cursor<'_, Transaction> {
+        let (from, to) = (booking_date_bound(from, true), booking_date_bound(to, true));
+        self.paged_transactions(sort_prefix, from, to, false)
+    }
+}
@@ -1,8 +1,83 @@
 
 use serde::{Deserialize, Serialize};
 use crate::fiat::CashFlow;
+use chrono::{DateTime, Utc};
+use std::sync::Mutex;
 use std::time::{SystemTime, UNIX_EPOCH};
 
+/// Source of transaction ids
+///
+/// Ids hold the creation time in nanoseconds since the Unix epoch in their upper 64 bits,
+/// such that transactions sort by creation time and handlers can seek them by time, see
+/// `time_id`. Each generator must return strictly increasing ids.
+///
+/// Ids written before this layout are plain nanoseconds, hence they are still unique
+/// and sort before all ids of this layout.
+pub trait IdGenerator {
+    fn next_id(&mut self) -> u128;
+}
+
+/// Smallest id of transactions created at the given time
+pub fn time_id(time: DateTime<Utc>) -> u128 {
+    (time.timestamp_nanos().max(0) as u128) << 64
+}
+
+/// Last id issued by any `ClockIdGenerator` of the process
+static LAST_CLOCK_ID: Mutex<u128> = Mutex::new(0);
+
+/// Ids read from the system clock, similar to ULIDs
+///
+/// The lower 64 bits of an id are drawn from the thread-local random generator of
+/// `rand`, which is seeded by the operating system. Two ids created by different
+/// processes in the same nanosecond collide with a probability of 2^-64, in which case
+/// inserting the second transaction fails with `DataError::Conflict` instead of
+/// overwriting the first. Within the process ids are strictly increasing: an id not above
+/// the last one issued is replaced by the last id plus one.
+#[derive(Debug, Default, Copy, Clone)]
+pub struct ClockIdGenerator;
+
+impl IdGenerator for ClockIdGenerator {
+    fn next_id(&mut self) -> u128 {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("Time went backwards, cannot safely create transaction id.")
+            .as_nanos() as u64;
+        let id = (now as u128) << 64 | rand::random::<u64>() as u128;
+
+        let mut last = LAST_CLOCK_ID.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        *last = id.max(*last + 1);
+        *last
+    }
+}
+
+/// Ids counting up from a given id in fixed steps, e.g. for tests or to replay a
+/// recorded sequence of transactions
+#[derive(Debug, Clone)]
+pub struct SequentialIdGenerator {
+    next: u128,
+    step: u128,
+}
+
+impl SequentialIdGenerator {
+    pub fn new(start: u128, step: u128) -> SequentialIdGenerator {
+        assert!(step > 0, "ids must be strictly increasing");
+        SequentialIdGenerator { next: start, step }
+    }
+
+    /// Ids of transactions created at the given time, counting up by one
+    pub fn starting_at(time: DateTime<Utc>) -> SequentialIdGenerator {
+        SequentialIdGenerator::new(time_id(time), 1)
+    }
+}
+
+impl IdGenerator for SequentialIdGenerator {
+    fn next_id(&mut self) -> u128 {
+        let id = self.next;
+        self.next += self.step;
+        id
+    }
+}
+
 /// Type of transaction
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum TransactionType {
@@ -24,24 +99,40 @@ pub struct Transaction {
 }
 
 impl Transaction {
+    /// Create transaction with an id read from the system clock
     pub fn new(
         transaction_type: TransactionType,
         cash_flow: CashFlow,
         note: Option<String>,
     ) -> Transaction {
-        let start = SystemTime::now();
-        let time_since_epoch = start
-            .duration_since(UNIX_EPOCH)
-            .expect("Time went backwards, cannot safely create transaction id.");
+        Transaction::with_id_generator(&mut ClockIdGenerator, transaction_type, cash_flow, note)
+    }
 
+    /// Create transaction with the next id of the given generator
+    pub fn with_id_generator<G: IdGenerator + ?Sized>(
+        generator: &mut G,
+        transaction_type: TransactionType,
+        cash_flow: CashFlow,
+        note: Option<String>,
+    ) -> Transaction {
         Transaction {
-            id: time_since_epoch.as_nanos(),
+            id: generator.next_id(),
             transaction_type,
             cash_flow,
             note,
         }
     }
 
+    /// Name of the asset the transaction refers to, if any
+    pub fn asset_name(&self) -> Option<&str> {
+        match &self.transaction_type {
+            TransactionType::Asset { asset_name, .. }
+            | TransactionType::Dividend { asset_name }
+            | TransactionType::Interest { asset_name } => Some(asset_name),
+            _ => None,
+        }
+    }
+
     /// Assign or change transaction's asset_id, if possible
     /// This is often required for transactions on new assets
     pub fn set_asset_name(&mut self, asset_name: String) {
@@ -73,3 +164,37 @@ impl Transaction {
         self.transaction_type = new_type;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fiat::Currency;
+    use chrono::{NaiveDate, TimeZone};
+
+    fn cash() -> Transaction {
+        let cash_flow = CashFlow::new(10.0, Currency::EUR, NaiveDate::from_ymd(2020, 9, 1));
+        Transaction::new(TransactionType::Cash, cash_flow, None)
+    }
+
+    #[test]
+    fn test_clock_ids_are_unique() {
+        let start = time_id(Utc::now());
+        let ids: Vec<u128> = (0..1000).map(|_| cash().id).collect();
+        assert!(ids.windows(2).all(|pair| pair[0] < pair[1]));
+        assert!(ids[0] >= start);
+        assert!(ids[999] < time_id(Utc::now() + chrono::Duration::seconds(1)));
+    }
+
+    #[test]
+    fn test_sequential_ids() {
+        let time = Utc.ymd(2020, 9, 1).and_hms(10, 0, 0);
+        let mut generator = SequentialIdGenerator::starting_at(time);
+        let first = Transaction::with_id_generator(&mut generator, TransactionType::Cash, cash().cash_flow, None);
+        assert_eq!(first.id, time_id(time));
+        assert_eq!(generator.next_id(), first.id + 1);
+
+        let mut generator = SequentialIdGenerator::new(10, 5);
+        let ids: Vec<u128> = (0..3).map(|_| generator.next_id()).collect();
+        assert_eq!(ids, vec![10, 15, 20]);
+    }
+}